@@ -0,0 +1,22 @@
+//! Feeds arbitrary bytes to the client events parsing of the VM.
+//!
+//! Run with `cargo +nightly fuzz run client_events`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vm::{commands_reader::CommandsReader, module::ClientInfo, state, wire};
+use vm_buffers::{ByteOrder, BytesReader};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    let allocator = common::allocator_from_bytes(data);
+    let byte_order = wire::byte_order(data).unwrap_or(ByteOrder::LittleEndian);
+    let mut bytes_reader = BytesReader::new(byte_order, &allocator);
+    let mut client_info = ClientInfo::new();
+
+    if let Ok(mut commands_reader) = CommandsReader::new(&mut bytes_reader, data.len() as u64) {
+        let _ = state::read_client_events(&mut commands_reader, &mut client_info);
+    }
+});
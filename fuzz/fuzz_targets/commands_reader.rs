@@ -0,0 +1,29 @@
+//! Feeds arbitrary bytes to the `CommandsReader`.
+//!
+//! Run with `cargo +nightly fuzz run commands_reader`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vm::{commands_reader::CommandsReader, wire};
+use vm_buffers::{ByteOrder, BytesReader};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    let allocator = common::allocator_from_bytes(data);
+    let byte_order = wire::byte_order(data).unwrap_or(ByteOrder::LittleEndian);
+    let mut bytes_reader = BytesReader::new(byte_order, &allocator);
+
+    let mut commands_reader = match CommandsReader::new(&mut bytes_reader, data.len() as u64) {
+        Ok(commands_reader) => commands_reader,
+        Err(_) => return,
+    };
+
+    while let Ok(Some(command)) = commands_reader.next() {
+        // Read the payload partially to exercise skipping.
+        for _ in 0..command.len / 2 {
+            command.bytes_reader.read_byte();
+        }
+    }
+});
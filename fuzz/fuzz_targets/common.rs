@@ -0,0 +1,16 @@
+//! Helpers shared by the fuzz targets.
+
+use vm_buffers::{ByteOrder, BytesWriter};
+use vm_memory::RegionAllocator;
+
+/// Create allocator that contains a copy of the `data`.
+pub fn allocator_from_bytes(data: &[u8]) -> RegionAllocator {
+    let allocator = RegionAllocator::new(data.len().max(1));
+    let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, &allocator);
+
+    for byte in data.iter() {
+        bytes_writer.write_byte(*byte);
+    }
+
+    allocator
+}
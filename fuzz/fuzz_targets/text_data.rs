@@ -0,0 +1,55 @@
+//! Feeds arbitrary bytes to the `TextData` decoding.
+//!
+//! Run with `cargo +nightly fuzz run text_data`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use vm::gapi::TextData;
+use vm_buffers::{ByteOrder, BytesReader, BytesWriter, IntoVMBuffers};
+use vm_memory::RegionAllocator;
+
+mod common;
+
+/// Compare the text data bitwise, the matrix can contain NaNs.
+fn same_text_data(a: &TextData, b: &TextData) -> bool {
+    let bits = |text_data: &TextData| {
+        text_data
+            .mvp_matrix
+            .data
+            .iter()
+            .flatten()
+            .map(|value| value.to_bits())
+            .collect::<Vec<u32>>()
+    };
+
+    a.font_id == b.font_id && a.font_size == b.font_size && a.text == b.text && bits(a) == bits(b)
+}
+
+fuzz_target!(|data: &[u8]| {
+    let allocator = common::allocator_from_bytes(data);
+
+    for byte_order in &[ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+        let mut bytes_reader = BytesReader::new(*byte_order, &allocator);
+
+        let text_data = match TextData::read(&mut bytes_reader, data.len() as u64) {
+            Ok(text_data) => text_data,
+            Err(_) => continue,
+        };
+
+        // `read_from_buffers` doesn't check bounds, it gets the data `read` has validated.
+        bytes_reader.reset();
+        let unchecked = TextData::read_from_buffers(&mut bytes_reader);
+        assert!(same_text_data(&unchecked, &text_data));
+
+        let size = text_data.encoded_size();
+        let encoded = RegionAllocator::new(size.max(1) as usize);
+        let mut bytes_writer = BytesWriter::new(*byte_order, &encoded);
+        text_data.write_to_buffers(&mut bytes_writer);
+        assert_eq!(bytes_writer.current_offset(), size);
+
+        let mut bytes_reader = BytesReader::new(*byte_order, &encoded);
+        let decoded = TextData::read(&mut bytes_reader, size).unwrap();
+        assert!(same_text_data(&decoded, &text_data));
+    }
+});
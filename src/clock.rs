@@ -0,0 +1,302 @@
+//! Frame clock.
+//!
+//! The VM reads the [`Clock`] once per rendered frame, the modules delta time
+//! is the difference between the current and the previous frame times,
+//! the first frame has zero delta time.
+//!
+//! - [`Clock::Real`] - the real time, the default;
+//! - [`Clock::Fixed`] - every frame advances the time by the same step,
+//!   e.g. for deterministic animation tests;
+//! - [`Clock::Manual`] - the time is supplied by the host, e.g. vsync timestamps,
+//!   see [`crate::tech_paws_vm_set_frame_time`].
+//!
+//! With [`FixedTimestep`] the VM accumulates the frame time and calls
+//! [`crate::module::Module::step`] zero or more times per frame at the fixed rate,
+//! [`crate::module::ModuleState::alpha`] is the interpolation factor
+//! between the last two steps for [`crate::module::Module::render`],
+//! so modules behave the same at any frame rate.
+
+use std::time::{Duration, Instant};
+
+/// Source of the frame time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Clock {
+    /// Real time since the `start`.
+    Real {
+        /// Start of the clock.
+        start: Instant,
+    },
+    /// Every frame advances the time by the `step`.
+    Fixed {
+        /// Frame duration.
+        step: Duration,
+        /// Time of the last frame, `None` before the first frame.
+        time: Option<Duration>,
+    },
+    /// Time is set manually.
+    Manual {
+        /// Time of the current frame.
+        time: Duration,
+    },
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::real()
+    }
+}
+
+impl Clock {
+    /// Real time clock that starts now.
+    pub fn real() -> Self {
+        Clock::Real {
+            start: Instant::now(),
+        }
+    }
+
+    /// Fixed-step clock, the first frame time is zero.
+    pub fn fixed(step: Duration) -> Self {
+        Clock::Fixed { step, time: None }
+    }
+
+    /// Manual clock that starts at zero.
+    pub fn manual() -> Self {
+        Clock::Manual {
+            time: Duration::from_secs(0),
+        }
+    }
+
+    /// Time of the next frame, advances the fixed-step clock.
+    pub fn frame_time(&mut self) -> Duration {
+        match self {
+            Clock::Real { start } => start.elapsed(),
+            Clock::Fixed { step, time } => {
+                let next = time.map_or(Duration::from_secs(0), |time| time + *step);
+                *time = Some(next);
+                next
+            }
+            Clock::Manual { time } => *time,
+        }
+    }
+
+    /// Set time of the manual clock, other clocks are replaced with the manual clock.
+    pub fn set_time(&mut self, time: Duration) {
+        *self = Clock::Manual { time };
+    }
+}
+
+/// Fixed-timestep simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedTimestep {
+    /// Duration of the step.
+    pub step: Duration,
+
+    /// Max number of steps per frame, the rest of the frame time is dropped,
+    /// so a long frame doesn't cause even longer frames.
+    pub max_steps: u32,
+
+    accumulator: Duration,
+    last_time: Option<Duration>,
+}
+
+impl FixedTimestep {
+    /// Create a new fixed-timestep simulation with at most 8 steps per frame.
+    pub fn new(step: Duration) -> Self {
+        assert!(step > Duration::from_secs(0));
+
+        FixedTimestep {
+            step,
+            max_steps: 8,
+            accumulator: Duration::from_secs(0),
+            last_time: None,
+        }
+    }
+
+    /// Accumulate time up to the frame `time`, returns the number of steps to run.
+    pub fn advance(&mut self, time: Duration) -> u32 {
+        let elapsed = self
+            .last_time
+            .map_or(Duration::from_secs(0), |last| time.saturating_sub(last));
+        self.last_time = Some(time);
+        self.accumulator += elapsed;
+
+        let mut steps = 0;
+
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+
+            if steps == self.max_steps {
+                self.accumulator = Duration::from_nanos(
+                    (self.accumulator.as_nanos() % self.step.as_nanos()) as u64,
+                );
+                break;
+            }
+        }
+
+        steps
+    }
+
+    /// Accumulated time that hasn't been stepped yet in steps, in `[0, 1)`.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use super::{Clock, FixedTimestep};
+    use crate::{
+        commands::Source,
+        module::{Module, ModuleState, StepState},
+        tech_paws_vm_set_frame_time, Vm,
+    };
+
+    struct RecordModule {
+        delta_times: Arc<Mutex<Vec<f32>>>,
+    }
+
+    impl Module for RecordModule {
+        fn id(&self) -> &'static str {
+            "tech.paws.tests.clock"
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, _: &mut ModuleState) -> StepState {
+            StepState::None
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            self.delta_times.lock().unwrap().push(state.delta_time);
+        }
+    }
+
+    /// Moves by 1 unit per second in steps.
+    struct PhysicsModule {
+        x: Arc<Mutex<f32>>,
+        steps: Arc<Mutex<u32>>,
+    }
+
+    impl Module for PhysicsModule {
+        fn id(&self) -> &'static str {
+            "tech.paws.tests.clock.physics"
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, state: &mut ModuleState) -> StepState {
+            *self.x.lock().unwrap() += state.delta_time;
+            *self.steps.lock().unwrap() += 1;
+            StepState::None
+        }
+
+        fn render(&mut self, _: &mut ModuleState) {}
+    }
+
+    /// Run frames at `fps` for a second, returns position, number of steps and alpha.
+    fn simulate(fps: u64) -> (f32, u32, f32) {
+        let x = Arc::new(Mutex::new(0.0));
+        let steps = Arc::new(Mutex::new(0));
+        let mut vm = Vm::new();
+        vm.set_clock(Clock::fixed(Duration::from_nanos(1_000_000_000 / fps)));
+        vm.set_fixed_timestep(Some(Duration::from_millis(10)));
+        vm.register_module(Box::new(PhysicsModule {
+            x: x.clone(),
+            steps: steps.clone(),
+        }));
+
+        for _ in 0..=fps {
+            vm.state_mut().process_commands(Source::Processor).unwrap();
+            vm.state_mut().process_commands(Source::GAPI).unwrap();
+        }
+
+        let alpha = vm.state().module_states["tech.paws.tests.clock.physics"].alpha;
+        let x = *x.lock().unwrap();
+        let steps = *steps.lock().unwrap();
+        (x, steps, alpha)
+    }
+
+    fn create_vm(clock: Clock) -> (Vm, Arc<Mutex<Vec<f32>>>) {
+        let delta_times = Arc::new(Mutex::new(Vec::new()));
+        let mut vm = Vm::new();
+        vm.set_clock(clock);
+        vm.register_module(Box::new(RecordModule {
+            delta_times: delta_times.clone(),
+        }));
+
+        (vm, delta_times)
+    }
+
+    #[test]
+    fn fixed_clock() {
+        let mut clock = Clock::fixed(Duration::from_millis(10));
+
+        assert_eq!(clock.frame_time(), Duration::from_millis(0));
+        assert_eq!(clock.frame_time(), Duration::from_millis(10));
+        assert_eq!(clock.frame_time(), Duration::from_millis(20));
+
+        clock.set_time(Duration::from_millis(5));
+        assert_eq!(clock.frame_time(), Duration::from_millis(5));
+        assert_eq!(clock.frame_time(), Duration::from_millis(5));
+    }
+
+    #[test]
+    fn deterministic_delta_time() {
+        let (mut vm, delta_times) = create_vm(Clock::fixed(Duration::from_millis(250)));
+
+        for _ in 0..3 {
+            vm.state_mut().process_commands(Source::GAPI).unwrap();
+        }
+
+        assert_eq!(*delta_times.lock().unwrap(), vec![0.0, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn host_frame_time() {
+        let (mut vm, delta_times) = create_vm(Clock::real());
+
+        for time in [1_000_000_000u64, 1_500_000_000, 1_400_000_000].iter() {
+            assert_eq!(unsafe { tech_paws_vm_set_frame_time(&mut vm, *time) }, 0);
+            vm.state_mut().process_commands(Source::GAPI).unwrap();
+        }
+
+        assert_eq!(*delta_times.lock().unwrap(), vec![0.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn fixed_timestep() {
+        let mut fixed_timestep = FixedTimestep::new(Duration::from_millis(10));
+
+        assert_eq!(fixed_timestep.advance(Duration::from_millis(100)), 0);
+        assert_eq!(fixed_timestep.advance(Duration::from_millis(125)), 2);
+        assert_eq!(fixed_timestep.alpha(), 0.5);
+        assert_eq!(fixed_timestep.advance(Duration::from_millis(130)), 1);
+        assert_eq!(fixed_timestep.alpha(), 0.0);
+
+        fixed_timestep.max_steps = 4;
+        assert_eq!(fixed_timestep.advance(Duration::from_millis(1133)), 4);
+        assert!((fixed_timestep.alpha() - 0.3).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frame_rate_independent_steps() {
+        let (x_25, steps_25, alpha_25) = simulate(25);
+        let (x_125, steps_125, alpha_125) = simulate(125);
+
+        assert_eq!(steps_25, 100);
+        assert_eq!(steps_125, 100);
+        assert_eq!(x_25, x_125);
+        assert_eq!(alpha_25, 0.0);
+        assert_eq!(alpha_125, 0.0);
+    }
+}
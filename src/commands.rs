@@ -27,7 +27,8 @@ pub const UPDATE_VIEWPORT: u64 = 0x0001_0004;
 /// Add text boundaries.
 pub const ADD_TEXT_BOUNDARIES: u64 = 0x0001_0005;
 
-/// On touch start event.
+/// On touch start event, payload: button `u8`, x `u32`, y `u32`,
+/// optionally followed by the pointer, see [`POINTER_PAYLOAD_SIZE`].
 pub const COMMAND_TOUCH_START: u64 = 0x0001_0006;
 
 /// On touch start event.
@@ -36,11 +37,57 @@ pub const COMMAND_TOUCH_END: u64 = 0x0001_0007;
 /// On touch start event.
 pub const COMMAND_TOUCH_MOVE: u64 = 0x0001_0008;
 
+/// On scroll event, payload: dx `f32`, dy `f32`, precise `u8`.
+pub const COMMAND_SCROLL: u64 = 0x0001_000E;
+
+/// On pinch (zoom) gesture, payload: scale `f32`, focus x `f32`, focus y `f32`.
+pub const COMMAND_PINCH: u64 = 0x0001_000F;
+
+/// On rotate gesture, payload: angle `f32` in radians, focus x `f32`, focus y `f32`.
+pub const COMMAND_ROTATE: u64 = 0x0001_0010;
+
+/// Bind the next recorded command to the macro argument slot.
+pub const MACRO_ARGUMENT: u64 = 0x0001_0009;
+
+/// On key down event, payload: key code `u32`, modifiers `u8`, repeat `u8`.
+pub const COMMAND_KEY_DOWN: u64 = 0x0001_000A;
+
+/// On key up event, payload: key code `u32`, modifiers `u8`.
+pub const COMMAND_KEY_UP: u64 = 0x0001_000B;
+
+/// On committed text input, payload: length `u64`, UTF-8 bytes.
+pub const COMMAND_TEXT_INPUT: u64 = 0x0001_000C;
+
+/// On IME composition update, payload: cursor start `u32`, cursor end `u32`,
+/// length `u64`, UTF-8 bytes of the composed text.
+pub const COMMAND_IME_COMPOSITION: u64 = 0x0001_000D;
+
 pub const COMMAND_MOUSE_BUTTON_UNKNOWN: u8 = 0;
 pub const COMMAND_MOUSE_BUTTON_LEFT: u8 = 1;
 pub const COMMAND_MOUSE_BUTTON_RIGHT: u8 = 2;
 pub const COMMAND_MOUSE_BUTTON_MIDDLE: u8 = 3;
 
+/// Size of the pointer at the end of the touch commands payload:
+/// pointer id `u64`, kind `u8`, pressure `f32`, tilt x `f32`, tilt y `f32`.
+/// Commands without the pointer come from the mouse with pointer id 0.
+pub const POINTER_PAYLOAD_SIZE: u64 = 21;
+
+/// Mouse pointer kind.
+pub const POINTER_KIND_MOUSE: u8 = 0;
+/// Touch pointer kind.
+pub const POINTER_KIND_TOUCH: u8 = 1;
+/// Pen pointer kind.
+pub const POINTER_KIND_PEN: u8 = 2;
+
+/// Shift key modifier bit.
+pub const KEY_MODIFIER_SHIFT: u8 = 1;
+/// Control key modifier bit.
+pub const KEY_MODIFIER_CONTROL: u8 = 1 << 1;
+/// Alt (Option) key modifier bit.
+pub const KEY_MODIFIER_ALT: u8 = 1 << 2;
+/// Super (Command, Windows) key modifier bit.
+pub const KEY_MODIFIER_SUPER: u8 = 1 << 3;
+
 /// Graphical API commands
 pub mod gapi {
     /// Command id to draw lines.
@@ -78,6 +125,12 @@ pub mod transforms {
 
     /// Scale.
     pub const SCALE: u64 = 0x0003_0003;
+
+    /// Save the current transform on the transform stack.
+    pub const PUSH_TRANSFORM: u64 = 0x0003_0004;
+
+    /// Restore the transform saved by `PUSH_TRANSFORM`.
+    pub const POP_TRANSFORM: u64 = 0x0003_0005;
 }
 
 /// Commands to manage assets.
@@ -85,7 +138,8 @@ pub mod assets {
     /// Load texture and save in memory.
     pub const LOAD_TEXTURE: u64 = 0x0004_0001;
 
-    /// Load macro and save in memory.
+    /// Load macro and save in memory, the file is read synchronously
+    /// when the frame is rendered.
     pub const LOAD_MACRO: u64 = 0x0004_0002;
 
     /// Remove texture from memory.
@@ -2,29 +2,97 @@
 //!
 //! Module implements abstraction for sending commands to a different modules.
 
-use parking_lot::lock_api::RawMutex;
+use std::{collections::HashMap, sync::Arc};
+
+use parking_lot::{lock_api::RawMutex, RwLock};
 use vm_buffers::BytesWriter;
 
-use crate::{commands::Source, STATE};
+use crate::{
+    commands::Source,
+    error::{Result, VmError},
+    module::ModuleCommands,
+    wire,
+};
+
+/// Size of the command header - id and payload size.
+pub(crate) const COMMAND_HEADER_SIZE: u64 = 16;
+
+/// How many bytes are reserved for the command payload if the payload size is unknown,
+/// unless the commands buffer budget is smaller.
+pub const DEFAULT_PAYLOAD_RESERVE: u64 = 1024;
+
+/// Commands buffers of the module that can receive commands.
+#[derive(Clone)]
+struct Route {
+    gapi_commands: Arc<ModuleCommands>,
+    processor_commands: Arc<ModuleCommands>,
+}
+
+/// Routing table of the VM - maps module addresses to their commands buffers.
+///
+/// Every VM owns its own router and every [`CommandsBus`] created by the VM
+/// shares it, so commands never leak from one VM to another.
+#[derive(Clone, Default)]
+pub struct CommandsRouter {
+    routes: Arc<RwLock<HashMap<&'static str, Route>>>,
+}
+
+impl CommandsRouter {
+    /// Create a new empty `CommandsRouter`.
+    pub fn new() -> Self {
+        CommandsRouter::default()
+    }
+
+    /// Make the module commands buffers reachable by the `address`.
+    pub fn register(
+        &self,
+        address: &'static str,
+        gapi_commands: Arc<ModuleCommands>,
+        processor_commands: Arc<ModuleCommands>,
+    ) {
+        self.routes.write().insert(
+            address,
+            Route {
+                gapi_commands,
+                processor_commands,
+            },
+        );
+    }
+
+    /// Get commands buffer of the module at the `address` for the `source`.
+    pub fn commands(&self, address: &str, source: Source) -> Result<Arc<ModuleCommands>> {
+        let routes = self.routes.read();
+        let route = routes
+            .get(address)
+            .ok_or_else(|| VmError::UnknownAddress(address.to_string()))?;
+
+        match source {
+            Source::GAPI => Ok(route.gapi_commands.clone()),
+            Source::Processor => Ok(route.processor_commands.clone()),
+        }
+    }
+}
 
 /// Commands bus. Used to communicate between modules.
 pub struct CommandsBus {
+    router: CommandsRouter,
+    pending_commands: Option<Arc<ModuleCommands>>,
+    command_offset: u64,
     start_offset: u64,
     payload_size_offset: u64,
-}
-
-impl Default for CommandsBus {
-    fn default() -> Self {
-        Self::new()
-    }
+    payload_size: u64,
 }
 
 impl CommandsBus {
-    /// Create a new `CommandsBus`.
-    pub fn new() -> Self {
+    /// Create a new `CommandsBus` that delivers commands through the `router`.
+    pub fn new(router: CommandsRouter) -> Self {
         CommandsBus {
+            router,
+            pending_commands: None,
+            command_offset: 0,
             start_offset: 0,
             payload_size_offset: 0,
+            payload_size: 0,
         }
     }
 
@@ -34,13 +102,20 @@ impl CommandsBus {
     /// of the module at the `address`;
     /// `commands_writer` is used to write the command payload.
     ///
+    /// The payload should not exceed [`DEFAULT_PAYLOAD_RESERVE`] bytes,
+    /// use [`CommandsBus::push_sized_command`] to send bigger payloads.
+    ///
+    /// Returns [`VmError::UnknownAddress`] if there is no module at the `address`
+    /// and [`VmError::BufferOverflow`] if the payload exceeds its reserve,
+    /// the command is dropped in this case.
+    ///
     /// # Examples
     ///
     /// ```rust
-    /// use vm::{commands, commands_bus::CommandsBus, module};
+    /// use vm::{commands, module, Vm};
     ///
-    /// unsafe { vm::init() };
-    /// let commands_bus = CommandsBus::new();
+    /// let vm = Vm::new();
+    /// let commands_bus = vm.commands_bus();
     /// commands_bus.push_command(
     ///     module::CLIENT_ID,
     ///     commands::gapi::SET_COLOR_PIPELINE,
@@ -51,34 +126,59 @@ impl CommandsBus {
     ///         bytes_writer.write_f32(1.0); // b
     ///         bytes_writer.write_f32(0.5); // a
     ///     },
-    /// );
+    /// )?;
+    /// # Ok::<(), vm::error::VmError>(())
     /// ```
-    pub fn push_command<F>(&self, address: &str, id: u64, source: Source, command_writer: F)
+    pub fn push_command<F>(
+        &self,
+        address: &str,
+        id: u64,
+        source: Source,
+        command_writer: F,
+    ) -> Result<()>
     where
         F: FnOnce(&mut BytesWriter),
     {
-        let state = unsafe { STATE.as_ref() }.unwrap();
-        let module_state = state.module_states.get(&address).unwrap();
-
-        let (mut bytes_writer, mut bytes_reader) = match source {
-            Source::GAPI => {
-                (
-                    module_state.gapi_commands.bytes_writer.lock(),
-                    module_state.gapi_commands.bytes_reader.lock(),
-                )
-            }
-            Source::Processor => {
-                (
-                    module_state.processor_commands.bytes_writer.lock(),
-                    module_state.processor_commands.bytes_reader.lock(),
-                )
-            }
-        };
+        self.write_command(address, id, source, None, command_writer)
+    }
 
-        // Update commands count
-        let commands_count = bytes_reader.read_u64_at(0);
+    /// Send command to the module.
+    ///
+    /// The same as [`CommandsBus::push_command`], but the commands buffer
+    /// grows to fit `payload_size` bytes of the payload, and the payload
+    /// should not exceed `payload_size` bytes.
+    pub fn push_sized_command<F>(
+        &self,
+        address: &str,
+        id: u64,
+        source: Source,
+        payload_size: u64,
+        command_writer: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut BytesWriter),
+    {
+        self.write_command(address, id, source, Some(payload_size), command_writer)
+    }
 
-        bytes_writer.write_u64_at(0, commands_count + 1);
+    fn write_command<F>(
+        &self,
+        address: &str,
+        id: u64,
+        source: Source,
+        payload_size: Option<u64>,
+        command_writer: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(&mut BytesWriter),
+    {
+        let commands = self.router.commands(address, source)?;
+        let mut bytes_writer = commands.bytes_writer.lock();
+        let payload_size =
+            payload_size.unwrap_or_else(|| default_payload_reserve(&commands, &bytes_writer));
+        commands.reserve(&mut bytes_writer, COMMAND_HEADER_SIZE + payload_size)?;
+
+        let command_offset = bytes_writer.current_offset();
         bytes_writer.write_u64(id);
 
         // Write size of payload in bytes
@@ -90,15 +190,30 @@ impl CommandsBus {
         command_writer(&mut bytes_writer);
         let end_offset = bytes_writer.current_offset();
 
+        if end_offset - start_offset > payload_size {
+            commands.truncate(&mut bytes_writer, command_offset);
+            return Err(VmError::BufferOverflow);
+        }
+
         // Write size of payload at size_offset
         bytes_writer.write_u64_at(payload_size_offset, end_offset - start_offset);
+
+        // Update commands count
+        let commands_count = commands
+            .bytes_reader
+            .lock()
+            .read_u64_at(wire::COMMANDS_COUNT_OFFSET);
+        bytes_writer.write_u64_at(wire::COMMANDS_COUNT_OFFSET, commands_count + 1);
+
+        Ok(())
     }
 
     /// Start writing command.
     ///
     /// [`CommandsBus::begin_command`] and [`CommandsBus::end_command`] is an unsafe
     /// alternative of [`CommandsBus::push_command`]. Can be useful for FFI.
-    /// Returns `bytes_writer` that used to write the command payload.
+    /// Returns `bytes_writer` that used to write the command payload,
+    /// the payload should not exceed `payload_size` bytes.
     ///
     /// # Safety
     ///
@@ -107,55 +222,51 @@ impl CommandsBus {
     /// # Examples
     ///
     /// ```rust
-    /// use vm::{commands, commands_bus::CommandsBus, module};
+    /// use vm::{commands, module, Vm};
     /// use vm_buffers::BytesWriter;
     ///
-    /// unsafe { vm::init() };
-    /// let mut commands_bus = CommandsBus::new();
+    /// let vm = Vm::new();
+    /// let mut commands_bus = vm.commands_bus();
     /// let bytes_writer_raw = unsafe {
     ///     commands_bus.begin_command(
     ///         module::CLIENT_ID,
     ///         commands::Source::GAPI,
     ///         commands::gapi::SET_COLOR_PIPELINE,
+    ///         16,
     ///     )
-    /// };
+    /// }?;
     /// let mut bytes_writer = unsafe { BytesWriter::from_raw(*bytes_writer_raw) };
     /// bytes_writer.write_f32(0.0); // r
     /// bytes_writer.write_f32(1.0); // g
     /// bytes_writer.write_f32(1.0); // b
     /// bytes_writer.write_f32(0.5); // a
-    /// unsafe { commands_bus.end_command(module::CLIENT_ID, commands::Source::GAPI) };
+    /// unsafe { commands_bus.end_command(module::CLIENT_ID, commands::Source::GAPI) }?;
+    /// # Ok::<(), vm::error::VmError>(())
     /// ```
     pub unsafe fn begin_command(
         &mut self,
         address: &str,
         source: Source,
         id: u64,
-    ) -> *mut vm_buffers::c_api::BytesWriter {
-        let state = STATE.as_ref().unwrap();
-        let module_state = &mut state.module_states.get(address).unwrap();
-
-        let bytes_writer = match source {
-            Source::GAPI => {
-                module_state.gapi_commands.bytes_writer.raw().lock();
-                module_state
-                    .gapi_commands
-                    .bytes_writer
-                    .data_ptr()
-                    .as_mut()
-                    .unwrap()
-            }
-            Source::Processor => {
-                module_state.processor_commands.bytes_writer.raw().lock();
-                module_state
-                    .processor_commands
-                    .bytes_writer
-                    .data_ptr()
-                    .as_mut()
-                    .unwrap()
-            }
-        };
+        payload_size: u64,
+    ) -> Result<*mut vm_buffers::c_api::BytesWriter> {
+        if self.pending_commands.is_some() {
+            return Err(VmError::MalformedCommand(
+                "previous command hasn't been ended",
+            ));
+        }
+
+        let commands = self.router.commands(address, source)?;
+        commands.bytes_writer.raw().lock();
+        let bytes_writer = commands.bytes_writer.data_ptr().as_mut().unwrap();
 
+        if let Err(err) = commands.reserve(bytes_writer, COMMAND_HEADER_SIZE + payload_size) {
+            commands.bytes_writer.raw().unlock();
+            return Err(err);
+        }
+
+        self.command_offset = bytes_writer.current_offset();
+        self.payload_size = payload_size;
         bytes_writer.write_u64(id);
 
         // Write size of payload in bytes
@@ -164,8 +275,9 @@ impl CommandsBus {
         bytes_writer.write_u64(0);
 
         self.start_offset = bytes_writer.current_offset();
+        self.pending_commands = Some(commands);
 
-        bytes_writer.raw()
+        Ok(bytes_writer.raw())
     }
 
     /// Finish writing command and send it to the module at the `address`.
@@ -173,56 +285,131 @@ impl CommandsBus {
     /// [`CommandsBus::begin_command`] and [`CommandsBus::end_command`] is an unsafe
     /// alternative of [`CommandsBus::push_command`]. Can be useful for FFI.
     ///
+    /// Returns [`VmError::BufferOverflow`] if the payload exceeds the size passed
+    /// to [`CommandsBus::begin_command`], the command is dropped in this case.
+    ///
     /// # Safety
     ///
     /// * Should always be used after [`CommandsBus::begin_command`] has been executed.
     /// * `source` should be the same as it was in [`CommandsBus::begin_command`].
-    pub unsafe fn end_command(&mut self, address: &str, source: Source) {
-        let state = STATE.as_ref().unwrap();
-        let module_state = &mut state.module_states.get(address).unwrap();
+    pub unsafe fn end_command(&mut self, address: &str, source: Source) -> Result<()> {
+        let commands = self.router.commands(address, source)?;
 
-        let mut bytes_reader = match source {
-            Source::GAPI => module_state.gapi_commands.bytes_reader.lock(),
-            Source::Processor => module_state.processor_commands.bytes_reader.lock(),
-        };
+        match &self.pending_commands {
+            Some(pending_commands) if Arc::ptr_eq(pending_commands, &commands) => (),
+            _ => return Err(VmError::MalformedCommand("command hasn't been started")),
+        }
 
-        let bytes_writer = match source {
-            Source::GAPI => {
-                module_state
-                    .gapi_commands
-                    .bytes_writer
-                    .data_ptr()
-                    .as_mut()
-                    .unwrap()
-            }
-            Source::Processor => {
-                module_state
-                    .processor_commands
-                    .bytes_writer
-                    .data_ptr()
-                    .as_mut()
-                    .unwrap()
-            }
+        self.pending_commands = None;
+        let bytes_writer = commands.bytes_writer.data_ptr().as_mut().unwrap();
+        let end_offset = bytes_writer.current_offset();
+
+        let result = if end_offset - self.start_offset > self.payload_size {
+            commands.truncate(bytes_writer, self.command_offset);
+            Err(VmError::BufferOverflow)
+        }
+        else {
+            // Write size of payload at size_offset
+            bytes_writer.write_u64_at(self.payload_size_offset, end_offset - self.start_offset);
+
+            // Update commands count
+            let commands_count = commands
+                .bytes_reader
+                .lock()
+                .read_u64_at(wire::COMMANDS_COUNT_OFFSET);
+            bytes_writer.write_u64_at(wire::COMMANDS_COUNT_OFFSET, commands_count + 1);
+            Ok(())
         };
 
-        // Update commands count
-        let commands_count = bytes_reader.read_u64_at(0);
-        bytes_writer.write_u64_at(0, commands_count + 1);
+        // unlock mutex locked in `begin_command`
+        commands.bytes_writer.raw().unlock();
 
-        let end_offset = bytes_writer.current_offset();
-        // Write size of payload at size_offset
-        bytes_writer.write_u64_at(self.payload_size_offset, end_offset - self.start_offset);
+        result
+    }
+}
 
-        // unlock mutexes
-        match source {
-            Source::GAPI => {
-                module_state.gapi_commands.allocator.raw().unlock();
-                module_state.gapi_commands.bytes_writer.raw().unlock();
-            }
-            Source::Processor => {
-                module_state.processor_commands.allocator.raw().unlock();
-                module_state.processor_commands.bytes_writer.raw().unlock();
+/// How many bytes to reserve for the payload of unknown size -
+/// [`DEFAULT_PAYLOAD_RESERVE`], but no more than the buffer budget allows.
+fn default_payload_reserve(commands: &ModuleCommands, bytes_writer: &BytesWriter) -> u64 {
+    let free_budget = commands
+        .budget()
+        .saturating_sub(bytes_writer.current_offset() + COMMAND_HEADER_SIZE);
+
+    DEFAULT_PAYLOAD_RESERVE.min(free_budget)
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_buffers::BytesWriter;
+
+    use crate::{
+        commands::Source,
+        error::VmError,
+        module::{create_commands_bus, ModuleCommands, ModuleOptions},
+    };
+
+    const ADDRESS: &str = "tech.paws.tests.commands_bus";
+
+    /// Ids and payload sizes of the commands.
+    fn read_commands(commands: &ModuleCommands) -> Vec<(u64, u64)> {
+        commands
+            .read(|commands_reader| {
+                let mut result = Vec::new();
+
+                while let Some(command) = commands_reader.next()? {
+                    result.push((command.id, command.len));
+                }
+
+                Ok(result)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn payload_overrun() {
+        let (commands, commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+
+        commands_bus
+            .push_sized_command(ADDRESS, 1, Source::GAPI, 8, |bytes_writer| {
+                bytes_writer.write_u64(1);
+            })
+            .unwrap();
+        assert_eq!(
+            commands_bus.push_sized_command(ADDRESS, 2, Source::GAPI, 8, |bytes_writer| {
+                bytes_writer.write_u64(1);
+                bytes_writer.write_u64(2);
+            }),
+            Err(VmError::BufferOverflow)
+        );
+        commands_bus
+            .push_command(ADDRESS, 3, Source::GAPI, |bytes_writer| {
+                bytes_writer.write_u32(1);
+            })
+            .unwrap();
+
+        assert_eq!(read_commands(&commands), vec![(1, 8), (3, 4)]);
+    }
+
+    #[test]
+    fn begin_command_overrun() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+
+        for (id, payload_size) in [(1, 8), (2, 4)].iter() {
+            let result = unsafe {
+                let bytes_writer_raw = commands_bus
+                    .begin_command(ADDRESS, Source::GAPI, *id, *payload_size)
+                    .unwrap();
+                let mut bytes_writer = BytesWriter::from_raw(*bytes_writer_raw);
+                bytes_writer.write_u64(1);
+                *bytes_writer_raw = *bytes_writer.raw();
+                commands_bus.end_command(ADDRESS, Source::GAPI)
+            };
+
+            if *id == 2 {
+                assert_eq!(result, Err(VmError::BufferOverflow));
             }
         }
+
+        assert_eq!(read_commands(&commands), vec![(1, 8)]);
     }
 }
@@ -1,66 +1,216 @@
 //! Commands reader.
+//!
+//! Reads commands buffer with layout `header + count + address + [id, len, payload]*`,
+//! see [`crate::wire`] for the header layout.
+//! The reader validates the buffer header and each command header against
+//! the buffer size, so corrupted or truncated buffers result in [`ReadError`].
+
+use std::fmt;
 
 use vm_buffers::BytesReader;
-use vm_buffers::IntoVMBuffers;
 
+use crate::wire;
+
+/// Size of the command header - id and payload size.
+const COMMAND_HEADER_SIZE: u64 = 16;
+
+/// Commands buffer reading error.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ReadError {
+    /// Buffer is too small to contain the header.
+    TruncatedHeader,
+    /// Buffer doesn't start with the magic bytes or the byte order mark is invalid.
+    InvalidMagic,
+    /// Buffer has been written with the protocol version the reader doesn't support.
+    UnsupportedVersion(u32),
+    /// Buffer has been written with a byte order other than the reader's one.
+    ByteOrderMismatch,
+    /// Commands count in the header doesn't fit in the buffer.
+    InvalidCount,
+    /// Address length doesn't fit in the buffer or address isn't a valid UTF-8 string.
+    InvalidAddress,
+    /// Command header or payload doesn't fit in the buffer.
+    TruncatedCommand,
+    /// Previous command payload has been read beyond its length.
+    PayloadOverflow,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::UnsupportedVersion(version) => {
+                write!(
+                    f,
+                    "{} {}, expected {}",
+                    self.reason(),
+                    version,
+                    wire::VERSION
+                )
+            }
+            _ => write!(f, "{}", self.reason()),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl ReadError {
+    /// Short description of the error.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ReadError::TruncatedHeader => "truncated commands buffer header",
+            ReadError::InvalidMagic => "invalid commands buffer magic",
+            ReadError::UnsupportedVersion(_) => "unsupported protocol version",
+            ReadError::ByteOrderMismatch => "commands buffer byte order mismatch",
+            ReadError::InvalidCount => "invalid commands count",
+            ReadError::InvalidAddress => "invalid commands buffer address",
+            ReadError::TruncatedCommand => "truncated command",
+            ReadError::PayloadOverflow => "last command payload overflow",
+        }
+    }
+}
+
+/// Commands buffer reader.
 pub struct CommandsReader<'a> {
     pub bytes_reader: &'a mut BytesReader,
     pub address: String,
     pub count: u64,
+    size: u64,
     command_breakpoint: u64,
     command_len: u64,
     read_commands: u64,
 }
 
+/// Command read from the commands buffer.
 pub struct Command<'a> {
+    /// Command id.
     pub id: u64,
+    /// Size of the command payload in bytes.
+    pub len: u64,
+    /// Reader positioned at the beginning of the command payload.
     pub bytes_reader: &'a mut BytesReader,
 }
 
 impl<'a> CommandsReader<'a> {
-    pub fn new(bytes_reader: &'a mut BytesReader) -> Self {
+    /// Create a new reader, `bytes_reader` should be positioned at the beginning
+    /// of the commands buffer, `size` is the size of the buffer data in bytes.
+    ///
+    /// `bytes_reader` should have the byte order of the buffer,
+    /// use [`wire::byte_order`] to detect it.
+    pub fn new(bytes_reader: &'a mut BytesReader, size: u64) -> Result<Self, ReadError> {
+        let remaining =
+            |bytes_reader: &BytesReader| size.saturating_sub(bytes_reader.current_offset());
+
+        wire::read_header(bytes_reader, size)?;
+
+        if remaining(bytes_reader) < 16 {
+            return Err(ReadError::TruncatedHeader);
+        }
+
         let count = bytes_reader.read_u64();
-        let address = String::read_from_buffers(bytes_reader);
+        let address_len = bytes_reader.read_u64();
+
+        if address_len > remaining(bytes_reader) {
+            return Err(ReadError::InvalidAddress);
+        }
+
+        let address_bytes = (0..address_len)
+            .map(|_| bytes_reader.read_byte())
+            .collect::<Vec<u8>>();
+        let address = String::from_utf8(address_bytes).map_err(|_| ReadError::InvalidAddress)?;
+
+        if count > remaining(bytes_reader) / COMMAND_HEADER_SIZE {
+            return Err(ReadError::InvalidCount);
+        }
+
         let command_breakpoint = bytes_reader.current_offset();
 
-        Self {
+        Ok(Self {
             bytes_reader,
             address,
             count,
+            size,
             command_breakpoint,
             command_len: 0,
             read_commands: 0,
-        }
+        })
     }
 
+    /// Commands count.
     pub fn len(&self) -> usize {
         self.count as usize
     }
 
-    pub fn next(&mut self) -> Option<Command> {
-        assert!(
-            self.bytes_reader.current_offset() <= self.command_breakpoint + self.command_len,
-            "last command payload overflow"
-        );
+    /// Whether the buffer has no commands.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Size of the current command payload in bytes.
+    pub fn payload_len(&self) -> u64 {
+        self.command_len
+    }
+
+    /// How many bytes of the current command payload have been read.
+    pub fn payload_read(&self) -> u64 {
+        self.bytes_reader.current_offset() - self.command_breakpoint
+    }
+
+    /// Move to the end of the current command payload, no matter how much
+    /// of the payload has been read.
+    pub fn skip_payload(&mut self) {
+        let payload_end = self.command_breakpoint + self.command_len;
+        let offset = self.bytes_reader.current_offset();
+
+        if offset <= payload_end {
+            self.bytes_reader.skip(payload_end - offset);
+        }
+        else {
+            self.bytes_reader.reset();
+            self.bytes_reader.skip(payload_end);
+        }
+    }
+
+    /// Read the next command.
+    ///
+    /// Returns [`ReadError::PayloadOverflow`] if the previous command payload
+    /// has been read beyond its length, the reader is moved to the next command,
+    /// so reading can be continued.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<Command>, ReadError> {
+        if self.payload_read() > self.command_len {
+            self.skip_payload();
+            return Err(ReadError::PayloadOverflow);
+        }
 
         if self.read_commands == self.count {
-            return None;
+            return Ok(None);
         }
 
-        let skip_bytes =
-            self.command_breakpoint + self.command_len - self.bytes_reader.current_offset();
+        self.skip_payload();
+
+        let remaining = self.size.saturating_sub(self.bytes_reader.current_offset());
 
-        self.bytes_reader.skip(skip_bytes);
+        if remaining < COMMAND_HEADER_SIZE {
+            return Err(ReadError::TruncatedCommand);
+        }
 
         let command_id = self.bytes_reader.read_u64();
-        self.command_len = self.bytes_reader.read_u64();
+        let command_len = self.bytes_reader.read_u64();
+
+        if command_len > remaining - COMMAND_HEADER_SIZE {
+            return Err(ReadError::TruncatedCommand);
+        }
+
+        self.command_len = command_len;
         self.command_breakpoint = self.bytes_reader.current_offset();
         self.read_commands += 1;
 
-        Some(Command {
+        Ok(Some(Command {
             id: command_id,
+            len: command_len,
             bytes_reader: self.bytes_reader,
-        })
+        }))
     }
 }
 
@@ -68,12 +218,18 @@ impl<'a> CommandsReader<'a> {
 mod tests {
     use vm_buffers::IntoVMBuffers;
     use vm_buffers::{ByteOrder, BytesReader, BytesWriter};
-    use vm_memory::RegionAllocator;
+    use vm_memory::{BufferAccessor, RegionAllocator};
+
+    use super::{CommandsReader, ReadError};
+    use crate::wire;
 
-    use super::CommandsReader;
+    fn write_demo(allocator: &RegionAllocator) -> u64 {
+        write_demo_with_byte_order(allocator, ByteOrder::LittleEndian)
+    }
 
-    fn write_demo(allocator: &RegionAllocator) {
-        let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, allocator);
+    fn write_demo_with_byte_order(allocator: &RegionAllocator, byte_order: ByteOrder) -> u64 {
+        let mut bytes_writer = BytesWriter::new(byte_order, allocator);
+        wire::write_header(&mut bytes_writer);
         bytes_writer.write_u64(5); // Commands count
         let from_address = String::from("tech.paws.tests");
         from_address.write_to_buffers(&mut bytes_writer);
@@ -113,46 +269,48 @@ mod tests {
         bytes_writer.write_u64(8); // Command payload len
         bytes_writer.write_u32(99); // Some payload
         bytes_writer.write_u32(123); // Some payload
+
+        bytes_writer.current_offset()
     }
 
     #[test]
     fn skip_commands() {
         let allocator = RegionAllocator::new(1024);
-        write_demo(&allocator);
+        let size = write_demo(&allocator);
         let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
-        let mut commands_reader = CommandsReader::new(&mut bytes_reader);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size).unwrap();
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 1);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 2);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 3);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 4);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 5);
     }
 
     #[test]
     fn partially_read_commands_payloads() {
         let allocator = RegionAllocator::new(1024);
-        write_demo(&allocator);
+        let size = write_demo(&allocator);
         let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
-        let mut commands_reader = CommandsReader::new(&mut bytes_reader);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size).unwrap();
 
         // Command 1
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 1);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 5);
 
         // Command 2
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 2);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 2);
@@ -162,12 +320,12 @@ mod tests {
         assert_eq!(data, 91);
 
         // Command 3
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 3);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 1);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 4);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 2);
@@ -176,7 +334,7 @@ mod tests {
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 5);
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 5);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 99);
@@ -185,34 +343,139 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
     fn payload_overflow() {
         let allocator = RegionAllocator::new(1024);
-        write_demo(&allocator);
+        let size = write_demo(&allocator);
         let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
-        let mut commands_reader = CommandsReader::new(&mut bytes_reader);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size).unwrap();
 
-        let command = commands_reader.next().unwrap();
+        let command = commands_reader.next().unwrap().unwrap();
         assert_eq!(command.id, 1);
         let data = command.bytes_reader.read_u32();
         assert_eq!(data, 5);
         command.bytes_reader.read_u64();
-        commands_reader.next();
+
+        assert_eq!(
+            commands_reader.next().err(),
+            Some(ReadError::PayloadOverflow)
+        );
+
+        let command = commands_reader.next().unwrap().unwrap();
+        assert_eq!(command.id, 2);
     }
 
     #[test]
     fn read_all_commands() {
         let allocator = RegionAllocator::new(1024);
-        write_demo(&allocator);
+        let size = write_demo(&allocator);
         let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
-        let mut commands_reader = CommandsReader::new(&mut bytes_reader);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size).unwrap();
 
         for _ in 0..5 {
-            let command = commands_reader.next();
+            let command = commands_reader.next().unwrap();
             assert!(command.is_some());
         }
 
-        let command = commands_reader.next();
+        let command = commands_reader.next().unwrap();
         assert!(command.is_none());
     }
+
+    #[test]
+    fn truncated_buffer() {
+        let allocator = RegionAllocator::new(1024);
+        let size = write_demo(&allocator);
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, 12);
+        assert_eq!(commands_reader.err(), Some(ReadError::TruncatedHeader));
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, 20);
+        assert_eq!(commands_reader.err(), Some(ReadError::TruncatedHeader));
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, 56);
+        assert_eq!(commands_reader.err(), Some(ReadError::InvalidCount));
+
+        // Cut the last command payload.
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size - 4).unwrap();
+
+        for _ in 0..4 {
+            assert!(commands_reader.next().unwrap().is_some());
+        }
+
+        assert_eq!(
+            commands_reader.next().err(),
+            Some(ReadError::TruncatedCommand)
+        );
+    }
+
+    #[test]
+    fn invalid_address() {
+        let allocator = RegionAllocator::new(1024);
+        let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, &allocator);
+        wire::write_header(&mut bytes_writer);
+        bytes_writer.write_u64(0); // Commands count
+        bytes_writer.write_u64(2); // Address len
+        bytes_writer.write_byte(0xC3);
+        bytes_writer.write_byte(0x28);
+        let size = bytes_writer.current_offset();
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, size);
+        assert_eq!(commands_reader.err(), Some(ReadError::InvalidAddress));
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, size - 1);
+        assert_eq!(commands_reader.err(), Some(ReadError::InvalidAddress));
+    }
+
+    #[test]
+    fn big_endian_buffer() {
+        let allocator = RegionAllocator::new(1024);
+        let size = write_demo_with_byte_order(&allocator, ByteOrder::BigEndian);
+
+        let data = unsafe { std::slice::from_raw_parts(allocator.get_buffer_ptr(), size as usize) };
+        assert_eq!(wire::byte_order(data), Some(ByteOrder::BigEndian));
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::BigEndian, &allocator);
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size).unwrap();
+        assert_eq!(commands_reader.address, "tech.paws.tests");
+        assert_eq!(commands_reader.count, 5);
+
+        let command = commands_reader.next().unwrap().unwrap();
+        assert_eq!(command.id, 1);
+        assert_eq!(command.len, 8);
+        assert_eq!(command.bytes_reader.read_u32(), 5);
+
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, size);
+        assert_eq!(commands_reader.err(), Some(ReadError::ByteOrderMismatch));
+    }
+
+    #[test]
+    fn incompatible_header() {
+        let allocator = RegionAllocator::new(1024);
+        let size = write_demo(&allocator);
+
+        let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, &allocator);
+        bytes_writer.write_byte(b'X');
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, size);
+        assert_eq!(commands_reader.err(), Some(ReadError::InvalidMagic));
+
+        let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, &allocator);
+        for byte in wire::MAGIC.iter() {
+            bytes_writer.write_byte(*byte);
+        }
+        bytes_writer.write_u32(wire::BYTE_ORDER_MARK);
+        bytes_writer.write_u32(wire::VERSION + 1);
+        let mut bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+        let commands_reader = CommandsReader::new(&mut bytes_reader, size);
+        assert_eq!(
+            commands_reader.err(),
+            Some(ReadError::UnsupportedVersion(wire::VERSION + 1))
+        );
+    }
 }
@@ -0,0 +1,140 @@
+//! Virtual machine errors.
+//!
+//! Rust API returns [`VmError`] wrapped in [`Result`], FFI functions return
+//! stable integer error codes (see [`VmError::code`]) and keep the error
+//! message that can be obtained with `tech_paws_vm_last_error`.
+
+use std::{cell::RefCell, fmt};
+
+use crate::commands_reader::ReadError;
+
+/// Result type of the virtual machine operations.
+pub type Result<T> = std::result::Result<T, VmError>;
+
+/// FFI code: operation succeeded.
+pub const VM_OK: i32 = 0;
+
+/// FFI code: see [`VmError::NotInitialized`].
+pub const VM_ERROR_NOT_INITIALIZED: i32 = 1;
+
+/// FFI code: see [`VmError::UnknownAddress`].
+pub const VM_ERROR_UNKNOWN_ADDRESS: i32 = 2;
+
+/// FFI code: see [`VmError::BufferOverflow`].
+pub const VM_ERROR_BUFFER_OVERFLOW: i32 = 3;
+
+/// FFI code: see [`VmError::MalformedCommand`].
+pub const VM_ERROR_MALFORMED_COMMAND: i32 = 4;
+
+/// FFI code: see [`VmError::InvalidUtf8`].
+pub const VM_ERROR_INVALID_UTF8: i32 = 5;
+
+/// FFI code: see [`VmError::Memory`].
+pub const VM_ERROR_MEMORY: i32 = 6;
+
+/// FFI code: see [`VmError::IncompatibleBuffer`].
+pub const VM_ERROR_INCOMPATIBLE_BUFFER: i32 = 7;
+
+/// FFI code: see [`VmError::Asset`].
+pub const VM_ERROR_ASSET: i32 = 8;
+
+/// FFI code: see [`VmError::InvalidState`].
+pub const VM_ERROR_INVALID_STATE: i32 = 9;
+
+/// Virtual machine error.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmError {
+    /// VM handle is null or the VM state hasn't been created.
+    NotInitialized,
+    /// There is no module registered at the address.
+    UnknownAddress(String),
+    /// Commands buffer has no space left for the command.
+    BufferOverflow,
+    /// Command or commands buffer has an invalid layout.
+    MalformedCommand(&'static str),
+    /// String passed to the VM isn't a valid UTF-8 string.
+    InvalidUtf8,
+    /// Memory allocator failure.
+    Memory(&'static str),
+    /// Commands buffer has been written by an incompatible protocol version
+    /// or with another byte order.
+    IncompatibleBuffer(ReadError),
+    /// Asset can't be loaded or saved.
+    Asset(String),
+    /// Operation isn't allowed in the current VM state,
+    /// e.g. stopping the recording that hasn't been started.
+    InvalidState(&'static str),
+}
+
+impl VmError {
+    /// Stable error code that is returned by FFI functions.
+    pub fn code(&self) -> i32 {
+        match self {
+            VmError::NotInitialized => VM_ERROR_NOT_INITIALIZED,
+            VmError::UnknownAddress(_) => VM_ERROR_UNKNOWN_ADDRESS,
+            VmError::BufferOverflow => VM_ERROR_BUFFER_OVERFLOW,
+            VmError::MalformedCommand(_) => VM_ERROR_MALFORMED_COMMAND,
+            VmError::InvalidUtf8 => VM_ERROR_INVALID_UTF8,
+            VmError::Memory(_) => VM_ERROR_MEMORY,
+            VmError::IncompatibleBuffer(_) => VM_ERROR_INCOMPATIBLE_BUFFER,
+            VmError::Asset(_) => VM_ERROR_ASSET,
+            VmError::InvalidState(_) => VM_ERROR_INVALID_STATE,
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::NotInitialized => write!(f, "vm is not initialized"),
+            VmError::UnknownAddress(address) => write!(f, "unknown address: {}", address),
+            VmError::BufferOverflow => write!(f, "commands buffer overflow"),
+            VmError::MalformedCommand(reason) => write!(f, "malformed command: {}", reason),
+            VmError::InvalidUtf8 => write!(f, "invalid utf-8 string"),
+            VmError::Memory(reason) => write!(f, "memory error: {}", reason),
+            VmError::IncompatibleBuffer(err) => write!(f, "incompatible commands buffer: {}", err),
+            VmError::Asset(reason) => write!(f, "asset error: {}", reason),
+            VmError::InvalidState(reason) => write!(f, "invalid state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<ReadError> for VmError {
+    fn from(err: ReadError) -> Self {
+        match err {
+            ReadError::InvalidMagic
+            | ReadError::UnsupportedVersion(_)
+            | ReadError::ByteOrderMismatch => VmError::IncompatibleBuffer(err),
+            _ => VmError::MalformedCommand(err.reason()),
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Convert the `result` to the FFI error code,
+/// the error message is stored as the last error of the current thread.
+pub fn error_code(result: Result<()>) -> i32 {
+    match result {
+        Ok(()) => VM_OK,
+        Err(err) => {
+            log::error!("{}", err);
+            let code = err.code();
+            LAST_ERROR.with(|last_error| *last_error.borrow_mut() = err.to_string());
+            code
+        }
+    }
+}
+
+/// Call `callback` with the last error message of the current thread,
+/// the message is empty if there were no errors.
+pub fn with_last_error<F, R>(callback: F) -> R
+where
+    F: FnOnce(&str) -> R,
+{
+    LAST_ERROR.with(|last_error| callback(&last_error.borrow()))
+}
@@ -6,11 +6,11 @@
 //! # Examples
 //!
 //! ```rust
-//! use vm::{commands, commands_bus::CommandsBus, gapi, module};
+//! use vm::{commands, gapi, module, Vm};
 //! use vm_math::*;
 //!
-//! unsafe { vm::init() };
-//! let mut commands_bus = CommandsBus::new();
+//! let vm = Vm::new();
+//! let mut commands_bus = vm.commands_bus();
 //!
 //! let gapi_context = gapi::GApiContext {
 //!     from: "my_module_id",
@@ -23,11 +23,11 @@
 //! let quad2_mvp_matrix = Mat4f::IDENT;
 //! let text_mvp_matrix = Mat4f::IDENT;
 //!
-//! gapi::set_color_pipeline(&gapi_context, Vec4f::new(1.0, 1.0, 0.0, 1.0));
-//! gapi::draw_centered_quads(&gapi_context, &[quad1_mvp_matrix, quad2_mvp_matrix]);
+//! gapi::set_color_pipeline(&gapi_context, Vec4f::new(1.0, 1.0, 0.0, 1.0))?;
+//! gapi::draw_centered_quads(&gapi_context, &[quad1_mvp_matrix, quad2_mvp_matrix])?;
 //!
 //! // To render text, we should apply font texture
-//! gapi::set_texture_pipeline(&gapi_context, 0);
+//! gapi::set_texture_pipeline(&gapi_context, 0)?;
 //!
 //! let text_data = gapi::TextData {
 //!     font_id: 0,
@@ -36,7 +36,8 @@
 //!     text: String::from("Hello World!"),
 //! };
 //!
-//! gapi::draw_texts(&gapi_context, &[text_data]);
+//! gapi::draw_texts(&gapi_context, &[text_data])?;
+//! # Ok::<(), vm::error::VmError>(())
 //! ```
 //!
 //! To get more examples check out vm_benchmarks.
@@ -44,9 +45,16 @@
 use vm_buffers::{BytesReader, BytesWriter, IntoVMBuffers};
 use vm_math::{Mat4f, Vec2f, Vec4f};
 
-use crate::{commands, commands_bus::CommandsBus};
+use crate::{
+    commands,
+    commands_bus::CommandsBus,
+    error::Result,
+    macros::{ArgumentKind, MacroArgument},
+    render_commands::{self, PayloadReader, MAT4F_SIZE, VEC4F_SIZE},
+};
 
 /// Data to render text.
+#[derive(Clone, Debug, PartialEq)]
 pub struct TextData {
     /// Asset id for the font to be used to render the text.
     pub font_id: u64,
@@ -62,6 +70,8 @@ pub struct TextData {
 }
 
 impl IntoVMBuffers for TextData {
+    /// Decode the text data without bounds checks, the data should have been
+    /// validated, use [`TextData::read`] to decode untrusted data.
     fn read_from_buffers(bytes_reader: &mut BytesReader) -> Self {
         Self {
             font_id: bytes_reader.read_u64(),
@@ -79,6 +89,22 @@ impl IntoVMBuffers for TextData {
     }
 }
 
+impl TextData {
+    /// Decode the text data from at most `len` bytes of the `bytes_reader`.
+    ///
+    /// Returns [`crate::commands_reader::ReadError::TruncatedCommand`] if the text data
+    /// doesn't fit in `len` bytes and [`crate::error::VmError::InvalidUtf8`] if the text
+    /// isn't a valid UTF-8 string.
+    pub fn read(bytes_reader: &mut BytesReader, len: u64) -> Result<Self> {
+        PayloadReader::new(bytes_reader, len).read_text_data()
+    }
+
+    /// Size of the encoded text data in bytes.
+    pub fn encoded_size(&self) -> u64 {
+        8 + 4 + MAT4F_SIZE + 8 + self.text.len() as u64
+    }
+}
+
 /// Graphical API context, holds important information to render
 pub struct GApiContext<'a> {
     /// The address where to send the render commands.
@@ -92,82 +118,70 @@ pub struct GApiContext<'a> {
 }
 
 /// Set render viewport.
-pub fn set_viewport(context: &GApiContext, x: u32, y: u32, w: u32, h: u32) {
-    context.commands_bus.push_command(
+pub fn set_viewport(context: &GApiContext, x: u32, y: u32, w: u32, h: u32) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::SET_VIEWPORT,
         commands::Source::GAPI,
-        |bytes_writer| {
-            bytes_writer.write_u32(x);
-            bytes_writer.write_u32(y);
-            bytes_writer.write_u32(w);
-            bytes_writer.write_u32(h);
-        },
-    );
+        16,
+        |bytes_writer| render_commands::write_viewport(bytes_writer, x, y, w, h),
+    )
 }
 
 /// Set current pipeline as color - a shader will be used that colorizes
 /// objects with the `color`.
-pub fn set_color_pipeline(context: &GApiContext, color: Vec4f) {
-    context.commands_bus.push_command(
+pub fn set_color_pipeline(context: &GApiContext, color: Vec4f) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::SET_COLOR_PIPELINE,
         commands::Source::GAPI,
+        VEC4F_SIZE,
         |bytes_writer| {
             color.write_to_buffers(bytes_writer);
         },
-    );
+    )
 }
 
 /// Set current pipeline as texture - a shader will be used that applies
 /// texture to objects. The texture will be obtained by asset id = `id`.
-pub fn set_texture_pipeline(context: &GApiContext, id: u64) {
-    context.commands_bus.push_command(
+pub fn set_texture_pipeline(context: &GApiContext, id: u64) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::SET_TEXTURE_PIPELINE,
         commands::Source::GAPI,
+        8,
         |bytes_writer| {
             bytes_writer.write_u64(id);
         },
-    );
+    )
 }
 
 /// Render a group of quads with a given `mvp_matrices`
 /// that is applied to the quads to display on the screen.
 ///
 /// All quads have a center pivot point.
-pub fn draw_centered_quads(context: &GApiContext, mvp_matrices: &[Mat4f]) {
-    context.commands_bus.push_command(
+pub fn draw_centered_quads(context: &GApiContext, mvp_matrices: &[Mat4f]) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::DRAW_CENTERED_QUADS,
         commands::Source::GAPI,
-        |bytes_writer| {
-            bytes_writer.write_u64(mvp_matrices.len() as u64);
-
-            for mat in mvp_matrices.iter() {
-                mat.write_to_buffers(bytes_writer);
-            }
-        },
-    );
+        render_commands::matrices_size(mvp_matrices),
+        |bytes_writer| render_commands::write_matrices(bytes_writer, mvp_matrices),
+    )
 }
 
 /// Render a group of quads with a given `mvp_matrices`
 /// that is applied to the quads to display on the screen.
 ///
 /// All quads have a pivot point in the upper left corner.
-pub fn draw_quads(context: &GApiContext, mvp_matrices: &[Mat4f]) {
-    context.commands_bus.push_command(
+pub fn draw_quads(context: &GApiContext, mvp_matrices: &[Mat4f]) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::DRAW_QUADS,
         commands::Source::GAPI,
-        |bytes_writer| {
-            bytes_writer.write_u64(mvp_matrices.len() as u64);
-
-            for mat in mvp_matrices.iter() {
-                mat.write_to_buffers(bytes_writer);
-            }
-        },
-    );
+        render_commands::matrices_size(mvp_matrices),
+        |bytes_writer| render_commands::write_matrices(bytes_writer, mvp_matrices),
+    )
 }
 
 /// Render a group of lines with a given `mvp_matrix`
@@ -176,20 +190,14 @@ pub fn draw_quads(context: &GApiContext, mvp_matrices: &[Mat4f]) {
 /// Every two point in `points` describe a line.
 ///
 /// Group has a pivot point in the upper left corner.
-pub fn draw_lines(context: &GApiContext, mvp_matrix: &Mat4f, points: &[Vec2f]) {
-    context.commands_bus.push_command(
+pub fn draw_lines(context: &GApiContext, mvp_matrix: &Mat4f, points: &[Vec2f]) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::DRAW_LINES,
         commands::Source::GAPI,
-        |bytes_writer| {
-            mvp_matrix.write_to_buffers(bytes_writer);
-            bytes_writer.write_u64(points.len() as u64);
-
-            for point in points.iter() {
-                point.write_to_buffers(bytes_writer);
-            }
-        },
-    );
+        render_commands::lines_size(points),
+        |bytes_writer| render_commands::write_lines(bytes_writer, mvp_matrix, points),
+    )
 }
 
 /// Render a group of connected straight lines with a given `mvp_matrix`
@@ -198,36 +206,210 @@ pub fn draw_lines(context: &GApiContext, mvp_matrix: &Mat4f, points: &[Vec2f]) {
 /// Every line connects with a previous point.
 ///
 /// Group has a pivot point in the upper left corner.
-pub fn draw_path(context: &GApiContext, mvp_matrix: &Mat4f, points: &[Vec2f]) {
-    context.commands_bus.push_command(
+pub fn draw_path(context: &GApiContext, mvp_matrix: &Mat4f, points: &[Vec2f]) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
         commands::gapi::DRAW_PATH,
         commands::Source::GAPI,
+        render_commands::lines_size(points),
+        |bytes_writer| render_commands::write_lines(bytes_writer, mvp_matrix, points),
+    )
+}
+
+/// Render a group of texts with a given `texts` that describe
+/// the properties of the texts.
+pub fn draw_texts(context: &GApiContext, texts: &[TextData]) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::gapi::DRAW_TEXTS,
+        commands::Source::GAPI,
+        render_commands::texts_size(context.from, texts),
+        |bytes_writer| render_commands::write_texts(bytes_writer, context.from, texts),
+    )
+}
+
+/// Save the current transform, use [`pop_transform`] to restore it.
+pub fn push_transform(context: &GApiContext) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::transforms::PUSH_TRANSFORM,
+        commands::Source::GAPI,
+        0,
+        |_| {},
+    )
+}
+
+/// Restore the transform saved by [`push_transform`].
+pub fn pop_transform(context: &GApiContext) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::transforms::POP_TRANSFORM,
+        commands::Source::GAPI,
+        0,
+        |_| {},
+    )
+}
+
+/// Move the subsequent draw commands by `x` and `y` in their model space,
+/// e.g. in pixels for the geometry drawn with [`crate::transforms::orthographic`].
+pub fn translate(context: &GApiContext, x: f32, y: f32) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::transforms::TRANSLATE,
+        commands::Source::GAPI,
+        8,
+        |bytes_writer| {
+            bytes_writer.write_f32(x);
+            bytes_writer.write_f32(y);
+        },
+    )
+}
+
+/// Rotate the subsequent draw commands by the `angle` in radians
+/// around the origin of their model space.
+pub fn rotate(context: &GApiContext, angle: f32) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::transforms::ROTATE,
+        commands::Source::GAPI,
+        4,
+        |bytes_writer| {
+            bytes_writer.write_f32(angle);
+        },
+    )
+}
+
+/// Scale the subsequent draw commands by `x` and `y` relative to the origin
+/// of their model space.
+pub fn scale(context: &GApiContext, x: f32, y: f32) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::transforms::SCALE,
+        commands::Source::GAPI,
+        8,
         |bytes_writer| {
-            mvp_matrix.write_to_buffers(bytes_writer);
-            bytes_writer.write_u64(points.len() as u64);
+            bytes_writer.write_f32(x);
+            bytes_writer.write_f32(y);
+        },
+    )
+}
+
+/// Start recording a macro with the `name`, the following commands
+/// are recorded until [`end_macro`] instead of being rendered.
+///
+/// The macro replaces the previous macro with the same name.
+pub fn begin_macro(context: &GApiContext, name: &str) -> Result<()> {
+    push_macro_name_command(context, commands::BEGIN_MACRO, name)
+}
 
-            for point in points.iter() {
-                point.write_to_buffers(bytes_writer);
+/// Start recording a macro with the `name` and argument `slots`,
+/// use [`macro_argument`] to bind the recorded commands to the slots.
+pub fn begin_macro_with_slots(
+    context: &GApiContext,
+    name: &str,
+    slots: &[ArgumentKind],
+) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::BEGIN_MACRO,
+        commands::Source::GAPI,
+        8 + name.len() as u64 + 8 + slots.len() as u64,
+        |bytes_writer| {
+            name.to_string().write_to_buffers(bytes_writer);
+            bytes_writer.write_u64(slots.len() as u64);
+
+            for slot in slots.iter() {
+                bytes_writer.write_byte(*slot as u8);
             }
         },
-    );
+    )
 }
 
-/// Render a group of texts with a given `texts` that describe
-/// the properties of the texts.
-pub fn draw_texts(context: &GApiContext, texts: &[TextData]) {
-    context.commands_bus.push_command(
+/// Bind the next recorded command to the argument `slot` of the macro,
+/// the argument passed to [`execute_macro_with_arguments`] is applied to the command.
+pub fn macro_argument(context: &GApiContext, slot: u64) -> Result<()> {
+    context.commands_bus.push_sized_command(
         context.address,
-        commands::gapi::DRAW_TEXTS,
+        commands::MACRO_ARGUMENT,
         commands::Source::GAPI,
+        8,
         |bytes_writer| {
-            context.from.to_string().write_to_buffers(bytes_writer);
-            bytes_writer.write_u64(texts.len() as u64);
+            bytes_writer.write_u64(slot);
+        },
+    )
+}
+
+/// End recording the macro started by [`begin_macro`].
+pub fn end_macro(context: &GApiContext) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::END_MACRO,
+        commands::Source::GAPI,
+        0,
+        |_| {},
+    )
+}
 
-            for text in texts.iter() {
-                text.write_to_buffers(bytes_writer);
+/// Render commands recorded into the macro with the `name`.
+pub fn execute_macro(context: &GApiContext, name: &str) -> Result<()> {
+    push_macro_name_command(context, commands::EXECUTE_MACRO, name)
+}
+
+/// Render commands recorded into the macro with the `name`,
+/// `arguments` fill the macro slots in order.
+pub fn execute_macro_with_arguments(
+    context: &GApiContext,
+    name: &str,
+    arguments: &[MacroArgument],
+) -> Result<()> {
+    let arguments_size: u64 = arguments.iter().map(MacroArgument::encoded_size).sum();
+
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::EXECUTE_MACRO,
+        commands::Source::GAPI,
+        8 + name.len() as u64 + 8 + arguments_size,
+        |bytes_writer| {
+            name.to_string().write_to_buffers(bytes_writer);
+            bytes_writer.write_u64(arguments.len() as u64);
+
+            for argument in arguments.iter() {
+                argument.write_to_buffers(bytes_writer);
             }
         },
-    );
+    )
+}
+
+/// Load the macro with the `name` from the file at the `path`,
+/// see [`crate::macro_file`] for the file format.
+///
+/// The file is read synchronously when the frame is rendered.
+pub fn load_macro(context: &GApiContext, name: &str, path: &str) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        commands::assets::LOAD_MACRO,
+        commands::Source::GAPI,
+        8 + name.len() as u64 + 8 + path.len() as u64,
+        |bytes_writer| {
+            name.to_string().write_to_buffers(bytes_writer);
+            path.to_string().write_to_buffers(bytes_writer);
+        },
+    )
+}
+
+/// Free the macro with the `name`.
+pub fn remove_macro(context: &GApiContext, name: &str) -> Result<()> {
+    push_macro_name_command(context, commands::assets::REMOVE_MACRO, name)
+}
+
+fn push_macro_name_command(context: &GApiContext, id: u64, name: &str) -> Result<()> {
+    context.commands_bus.push_sized_command(
+        context.address,
+        id,
+        commands::Source::GAPI,
+        8 + name.len() as u64,
+        |bytes_writer| {
+            name.to_string().write_to_buffers(bytes_writer);
+        },
+    )
 }
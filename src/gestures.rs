@@ -0,0 +1,671 @@
+//! Gesture recognizer.
+//!
+//! [`GestureRecognizer`] turns the raw pointer events into high-level
+//! [`Gesture`]s - tap, double tap, long press, drag and pinch.
+//! The VM runs the recognizer over the client events every step with the
+//! frame time, the recognized gestures are in [`crate::module::ClientInfo::gestures`].
+//! Modules that need other thresholds can run their own recognizer over
+//! [`crate::module::ClientInfo::events`].
+//!
+//! Tap is reported on every release, so the double tap is preceded by the tap.
+//! Only the left mouse button presses and releases take part in the gestures.
+//!
+//! Client events don't carry timestamps, all events of the frame get the frame
+//! time, so the timeouts are measured with the frame resolution, e.g. a press
+//! and a release received in the same frame have zero duration.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use vm::{
+//!     gestures::{Gesture, GestureRecognizer},
+//!     module::{ClientEvent, MouseButton, Pointer},
+//! };
+//!
+//! let mut recognizer = GestureRecognizer::default();
+//! let press = ClientEvent::MouseDown {
+//!     button: MouseButton::Left,
+//!     x: 10.0,
+//!     y: 10.0,
+//!     pointer: Pointer::touch(1),
+//! };
+//!
+//! assert!(recognizer
+//!     .update(Duration::from_millis(0), &[press])
+//!     .is_empty());
+//! assert_eq!(
+//!     recognizer.update(Duration::from_millis(600), &[]),
+//!     vec![Gesture::LongPress { x: 10.0, y: 10.0 }]
+//! );
+//! ```
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::module::{ClientEvent, MouseButton, Pointer, PointerKind};
+
+/// Thresholds of the gestures.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GestureOptions {
+    /// Max distance the pointer can move to be still a tap or a long press.
+    pub tap_slop: f32,
+
+    /// Max duration of the press to be a tap.
+    pub tap_timeout: Duration,
+
+    /// Max time between the taps of the double tap.
+    pub double_tap_timeout: Duration,
+
+    /// Max distance between the taps of the double tap.
+    pub double_tap_slop: f32,
+
+    /// Min duration of the press to be a long press.
+    pub long_press_timeout: Duration,
+}
+
+impl Default for GestureOptions {
+    fn default() -> Self {
+        GestureOptions {
+            tap_slop: 10.0,
+            tap_timeout: Duration::from_millis(300),
+            double_tap_timeout: Duration::from_millis(300),
+            double_tap_slop: 40.0,
+            long_press_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+/// High-level gesture.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Gesture {
+    /// Short press and release.
+    Tap {
+        /// Position x.
+        x: f32,
+        /// Position y.
+        y: f32,
+    },
+    /// Second tap shortly after the first one.
+    DoubleTap {
+        /// Position x.
+        x: f32,
+        /// Position y.
+        y: f32,
+    },
+    /// Press held without moving.
+    LongPress {
+        /// Position x.
+        x: f32,
+        /// Position y.
+        y: f32,
+    },
+    /// Single pointer started to move beyond the tap slop.
+    DragStart {
+        /// Press position x.
+        x: f32,
+        /// Press position y.
+        y: f32,
+    },
+    /// Dragging pointer has moved.
+    Drag {
+        /// Position x.
+        x: f32,
+        /// Position y.
+        y: f32,
+        /// Movement since the previous drag event along x.
+        dx: f32,
+        /// Movement since the previous drag event along y.
+        dy: f32,
+    },
+    /// Dragging pointer has been released.
+    DragEnd {
+        /// Position x.
+        x: f32,
+        /// Position y.
+        y: f32,
+    },
+    /// Second pointer has been pressed.
+    PinchStart {
+        /// Center between the pointers x.
+        x: f32,
+        /// Center between the pointers y.
+        y: f32,
+    },
+    /// Pinching pointers have moved.
+    Pinch {
+        /// Scale factor relative to the previous pinch event.
+        scale: f32,
+        /// Center between the pointers x.
+        x: f32,
+        /// Center between the pointers y.
+        y: f32,
+    },
+    /// One of the pinching pointers has been released.
+    PinchEnd,
+}
+
+/// Pressed pointer.
+#[derive(Clone, Debug)]
+struct Contact {
+    start_time: Duration,
+    start: (f32, f32),
+    position: (f32, f32),
+    dragging: bool,
+    long_pressed: bool,
+    /// Contact took part in the pinch, it isn't a tap or drag anymore.
+    cancelled: bool,
+}
+
+/// Recognizes gestures from the stream of the pointer events.
+#[derive(Clone, Debug, Default)]
+pub struct GestureRecognizer {
+    /// Thresholds of the gestures.
+    pub options: GestureOptions,
+
+    contacts: HashMap<u64, Contact>,
+    /// Ids of the pinching pointers and the distance between them.
+    pinch: Option<(u64, u64, f32)>,
+    /// Time and position of the last tap.
+    last_tap: Option<(Duration, f32, f32)>,
+}
+
+impl GestureRecognizer {
+    /// Create a new recognizer with the `options`.
+    pub fn new(options: GestureOptions) -> Self {
+        GestureRecognizer {
+            options,
+            ..GestureRecognizer::default()
+        }
+    }
+
+    /// Consume the `events` received at the `time`, returns the recognized gestures.
+    /// Should be called every frame, even without events, to recognize long presses.
+    pub fn update(&mut self, time: Duration, events: &[ClientEvent]) -> Vec<Gesture> {
+        let mut gestures = Vec::new();
+
+        for event in events.iter() {
+            match event {
+                ClientEvent::MouseDown {
+                    button,
+                    x,
+                    y,
+                    pointer,
+                } if is_primary(button, pointer) => {
+                    self.press(time, pointer.id, (*x, *y), &mut gestures);
+                }
+                ClientEvent::MouseMove { x, y, pointer } => {
+                    self.moved(pointer.id, (*x, *y), &mut gestures);
+                }
+                ClientEvent::MouseUp {
+                    button,
+                    x,
+                    y,
+                    pointer,
+                } if is_primary(button, pointer) => {
+                    self.release(time, pointer.id, (*x, *y), &mut gestures);
+                }
+                _ => (),
+            }
+        }
+
+        for contact in self.contacts.values_mut() {
+            let pressed = time.saturating_sub(contact.start_time);
+
+            if !contact.dragging
+                && !contact.long_pressed
+                && !contact.cancelled
+                && pressed >= self.options.long_press_timeout
+            {
+                contact.long_pressed = true;
+                gestures.push(Gesture::LongPress {
+                    x: contact.position.0,
+                    y: contact.position.1,
+                });
+            }
+        }
+
+        gestures
+    }
+
+    fn press(
+        &mut self,
+        time: Duration,
+        id: u64,
+        position: (f32, f32),
+        gestures: &mut Vec<Gesture>,
+    ) {
+        self.contacts.insert(
+            id,
+            Contact {
+                start_time: time,
+                start: position,
+                position,
+                dragging: false,
+                long_pressed: false,
+                cancelled: false,
+            },
+        );
+
+        if self.pinch.is_some() || self.contacts.len() != 2 {
+            return;
+        }
+
+        let mut ids = self.contacts.keys().copied().collect::<Vec<u64>>();
+        ids.sort_unstable();
+
+        for contact in self.contacts.values_mut() {
+            if contact.dragging {
+                gestures.push(Gesture::DragEnd {
+                    x: contact.position.0,
+                    y: contact.position.1,
+                });
+            }
+
+            contact.dragging = false;
+            contact.cancelled = true;
+        }
+
+        let (first, second) = (&self.contacts[&ids[0]], &self.contacts[&ids[1]]);
+        let (x, y) = center(first.position, second.position);
+        self.pinch = Some((ids[0], ids[1], distance(first.position, second.position)));
+        gestures.push(Gesture::PinchStart { x, y });
+    }
+
+    fn moved(&mut self, id: u64, position: (f32, f32), gestures: &mut Vec<Gesture>) {
+        let contact = match self.contacts.get_mut(&id) {
+            Some(contact) => contact,
+            None => return,
+        };
+
+        let previous = contact.position;
+        contact.position = position;
+
+        if let Some((first, second, last_distance)) = self.pinch {
+            if id == first || id == second {
+                let (first, second) = (&self.contacts[&first], &self.contacts[&second]);
+                let new_distance = distance(first.position, second.position);
+                let (x, y) = center(first.position, second.position);
+                let scale = if last_distance > 0.0 {
+                    new_distance / last_distance
+                }
+                else {
+                    1.0
+                };
+
+                self.pinch = self
+                    .pinch
+                    .map(|(first, second, _)| (first, second, new_distance));
+                gestures.push(Gesture::Pinch { scale, x, y });
+            }
+
+            return;
+        }
+
+        if contact.cancelled {
+            return;
+        }
+
+        if !contact.dragging && distance(contact.start, position) > self.options.tap_slop {
+            contact.dragging = true;
+            gestures.push(Gesture::DragStart {
+                x: contact.start.0,
+                y: contact.start.1,
+            });
+            gestures.push(Gesture::Drag {
+                x: position.0,
+                y: position.1,
+                dx: position.0 - contact.start.0,
+                dy: position.1 - contact.start.1,
+            });
+        }
+        else if contact.dragging {
+            gestures.push(Gesture::Drag {
+                x: position.0,
+                y: position.1,
+                dx: position.0 - previous.0,
+                dy: position.1 - previous.1,
+            });
+        }
+    }
+
+    fn release(
+        &mut self,
+        time: Duration,
+        id: u64,
+        position: (f32, f32),
+        gestures: &mut Vec<Gesture>,
+    ) {
+        let contact = match self.contacts.remove(&id) {
+            Some(contact) => contact,
+            None => return,
+        };
+
+        if let Some((first, second, _)) = self.pinch {
+            if id == first || id == second {
+                self.pinch = None;
+                gestures.push(Gesture::PinchEnd);
+            }
+        }
+
+        if contact.dragging {
+            gestures.push(Gesture::DragEnd {
+                x: position.0,
+                y: position.1,
+            });
+            return;
+        }
+
+        let is_tap = !contact.cancelled
+            && !contact.long_pressed
+            && time.saturating_sub(contact.start_time) <= self.options.tap_timeout
+            && distance(contact.start, position) <= self.options.tap_slop;
+
+        if !is_tap {
+            return;
+        }
+
+        gestures.push(Gesture::Tap {
+            x: position.0,
+            y: position.1,
+        });
+
+        let is_double_tap = match self.last_tap {
+            Some((last_time, x, y)) => {
+                time.saturating_sub(last_time) <= self.options.double_tap_timeout
+                    && distance((x, y), position) <= self.options.double_tap_slop
+            }
+            None => false,
+        };
+
+        if is_double_tap {
+            self.last_tap = None;
+            gestures.push(Gesture::DoubleTap {
+                x: position.0,
+                y: position.1,
+            });
+        }
+        else {
+            self.last_tap = Some((time, position.0, position.1));
+        }
+    }
+}
+
+/// Whether the press or the release takes part in a gesture,
+/// only the left mouse button does.
+fn is_primary(button: &MouseButton, pointer: &Pointer) -> bool {
+    pointer.kind != PointerKind::Mouse || *button == MouseButton::Left
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn center(a: (f32, f32), b: (f32, f32)) -> (f32, f32) {
+    ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Gesture, GestureOptions, GestureRecognizer};
+    use crate::{
+        clock::Clock,
+        commands::Source,
+        module::{self, ClientEvent, MouseButton, Pointer},
+        Vm,
+    };
+
+    fn down(id: u64, x: f32, y: f32) -> ClientEvent {
+        ClientEvent::MouseDown {
+            button: MouseButton::Left,
+            x,
+            y,
+            pointer: Pointer::touch(id),
+        }
+    }
+
+    fn up(id: u64, x: f32, y: f32) -> ClientEvent {
+        ClientEvent::MouseUp {
+            button: MouseButton::Left,
+            x,
+            y,
+            pointer: Pointer::touch(id),
+        }
+    }
+
+    fn moved(id: u64, x: f32, y: f32) -> ClientEvent {
+        ClientEvent::MouseMove {
+            x,
+            y,
+            pointer: Pointer::touch(id),
+        }
+    }
+
+    /// Feed `(time in ms, event)` stream, returns all recognized gestures.
+    fn recognize(
+        recognizer: &mut GestureRecognizer,
+        stream: &[(u64, ClientEvent)],
+    ) -> Vec<Gesture> {
+        stream
+            .iter()
+            .flat_map(|(time, event)| {
+                recognizer.update(Duration::from_millis(*time), std::slice::from_ref(event))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tap_and_double_tap() {
+        let mut recognizer = GestureRecognizer::default();
+
+        let gestures = recognize(
+            &mut recognizer,
+            &[
+                (0, down(1, 10.0, 10.0)),
+                (100, up(1, 12.0, 10.0)),
+                (250, down(2, 20.0, 15.0)),
+                (300, up(2, 20.0, 15.0)),
+                (1000, down(3, 20.0, 15.0)),
+                (1400, up(3, 20.0, 15.0)),
+            ],
+        );
+
+        assert_eq!(
+            gestures,
+            vec![
+                Gesture::Tap { x: 12.0, y: 10.0 },
+                Gesture::Tap { x: 20.0, y: 15.0 },
+                Gesture::DoubleTap { x: 20.0, y: 15.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn long_press() {
+        let mut recognizer = GestureRecognizer::new(GestureOptions {
+            long_press_timeout: Duration::from_millis(200),
+            ..GestureOptions::default()
+        });
+
+        let gestures = recognize(
+            &mut recognizer,
+            &[
+                (0, down(1, 10.0, 10.0)),
+                (100, moved(1, 15.0, 10.0)),
+                (250, moved(1, 16.0, 10.0)),
+                (300, up(1, 16.0, 10.0)),
+            ],
+        );
+
+        assert_eq!(gestures, vec![Gesture::LongPress { x: 16.0, y: 10.0 }]);
+    }
+
+    #[test]
+    fn drag() {
+        let mut recognizer = GestureRecognizer::default();
+
+        let gestures = recognize(
+            &mut recognizer,
+            &[
+                (0, down(1, 10.0, 10.0)),
+                (16, moved(1, 15.0, 10.0)),
+                (32, moved(1, 30.0, 20.0)),
+                (48, moved(1, 40.0, 20.0)),
+                (900, up(1, 40.0, 20.0)),
+            ],
+        );
+
+        assert_eq!(
+            gestures,
+            vec![
+                Gesture::DragStart { x: 10.0, y: 10.0 },
+                Gesture::Drag {
+                    x: 30.0,
+                    y: 20.0,
+                    dx: 20.0,
+                    dy: 10.0,
+                },
+                Gesture::Drag {
+                    x: 40.0,
+                    y: 20.0,
+                    dx: 10.0,
+                    dy: 0.0,
+                },
+                Gesture::DragEnd { x: 40.0, y: 20.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn secondary_mouse_buttons() {
+        let mut recognizer = GestureRecognizer::default();
+        let mouse = |event: ClientEvent, button: MouseButton| {
+            match event {
+                ClientEvent::MouseDown { x, y, .. } => {
+                    ClientEvent::MouseDown {
+                        button,
+                        x,
+                        y,
+                        pointer: Pointer::MOUSE,
+                    }
+                }
+                ClientEvent::MouseUp { x, y, .. } => {
+                    ClientEvent::MouseUp {
+                        button,
+                        x,
+                        y,
+                        pointer: Pointer::MOUSE,
+                    }
+                }
+                event => event,
+            }
+        };
+
+        let gestures = recognize(
+            &mut recognizer,
+            &[
+                (0, mouse(down(0, 10.0, 10.0), MouseButton::Left)),
+                (20, mouse(down(0, 10.0, 10.0), MouseButton::Right)),
+                (40, mouse(up(0, 10.0, 10.0), MouseButton::Right)),
+                (
+                    60,
+                    ClientEvent::MouseMove {
+                        x: 40.0,
+                        y: 10.0,
+                        pointer: Pointer::MOUSE,
+                    },
+                ),
+                (100, mouse(up(0, 40.0, 10.0), MouseButton::Left)),
+            ],
+        );
+
+        assert_eq!(
+            gestures,
+            vec![
+                Gesture::DragStart { x: 10.0, y: 10.0 },
+                Gesture::Drag {
+                    x: 40.0,
+                    y: 10.0,
+                    dx: 30.0,
+                    dy: 0.0,
+                },
+                Gesture::DragEnd { x: 40.0, y: 10.0 },
+            ]
+        );
+    }
+
+    #[test]
+    fn pinch() {
+        let mut recognizer = GestureRecognizer::default();
+
+        let gestures = recognize(
+            &mut recognizer,
+            &[
+                (0, down(1, 0.0, 0.0)),
+                (10, moved(1, 20.0, 0.0)),
+                (20, down(2, 120.0, 0.0)),
+                (30, moved(2, 220.0, 0.0)),
+                (40, moved(1, 120.0, 0.0)),
+                (50, up(2, 220.0, 0.0)),
+                (60, moved(1, 150.0, 0.0)),
+                (70, up(1, 150.0, 0.0)),
+            ],
+        );
+
+        assert_eq!(
+            gestures,
+            vec![
+                Gesture::DragStart { x: 0.0, y: 0.0 },
+                Gesture::Drag {
+                    x: 20.0,
+                    y: 0.0,
+                    dx: 20.0,
+                    dy: 0.0,
+                },
+                Gesture::DragEnd { x: 20.0, y: 0.0 },
+                Gesture::PinchStart { x: 70.0, y: 0.0 },
+                Gesture::Pinch {
+                    scale: 2.0,
+                    x: 120.0,
+                    y: 0.0,
+                },
+                Gesture::Pinch {
+                    scale: 0.5,
+                    x: 170.0,
+                    y: 0.0,
+                },
+                Gesture::PinchEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn vm_gestures() {
+        let mut vm = Vm::new();
+        vm.set_clock(Clock::manual());
+
+        let mut frame = |time: u64, event: Option<ClientEvent>| {
+            if let Some(event) = event {
+                vm.push_client_event(&event).unwrap();
+            }
+
+            vm.state_mut().clock.set_time(Duration::from_millis(time));
+            vm.state_mut().process_commands(Source::Processor).unwrap();
+            vm.state().module_states[module::CLIENT_ID]
+                .client_info
+                .clone()
+        };
+
+        frame(0, Some(down(1, 10.0, 10.0)));
+        let client_info = frame(100, Some(up(1, 10.0, 10.0)));
+        assert_eq!(
+            client_info.gestures,
+            vec![Gesture::Tap { x: 10.0, y: 10.0 }]
+        );
+        assert_eq!(client_info.time, Duration::from_millis(100));
+
+        assert!(frame(200, None).gestures.is_empty());
+    }
+}
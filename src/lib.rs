@@ -2,94 +2,515 @@
 
 //! Virtual machine memory management.
 
+pub mod clock;
 pub mod commands;
 pub mod commands_bus;
 pub mod commands_reader;
 pub mod data;
+pub mod error;
 pub mod gapi;
+pub mod gestures;
+pub mod macro_file;
+pub mod macros;
 pub mod module;
+#[cfg(feature = "snapshot")]
+pub mod png;
+#[cfg(feature = "rasterizer")]
+pub mod rasterizer;
+pub mod render_commands;
+pub mod session;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
 pub mod state;
+#[cfg(feature = "svg")]
+pub mod svg;
+pub mod test_vm;
+pub mod transforms;
+pub mod wire;
 
-use std::{ffi::CStr, os::raw::c_char};
+use std::{ffi::CStr, os::raw::c_char, ptr, time::Duration};
 
 use commands::Source;
 
-use crate::module::Module;
+use crate::module::{ClientEvent, KeyModifiers, Module};
+use clock::{Clock, FixedTimestep};
+use commands_bus::CommandsBus;
 use data::{BytesBuffer, MutBytesBuffer};
+use error::{Result, VmError};
+use macro_file::MacroFormat;
+use session::Session;
 use state::VMState;
 
-static mut STATE: Option<VMState> = None;
+/// Virtual machine handle.
+///
+/// Owns its own [`VMState`] - modules, their states and commands buffers,
+/// so several isolated VMs can live side by side in one process.
+pub struct Vm {
+    state: VMState,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    /// Create a new VM with the client module registered.
+    pub fn new() -> Self {
+        let mut state = VMState::new();
+        state.register_module(Box::new(module::ClientModule::new()));
+
+        Vm { state }
+    }
+
+    /// VM state.
+    pub fn state(&self) -> &VMState {
+        &self.state
+    }
+
+    /// Mutable VM state.
+    pub fn state_mut(&mut self) -> &mut VMState {
+        &mut self.state
+    }
+
+    /// Register a new module.
+    pub fn register_module(&mut self, module: Box<dyn Module>) {
+        self.state.register_module(module);
+    }
+
+    /// Replace the clock the modules delta time is computed from.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.state.clock = clock;
+    }
+
+    /// Enable the fixed-timestep mode with the `step` duration,
+    /// `None` steps modules once per frame, see [`clock`].
+    pub fn set_fixed_timestep(&mut self, step: Option<Duration>) {
+        self.state.fixed_timestep = step.map(FixedTimestep::new);
+    }
+
+    /// Send the client `event` to the client module, modules receive it at the next step.
+    pub fn push_client_event(&self, event: &ClientEvent) -> Result<()> {
+        state::write_client_event(&self.state.client_command_bus, event)
+    }
+
+    /// Create a new commands bus to send commands to the modules of this VM.
+    pub fn commands_bus(&self) -> CommandsBus {
+        CommandsBus::new(self.state.router.clone())
+    }
+}
+
+/// Create a new VM, returns an opaque handle that should be passed
+/// to the rest of the `tech_paws_vm_*` functions.
+#[no_mangle]
+pub extern "C" fn tech_paws_vm_create() -> *mut Vm {
+    Box::into_raw(Box::new(Vm::new()))
+}
 
-/// Initialize VM State.
+/// Destroy the VM created by [`tech_paws_vm_create`].
 ///
 /// # Safety
 ///
-/// Should call once in the main thread.
-pub unsafe fn init() {
-    STATE = Some(VMState::new());
-    let client_module = module::ClientModule::new();
-    register_module(Box::new(client_module));
+/// `vm` should be a handle returned by [`tech_paws_vm_create`],
+/// it can't be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_destroy(vm: *mut Vm) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
 }
 
-/// Initialize VM State.
-#[no_mangle]
-pub unsafe extern "C" fn tech_paws_vm_init() {
-    init();
+/// Get VM from the raw FFI handle.
+unsafe fn vm_from_raw<'a>(vm: *mut Vm) -> Result<&'a mut Vm> {
+    vm.as_mut().ok_or(VmError::NotInitialized)
 }
 
-/// Register a new module
-pub fn register_module(module: Box<dyn Module>) {
-    let state = unsafe { STATE.as_mut().unwrap() };
-    state.register_module(module);
+/// Get string from the raw FFI null terminated string.
+unsafe fn str_from_raw<'a>(str: *const c_char) -> Result<&'a str> {
+    if str.is_null() {
+        return Err(VmError::InvalidUtf8);
+    }
+
+    CStr::from_ptr(str)
+        .to_str()
+        .map_err(|_| VmError::InvalidUtf8)
 }
 
 /// Process all commands from all modules.
+///
+/// Writes to `render_update` whether modules requested rendering.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `render_update` should be a valid pointer or null.
 #[no_mangle]
-pub extern "C" fn tech_paws_vm_process_commands() -> bool {
-    let state = unsafe { STATE.as_mut().unwrap() };
-    state.process_commands(Source::Processor).unwrap()
+pub unsafe extern "C" fn tech_paws_vm_process_commands(
+    vm: *mut Vm,
+    render_update: *mut bool,
+) -> i32 {
+    error::error_code((|| {
+        let update = vm_from_raw(vm)?.state.process_commands(Source::Processor)?;
+
+        if let Some(render_update) = render_update.as_mut() {
+            *render_update = update;
+        }
+
+        Ok(())
+    })())
 }
 
 /// Process all render commands from all modules.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
 #[no_mangle]
-pub extern "C" fn tech_paws_vm_process_render_commands() {
-    let state = unsafe { STATE.as_mut().unwrap() };
-    state.process_commands(Source::GAPI).unwrap();
+pub unsafe extern "C" fn tech_paws_vm_process_render_commands(vm: *mut Vm) -> i32 {
+    error::error_code((|| {
+        vm_from_raw(vm)?.state.process_commands(Source::GAPI)?;
+        Ok(())
+    })())
 }
 
 /// Clear current iteration state - commands memory, frame memory etc.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
 #[no_mangle]
-pub extern "C" fn tech_paws_vm_flush() {
-    let state = unsafe { STATE.as_mut().unwrap() };
-    state.flush().unwrap();
+pub unsafe extern "C" fn tech_paws_vm_flush(vm: *mut Vm) -> i32 {
+    error::error_code((|| vm_from_raw(vm)?.state.flush())())
 }
 
 // TODO(sysint64): Create API to lock with mutex data
-/// Get commands buffer data.
+/// Get commands buffer data and write it to `buffer`.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `buffer` should be a valid pointer.
 #[no_mangle]
-pub extern "C" fn tech_paws_vm_get_commands_buffer() -> MutBytesBuffer {
-    let state = unsafe { STATE.as_mut().unwrap() };
-    state.get_commands_buffer(Source::GAPI)
+pub unsafe extern "C" fn tech_paws_vm_get_commands_buffer(
+    vm: *mut Vm,
+    buffer: *mut MutBytesBuffer,
+) -> i32 {
+    error::error_code((|| {
+        let commands_buffer = vm_from_raw(vm)?.state.get_commands_buffer(Source::GAPI)?;
+        *buffer.as_mut().ok_or(VmError::NotInitialized)? = commands_buffer;
+        Ok(())
+    })())
 }
 
-/// Preparing command for sending.
+/// Preparing command for sending, the payload should not exceed `payload_size` bytes.
+/// Returns null on error, use [`tech_paws_vm_last_error`] to get the reason.
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `address` should be a valid null terminated string.
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_begin_command(
+    vm: *mut Vm,
     address: *const c_char,
     source: Source,
     id: u64,
+    payload_size: u64,
 ) -> *mut vm_buffers::c_api::BytesWriter {
-    let state = STATE.as_mut().unwrap();
-    let address: &str = CStr::from_ptr(address).to_str().unwrap();
-    state.client_command_bus.begin_command(address, source, id)
+    let mut bytes_writer = ptr::null_mut();
+
+    error::error_code((|| {
+        let vm = vm_from_raw(vm)?;
+        let address = str_from_raw(address)?;
+        bytes_writer =
+            vm.state
+                .client_command_bus
+                .begin_command(address, source, id, payload_size)?;
+        Ok(())
+    })());
+
+    bytes_writer
 }
 
 /// Finish command and send it to `address`
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `address` should be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_end_command(
+    vm: *mut Vm,
+    address: *const c_char,
+    source: Source,
+) -> i32 {
+    error::error_code((|| {
+        let vm = vm_from_raw(vm)?;
+        let address = str_from_raw(address)?;
+        vm.state.client_command_bus.end_command(address, source)
+    })())
+}
+
+/// Send the scroll event to the client module, `precise` deltas are in pixels
+/// (trackpad) rather than in lines (wheel).
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_scroll(
+    vm: *mut Vm,
+    dx: f32,
+    dy: f32,
+    precise: bool,
+) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::Scroll { dx, dy, precise };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the pinch (zoom) gesture to the client module, `scale` is relative
+/// to the previous event, `x` and `y` is the focus point.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_pinch(vm: *mut Vm, scale: f32, x: f32, y: f32) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::Pinch { scale, x, y };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the rotate gesture to the client module, `angle` is the rotation
+/// since the previous event in radians, `x` and `y` is the focus point.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_rotate(vm: *mut Vm, angle: f32, x: f32, y: f32) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::Rotate { angle, x, y };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the key down event to the client module, `modifiers` are
+/// the `commands::KEY_MODIFIER_*` bits.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_key_down(
+    vm: *mut Vm,
+    key_code: u32,
+    modifiers: u8,
+    repeat: bool,
+) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::KeyDown {
+            key_code,
+            modifiers: KeyModifiers::from_bits(modifiers),
+            repeat,
+        };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the key up event to the client module, `modifiers` are
+/// the `commands::KEY_MODIFIER_*` bits.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_key_up(
+    vm: *mut Vm,
+    key_code: u32,
+    modifiers: u8,
+) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::KeyUp {
+            key_code,
+            modifiers: KeyModifiers::from_bits(modifiers),
+        };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the committed `text` to the client module.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `text` should be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_text_input(vm: *mut Vm, text: *const c_char) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::TextInput {
+            text: str_from_raw(text)?.to_string(),
+        };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Send the IME composition `text` with the selected range in bytes
+/// to the client module, the empty `text` ends the composition.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `text` should be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_push_ime_composition(
+    vm: *mut Vm,
+    text: *const c_char,
+    cursor_start: u32,
+    cursor_end: u32,
+) -> i32 {
+    error::error_code((|| {
+        let event = ClientEvent::ImeComposition {
+            text: str_from_raw(text)?.to_string(),
+            cursor_start,
+            cursor_end,
+        };
+        vm_from_raw(vm)?.push_client_event(&event)
+    })())
+}
+
+/// Save the macro with the `name` recorded by the modules to the file at the `path`.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `name` and `path` should be valid null terminated strings.
 #[no_mangle]
-pub unsafe extern "C" fn tech_paws_end_command(address: *const c_char, source: Source) {
-    let state = STATE.as_mut().unwrap();
-    let address: &str = CStr::from_ptr(address).to_str().unwrap();
-    state.client_command_bus.end_command(address, source);
+pub unsafe extern "C" fn tech_paws_vm_save_macro(
+    vm: *mut Vm,
+    name: *const c_char,
+    path: *const c_char,
+    format: MacroFormat,
+) -> i32 {
+    error::error_code((|| {
+        let vm = vm_from_raw(vm)?;
+        let name = str_from_raw(name)?;
+        let path = str_from_raw(path)?;
+        vm.state.save_macro(name, path, format)
+    })())
+}
+
+/// Start recording the client input, see [`session`].
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_start_recording(vm: *mut Vm) -> i32 {
+    error::error_code((|| vm_from_raw(vm)?.state.start_recording())())
+}
+
+/// Stop recording the client input and save the session to the file at the `path`.
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `path` should be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_stop_recording(vm: *mut Vm, path: *const c_char) -> i32 {
+    error::error_code((|| {
+        let vm = vm_from_raw(vm)?;
+        let path = str_from_raw(path)?;
+        let session = vm
+            .state
+            .stop_recording()
+            .ok_or(VmError::InvalidState("session isn't being recorded"))?;
+        session.save(path)
+    })())
+}
+
+/// Replay the session from the file at the `path` instead of the client input,
+/// see [`session`].
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`],
+/// `path` should be a valid null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_replay(vm: *mut Vm, path: *const c_char) -> i32 {
+    error::error_code((|| {
+        let vm = vm_from_raw(vm)?;
+        let path = str_from_raw(path)?;
+        let session = Session::load(path)?;
+        vm.state.start_replay(session)
+    })())
+}
+
+/// Set time of the next frame in nanoseconds, e.g. the vsync timestamp,
+/// the VM switches to the manual clock, see [`clock`].
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_set_frame_time(vm: *mut Vm, time: u64) -> i32 {
+    error::error_code((|| {
+        vm_from_raw(vm)?
+            .state
+            .clock
+            .set_time(Duration::from_nanos(time));
+        Ok(())
+    })())
+}
+
+/// Set the fixed-timestep duration in nanoseconds, 0 steps modules once
+/// per frame, see [`clock`].
+/// Returns error code, see [`error`].
+///
+/// # Safety
+///
+/// `vm` should be a valid handle returned by [`tech_paws_vm_create`].
+#[no_mangle]
+pub unsafe extern "C" fn tech_paws_vm_set_fixed_timestep(vm: *mut Vm, step: u64) -> i32 {
+    error::error_code((|| {
+        let step = if step == 0 {
+            None
+        }
+        else {
+            Some(Duration::from_nanos(step))
+        };
+        vm_from_raw(vm)?.set_fixed_timestep(step);
+        Ok(())
+    })())
+}
+
+/// Get the message of the last error occurred in the current thread.
+///
+/// The message is valid until the next failed call.
+#[no_mangle]
+pub extern "C" fn tech_paws_vm_last_error() -> BytesBuffer {
+    error::with_last_error(BytesBuffer::from_str)
 }
 
 /// Get client module id.
@@ -104,34 +525,530 @@ pub extern "C" fn tech_paws_vm_client_id() -> BytesBuffer {
 /// Log level: trace
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_vm_log_trace(message: *const c_char) {
-    let str = CStr::from_ptr(message).to_str().unwrap();
+    let str = CStr::from_ptr(message).to_string_lossy();
     log::trace!("{}", str);
 }
 
 /// Log level: error
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_vm_log_error(message: *const c_char) {
-    let str = CStr::from_ptr(message).to_str().unwrap();
+    let str = CStr::from_ptr(message).to_string_lossy();
     log::error!("{}", str);
 }
 
 /// Log level: warning
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_vm_log_warn(message: *const c_char) {
-    let str = CStr::from_ptr(message).to_str().unwrap();
+    let str = CStr::from_ptr(message).to_string_lossy();
     log::warn!("{}", str);
 }
 
 /// Log level: debug
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_vm_log_debug(message: *const c_char) {
-    let str = CStr::from_ptr(message).to_str().unwrap();
+    let str = CStr::from_ptr(message).to_string_lossy();
     log::debug!("{}", str);
 }
 
 /// Log level: info
 #[no_mangle]
 pub unsafe extern "C" fn tech_paws_vm_log_info(message: *const c_char) {
-    let str = CStr::from_ptr(message).to_str().unwrap();
+    let str = CStr::from_ptr(message).to_string_lossy();
     log::info!("{}", str);
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, ffi::CString, rc::Rc};
+
+    use vm_math::Mat4f;
+
+    use crate::{
+        commands,
+        commands_reader::Command,
+        error::{self, VmError},
+        gapi,
+        module::{
+            self, ClientEvent, CommandStatus, KeyModifiers, Module, ModuleOptions, ModuleState,
+            StepState,
+        },
+        tech_paws_vm_push_ime_composition, tech_paws_vm_push_key_down, tech_paws_vm_push_key_up,
+        tech_paws_vm_push_pinch, tech_paws_vm_push_rotate, tech_paws_vm_push_scroll,
+        tech_paws_vm_push_text_input, tech_paws_vm_stop_recording, wire, Vm,
+    };
+
+    const SERVICE_ID: &str = "tech.paws.tests.service";
+
+    const PING: u64 = 0xFFFF_0001;
+
+    #[derive(Default)]
+    struct ServiceModule {
+        received: Rc<Cell<usize>>,
+    }
+
+    impl Module for ServiceModule {
+        fn id(&self) -> &'static str {
+            SERVICE_ID
+        }
+
+        fn options(&self) -> ModuleOptions {
+            ModuleOptions {
+                processor_commands_capacity: 64,
+                processor_commands_budget: 128,
+                ..ModuleOptions::default()
+            }
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn handle_command(&mut self, _: &mut ModuleState, command: Command) -> CommandStatus {
+            match command.id {
+                PING => {
+                    command.bytes_reader.read_u64();
+                    self.received.set(self.received.get() + 1);
+                    CommandStatus::Handled
+                }
+                _ => CommandStatus::Unhandled,
+            }
+        }
+
+        fn step(&mut self, _: &mut ModuleState) -> StepState {
+            StepState::None
+        }
+
+        fn render(&mut self, _: &mut ModuleState) {}
+    }
+
+    fn commands_count(vm: &mut Vm) -> u64 {
+        let buffer = vm
+            .state_mut()
+            .get_commands_buffer(commands::Source::GAPI)
+            .unwrap();
+        unsafe {
+            (buffer.base.add(wire::COMMANDS_COUNT_OFFSET as usize) as *const u64).read_unaligned()
+        }
+    }
+
+    #[test]
+    fn isolated_vms() {
+        let mut vm1 = Vm::new();
+        let mut vm2 = Vm::new();
+
+        vm1.commands_bus()
+            .push_command(
+                module::CLIENT_ID,
+                commands::gapi::SET_TEXTURE_PIPELINE,
+                commands::Source::GAPI,
+                |bytes_writer| bytes_writer.write_u64(0),
+            )
+            .unwrap();
+
+        assert_eq!(commands_count(&mut vm1), 1);
+        assert_eq!(commands_count(&mut vm2), 0);
+    }
+
+    #[test]
+    fn unknown_address() {
+        let vm = Vm::new();
+        let result = vm.commands_bus().push_command(
+            "tech.paws.unknown",
+            commands::gapi::SET_TEXTURE_PIPELINE,
+            commands::Source::GAPI,
+            |bytes_writer| bytes_writer.write_u64(0),
+        );
+
+        assert_eq!(
+            result,
+            Err(VmError::UnknownAddress(String::from("tech.paws.unknown")))
+        );
+    }
+
+    #[test]
+    fn grow_commands_buffer() {
+        let mut vm = Vm::new();
+        let mut commands_bus = vm.commands_bus();
+        let gapi_context = gapi::GApiContext {
+            from: module::CLIENT_ID,
+            address: module::CLIENT_ID,
+            commands_bus: &mut commands_bus,
+        };
+        let mvp_matrices = vec![Mat4f::IDENT; 500];
+
+        for _ in 0..3 {
+            gapi::draw_quads(&gapi_context, &mvp_matrices).unwrap();
+        }
+
+        assert_eq!(commands_count(&mut vm), 3);
+    }
+
+    #[test]
+    fn commands_buffer_budget() {
+        let mut vm = Vm::new();
+        vm.register_module(Box::new(ServiceModule::default()));
+
+        let result = vm.commands_bus().push_sized_command(
+            SERVICE_ID,
+            commands::COMMAND_TOUCH_MOVE,
+            commands::Source::Processor,
+            256,
+            |_| {},
+        );
+
+        assert_eq!(result, Err(VmError::BufferOverflow));
+
+        let memory_usage = vm.state().memory_usage(SERVICE_ID).unwrap();
+        assert_eq!(memory_usage.processor_commands.capacity, 64);
+        assert_eq!(memory_usage.processor_commands.budget, 128);
+    }
+
+    #[test]
+    fn deliver_processor_commands() {
+        let mut vm = Vm::new();
+        let service = ServiceModule::default();
+        let received = service.received.clone();
+        vm.register_module(Box::new(service));
+
+        for _ in 0..2 {
+            vm.commands_bus()
+                .push_command(
+                    SERVICE_ID,
+                    PING,
+                    commands::Source::Processor,
+                    |bytes_writer| bytes_writer.write_u64(0),
+                )
+                .unwrap();
+        }
+
+        vm.state_mut()
+            .process_commands(commands::Source::Processor)
+            .unwrap();
+        assert_eq!(received.get(), 2);
+
+        vm.state_mut()
+            .process_commands(commands::Source::Processor)
+            .unwrap();
+        assert_eq!(received.get(), 2);
+    }
+
+    #[test]
+    fn report_dispatch_errors() {
+        let mut vm = Vm::new();
+        vm.register_module(Box::new(ServiceModule::default()));
+        let commands_bus = vm.commands_bus();
+
+        commands_bus
+            .push_command(
+                SERVICE_ID,
+                PING,
+                commands::Source::Processor,
+                |bytes_writer| {
+                    bytes_writer.write_u64(0);
+                    bytes_writer.write_u64(0);
+                },
+            )
+            .unwrap();
+        commands_bus
+            .push_command(
+                SERVICE_ID,
+                commands::COMMAND_TOUCH_MOVE,
+                commands::Source::Processor,
+                |_| {},
+            )
+            .unwrap();
+
+        vm.state_mut()
+            .process_commands(commands::Source::Processor)
+            .unwrap();
+
+        let report = &vm
+            .state_mut()
+            .module_state_mut(SERVICE_ID)
+            .unwrap()
+            .dispatch_report;
+        assert_eq!(report.malformed, vec![PING]);
+        assert_eq!(report.unhandled, vec![commands::COMMAND_TOUCH_MOVE]);
+    }
+
+    fn client_events(vm: &mut Vm) -> Result<Vec<ClientEvent>, VmError> {
+        vm.state_mut()
+            .process_commands(commands::Source::Processor)?;
+        Ok(vm
+            .state_mut()
+            .module_state_mut(module::CLIENT_ID)?
+            .client_info
+            .events
+            .clone())
+    }
+
+    #[test]
+    fn keyboard_and_text_events() {
+        let mut vm = Vm::new();
+        let shift_control = commands::KEY_MODIFIER_SHIFT | commands::KEY_MODIFIER_CONTROL;
+
+        unsafe {
+            assert_eq!(
+                tech_paws_vm_push_key_down(&mut vm, 65, shift_control, false),
+                0
+            );
+            assert_eq!(
+                tech_paws_vm_push_key_down(&mut vm, 65, shift_control, true),
+                0
+            );
+            assert_eq!(tech_paws_vm_push_key_up(&mut vm, 65, 0), 0);
+            assert_eq!(
+                tech_paws_vm_push_ime_composition(&mut vm, "にほ\0".as_ptr() as _, 3, 6),
+                0
+            );
+            assert_eq!(
+                tech_paws_vm_push_text_input(&mut vm, "日本\0".as_ptr() as _),
+                0
+            );
+        }
+
+        let modifiers = KeyModifiers {
+            shift: true,
+            control: true,
+            ..KeyModifiers::default()
+        };
+        assert_eq!(modifiers.bits(), shift_control);
+
+        assert_eq!(
+            client_events(&mut vm).unwrap(),
+            vec![
+                ClientEvent::KeyDown {
+                    key_code: 65,
+                    modifiers,
+                    repeat: false,
+                },
+                ClientEvent::KeyDown {
+                    key_code: 65,
+                    modifiers,
+                    repeat: true,
+                },
+                ClientEvent::KeyUp {
+                    key_code: 65,
+                    modifiers: KeyModifiers::default(),
+                },
+                ClientEvent::ImeComposition {
+                    text: String::from("にほ"),
+                    cursor_start: 3,
+                    cursor_end: 6,
+                },
+                ClientEvent::TextInput {
+                    text: String::from("日本"),
+                },
+            ]
+        );
+        assert!(client_events(&mut vm).unwrap().is_empty());
+    }
+
+    #[test]
+    fn coalesced_gesture_events() {
+        let mut vm = Vm::new();
+        let mouse_down = ClientEvent::MouseDown {
+            button: module::MouseButton::Left,
+            x: 1.0,
+            y: 2.0,
+            pointer: module::Pointer::MOUSE,
+        };
+
+        unsafe {
+            assert_eq!(tech_paws_vm_push_scroll(&mut vm, 1.0, -2.0, true), 0);
+            assert_eq!(tech_paws_vm_push_scroll(&mut vm, 0.5, -0.5, true), 0);
+            assert_eq!(tech_paws_vm_push_pinch(&mut vm, 2.0, 10.0, 10.0), 0);
+            assert_eq!(tech_paws_vm_push_pinch(&mut vm, 1.5, 20.0, 30.0), 0);
+            assert_eq!(tech_paws_vm_push_scroll(&mut vm, 0.0, 3.0, false), 0);
+            assert_eq!(tech_paws_vm_push_scroll(&mut vm, 0.0, 1.0, true), 0);
+            assert_eq!(tech_paws_vm_push_rotate(&mut vm, 0.25, 20.0, 30.0), 0);
+            vm.push_client_event(&mouse_down).unwrap();
+            assert_eq!(tech_paws_vm_push_rotate(&mut vm, 0.5, 25.0, 35.0), 0);
+            assert_eq!(tech_paws_vm_push_scroll(&mut vm, 1.0, 1.0, true), 0);
+        }
+
+        assert_eq!(
+            client_events(&mut vm).unwrap(),
+            vec![
+                ClientEvent::Scroll {
+                    dx: 1.5,
+                    dy: -2.5,
+                    precise: true,
+                },
+                ClientEvent::Pinch {
+                    scale: 3.0,
+                    x: 20.0,
+                    y: 30.0,
+                },
+                ClientEvent::Scroll {
+                    dx: 0.0,
+                    dy: 3.0,
+                    precise: false,
+                },
+                ClientEvent::Scroll {
+                    dx: 0.0,
+                    dy: 1.0,
+                    precise: true,
+                },
+                ClientEvent::Rotate {
+                    angle: 0.25,
+                    x: 20.0,
+                    y: 30.0,
+                },
+                mouse_down,
+                ClientEvent::Rotate {
+                    angle: 0.5,
+                    x: 25.0,
+                    y: 35.0,
+                },
+                ClientEvent::Scroll {
+                    dx: 1.0,
+                    dy: 1.0,
+                    precise: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn multi_touch_pointers() {
+        let mut vm = Vm::new();
+        let pen = module::Pointer {
+            id: 7,
+            kind: module::PointerKind::Pen {
+                pressure: 0.5,
+                tilt_x: 0.25,
+                tilt_y: -0.25,
+            },
+        };
+        let events = [
+            ClientEvent::MouseDown {
+                button: module::MouseButton::Left,
+                x: 10.0,
+                y: 10.0,
+                pointer: module::Pointer::touch(1),
+            },
+            ClientEvent::MouseDown {
+                button: module::MouseButton::Left,
+                x: 50.0,
+                y: 10.0,
+                pointer: module::Pointer::touch(2),
+            },
+            ClientEvent::MouseMove {
+                x: 60.0,
+                y: 20.0,
+                pointer: module::Pointer::touch(2),
+            },
+            ClientEvent::MouseMove {
+                x: 30.0,
+                y: 40.0,
+                pointer: pen,
+            },
+        ];
+
+        for event in events.iter() {
+            vm.push_client_event(event).unwrap();
+        }
+
+        assert_eq!(client_events(&mut vm).unwrap(), events.to_vec());
+
+        let pointers = &vm.state().module_states[module::CLIENT_ID]
+            .client_info
+            .pointers;
+        assert_eq!(pointers.len(), 3);
+        assert_eq!(
+            pointers[&2],
+            module::PointerState {
+                kind: module::PointerKind::Touch,
+                x: 60.0,
+                y: 20.0,
+                pressed: Some(module::MouseButton::Left),
+            }
+        );
+        assert_eq!(pointers[&7].kind, pen.kind);
+        assert_eq!(pointers[&7].pressed, None);
+
+        vm.push_client_event(&ClientEvent::MouseUp {
+            button: module::MouseButton::Left,
+            x: 10.0,
+            y: 10.0,
+            pointer: module::Pointer::touch(1),
+        })
+        .unwrap();
+
+        // Legacy payload without the pointer comes from the mouse.
+        vm.commands_bus()
+            .push_command(
+                module::CLIENT_ID,
+                commands::COMMAND_TOUCH_MOVE,
+                commands::Source::Processor,
+                |bytes_writer| {
+                    bytes_writer.write_u32(5);
+                    bytes_writer.write_u32(6);
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            client_events(&mut vm).unwrap()[1],
+            ClientEvent::MouseMove {
+                x: 5.0,
+                y: 6.0,
+                pointer: module::Pointer::MOUSE,
+            }
+        );
+
+        let pointers = &vm.state().module_states[module::CLIENT_ID]
+            .client_info
+            .pointers;
+        let mut ids = pointers.keys().copied().collect::<Vec<u64>>();
+        ids.sort_unstable();
+        assert_eq!(ids, vec![0, 2, 7]);
+    }
+
+    #[test]
+    fn stop_recording_without_recording() {
+        let mut vm = Vm::new();
+        let path = CString::new("session.tps").unwrap();
+        let code = unsafe { tech_paws_vm_stop_recording(&mut vm, path.as_ptr()) };
+
+        assert_eq!(code, error::VM_ERROR_INVALID_STATE);
+        error::with_last_error(|message| {
+            assert_eq!(message, "invalid state: session isn't being recorded")
+        });
+    }
+
+    #[test]
+    fn invalid_text_input() {
+        let mut vm = Vm::new();
+        vm.commands_bus()
+            .push_command(
+                module::CLIENT_ID,
+                commands::COMMAND_TEXT_INPUT,
+                commands::Source::Processor,
+                |bytes_writer| {
+                    bytes_writer.write_u64(2);
+                    bytes_writer.write_byte(0xff);
+                    bytes_writer.write_byte(0xfe);
+                },
+            )
+            .unwrap();
+        assert_eq!(client_events(&mut vm), Err(VmError::InvalidUtf8));
+
+        vm.commands_bus()
+            .push_command(
+                module::CLIENT_ID,
+                commands::COMMAND_TEXT_INPUT,
+                commands::Source::Processor,
+                |bytes_writer| {
+                    bytes_writer.write_u64(16);
+                    bytes_writer.write_byte(b'a');
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            client_events(&mut vm),
+            Err(VmError::MalformedCommand("truncated command"))
+        );
+    }
+}
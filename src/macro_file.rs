@@ -0,0 +1,519 @@
+//! Macro files.
+//!
+//! Recorded macros can be saved to disk and loaded back with
+//! [`commands::assets::LOAD_MACRO`], so pre-baked drawing sequences
+//! can be shipped with the app. There are two formats, [`load`] detects
+//! the format by the magic bytes.
+//!
+//! # Binary format
+//!
+//! All numbers are little-endian:
+//!
+//! ```text
+//! magic        [u8; 8] = "TPVMMACR"
+//! version      u32     = 1
+//! byte_order   u8      - byte order of the payloads, 0 - little-endian, 1 - big-endian
+//! slots_count  u64
+//! slots        [kind u8; slots_count] - 0 - transform, 1 - color, 2 - text
+//! count        u64
+//! commands     [id u64, argument u64, payload_len u64, payload [u8; payload_len]; count]
+//! ```
+//!
+//! `argument` is the index of the argument slot the command is bound to,
+//! or `u64::MAX` if the command isn't bound.
+//!
+//! # Text format
+//!
+//! One item per line, empty lines and lines starting with `#` are ignored:
+//!
+//! ```text
+//! tpvm-macro 1
+//! byte_order little
+//! slots transform color
+//! # command <id> <argument slot or -> <hex payload>
+//! command 0x00020006 1 0000803f0000803f0000803f0000803f
+//! command 0x00020003 - 0100000000000000...
+//! ```
+//!
+//! [`commands::assets::LOAD_MACRO`]: crate::commands::assets::LOAD_MACRO
+
+use std::{fs, path::Path};
+
+use vm_buffers::ByteOrder;
+
+use crate::{
+    commands,
+    error::{Result, VmError},
+    macros::{ArgumentKind, Macro, MacroCommand},
+};
+
+/// Magic bytes at the beginning of the binary macro file.
+pub const MAGIC: [u8; 8] = *b"TPVMMACR";
+
+/// Current version of the macro file format.
+pub const VERSION: u32 = 1;
+
+/// Header line of the text macro file, followed by the version.
+const TEXT_HEADER: &str = "tpvm-macro";
+
+/// `argument` value of the command that isn't bound to an argument slot.
+const NO_ARGUMENT: u64 = u64::MAX;
+
+/// Macro file format.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(C)]
+pub enum MacroFormat {
+    /// Compact binary format.
+    Binary = 0,
+    /// Human-readable text format.
+    Text = 1,
+}
+
+/// Save the `recorded` macro to the file at the `path`,
+/// `byte_order` is the byte order of the recorded payloads.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    recorded: &Macro,
+    byte_order: ByteOrder,
+    format: MacroFormat,
+) -> Result<()> {
+    let path = path.as_ref();
+
+    fs::write(path, encode(recorded, byte_order, format))
+        .map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))
+}
+
+/// Load the macro from the file at the `path`,
+/// returns the macro and the byte order of the recorded payloads.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<(Macro, ByteOrder)> {
+    let path = path.as_ref();
+    let data =
+        fs::read(path).map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))?;
+
+    decode(&data).map_err(|err| {
+        match err {
+            VmError::Asset(reason) => VmError::Asset(format!("{}: {}", path.display(), reason)),
+            err => err,
+        }
+    })
+}
+
+/// Encode the `recorded` macro in the `format`.
+pub fn encode(recorded: &Macro, byte_order: ByteOrder, format: MacroFormat) -> Vec<u8> {
+    match format {
+        MacroFormat::Binary => encode_binary(recorded, byte_order),
+        MacroFormat::Text => encode_text(recorded, byte_order).into_bytes(),
+    }
+}
+
+/// Decode the macro encoded in any of the formats.
+pub fn decode(data: &[u8]) -> Result<(Macro, ByteOrder)> {
+    if data.starts_with(&MAGIC) {
+        decode_binary(data)
+    }
+    else {
+        let text = std::str::from_utf8(data).map_err(|_| VmError::InvalidUtf8)?;
+        decode_text(text)
+    }
+}
+
+fn encode_binary(recorded: &Macro, byte_order: ByteOrder) -> Vec<u8> {
+    let mut data = Vec::new();
+
+    data.extend_from_slice(&MAGIC);
+    data.extend_from_slice(&VERSION.to_le_bytes());
+    data.push(match byte_order {
+        ByteOrder::LittleEndian => 0,
+        ByteOrder::BigEndian => 1,
+    });
+
+    data.extend_from_slice(&(recorded.slots.len() as u64).to_le_bytes());
+    data.extend(recorded.slots.iter().map(|slot| *slot as u8));
+
+    data.extend_from_slice(&(recorded.commands.len() as u64).to_le_bytes());
+
+    for command in recorded.commands.iter() {
+        let argument = command
+            .argument
+            .map(|slot| slot as u64)
+            .unwrap_or(NO_ARGUMENT);
+
+        data.extend_from_slice(&command.id.to_le_bytes());
+        data.extend_from_slice(&argument.to_le_bytes());
+        data.extend_from_slice(&(command.payload.len() as u64).to_le_bytes());
+        data.extend_from_slice(&command.payload);
+    }
+
+    data
+}
+
+/// Reads little-endian numbers from the binary macro file.
+struct BinaryReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    fn read_bytes(&mut self, len: u64) -> Result<&'a [u8]> {
+        if len > self.data.len() as u64 {
+            return Err(VmError::Asset(String::from("truncated macro file")));
+        }
+
+        let (bytes, data) = self.data.split_at(len as usize);
+        self.data = data;
+        Ok(bytes)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+fn decode_binary(data: &[u8]) -> Result<(Macro, ByteOrder)> {
+    let mut reader = BinaryReader { data };
+    reader.read_bytes(MAGIC.len() as u64)?;
+
+    let version = reader.read_u32()?;
+
+    if version != VERSION {
+        return Err(unsupported_version(version));
+    }
+
+    let byte_order = match reader.read_byte()? {
+        0 => ByteOrder::LittleEndian,
+        1 => ByteOrder::BigEndian,
+        _ => return Err(VmError::Asset(String::from("invalid macro byte order"))),
+    };
+
+    let slots_count = reader.read_u64()?;
+    let slots = reader
+        .read_bytes(slots_count)?
+        .iter()
+        .map(|kind| ArgumentKind::from_u8(*kind).ok_or_else(invalid_kind))
+        .collect::<Result<Vec<ArgumentKind>>>()?;
+
+    let count = reader.read_u64()?;
+    let mut commands = Vec::new();
+
+    for _ in 0..count {
+        let id = reader.read_u64()?;
+        let argument = match reader.read_u64()? {
+            NO_ARGUMENT => None,
+            slot => Some(slot as usize),
+        };
+        let payload_len = reader.read_u64()?;
+        let payload = reader.read_bytes(payload_len)?.to_vec();
+
+        commands.push(MacroCommand {
+            id,
+            payload,
+            argument,
+        });
+    }
+
+    let recorded = Macro { slots, commands };
+    validate(&recorded)?;
+    Ok((recorded, byte_order))
+}
+
+fn encode_text(recorded: &Macro, byte_order: ByteOrder) -> String {
+    let mut text = format!("{} {}\n", TEXT_HEADER, VERSION);
+
+    text.push_str(match byte_order {
+        ByteOrder::LittleEndian => "byte_order little\n",
+        ByteOrder::BigEndian => "byte_order big\n",
+    });
+
+    text.push_str("slots");
+
+    for slot in recorded.slots.iter() {
+        text.push(' ');
+        text.push_str(kind_name(*slot));
+    }
+
+    text.push('\n');
+
+    for command in recorded.commands.iter() {
+        let argument = command
+            .argument
+            .map(|slot| slot.to_string())
+            .unwrap_or_else(|| String::from("-"));
+        let payload = command
+            .payload
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        text.push_str(&format!(
+            "command {:#010x} {} {}\n",
+            command.id, argument, payload
+        ));
+    }
+
+    text
+}
+
+fn decode_text(text: &str) -> Result<(Macro, ByteOrder)> {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let header = lines.next().unwrap_or_default();
+    let version = match header.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        [TEXT_HEADER, version] => version.parse::<u32>().map_err(|_| invalid_line(header))?,
+        _ => return Err(VmError::Asset(String::from("invalid macro file magic"))),
+    };
+
+    if version != VERSION {
+        return Err(unsupported_version(version));
+    }
+
+    let mut byte_order = ByteOrder::LittleEndian;
+    let mut recorded = Macro::default();
+
+    for line in lines {
+        let mut items = line.split_whitespace();
+
+        match items.next() {
+            Some("byte_order") => {
+                byte_order = match items.next() {
+                    Some("little") => ByteOrder::LittleEndian,
+                    Some("big") => ByteOrder::BigEndian,
+                    _ => return Err(invalid_line(line)),
+                };
+            }
+            Some("slots") => {
+                recorded.slots = items
+                    .map(|name| kind_from_name(name).ok_or_else(invalid_kind))
+                    .collect::<Result<Vec<ArgumentKind>>>()?;
+            }
+            Some("command") => {
+                let id = items
+                    .next()
+                    .and_then(|id| u64::from_str_radix(id.trim_start_matches("0x"), 16).ok())
+                    .ok_or_else(|| invalid_line(line))?;
+                let argument = match items.next() {
+                    Some("-") => None,
+                    Some(slot) => Some(slot.parse::<usize>().map_err(|_| invalid_line(line))?),
+                    None => return Err(invalid_line(line)),
+                };
+                let payload = decode_hex(items.next().unwrap_or_default())
+                    .ok_or_else(|| invalid_line(line))?;
+
+                recorded.commands.push(MacroCommand {
+                    id,
+                    payload,
+                    argument,
+                });
+            }
+            _ => return Err(invalid_line(line)),
+        }
+    }
+
+    validate(&recorded)?;
+    Ok((recorded, byte_order))
+}
+
+/// Macro commands that can't be recorded in the macro body.
+const CONTROL_COMMANDS: [u64; 5] = [
+    commands::BEGIN_MACRO,
+    commands::END_MACRO,
+    commands::EXECUTE_MACRO,
+    commands::assets::LOAD_MACRO,
+    commands::assets::REMOVE_MACRO,
+];
+
+/// Make sure the body doesn't contain macro commands
+/// and the commands are bound to the existing slots of the matching kind.
+fn validate(recorded: &Macro) -> Result<()> {
+    for command in recorded.commands.iter() {
+        if CONTROL_COMMANDS.contains(&command.id) {
+            return Err(VmError::Asset(format!(
+                "macro command {:#x} can't be recorded in the macro",
+                command.id
+            )));
+        }
+
+        if let Some(slot) = command.argument {
+            match recorded.slots.get(slot) {
+                Some(kind) if kind.accepts(command.id) => (),
+                _ => {
+                    return Err(VmError::Asset(format!(
+                        "command {:#x} is bound to invalid argument slot {}",
+                        command.id, slot
+                    )))
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn kind_name(kind: ArgumentKind) -> &'static str {
+    match kind {
+        ArgumentKind::Transform => "transform",
+        ArgumentKind::Color => "color",
+        ArgumentKind::Text => "text",
+    }
+}
+
+fn kind_from_name(name: &str) -> Option<ArgumentKind> {
+    match name {
+        "transform" => Some(ArgumentKind::Transform),
+        "color" => Some(ArgumentKind::Color),
+        "text" => Some(ArgumentKind::Text),
+        _ => None,
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            match pair {
+                [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn invalid_kind() -> VmError {
+    VmError::Asset(String::from("invalid macro argument kind"))
+}
+
+fn invalid_line(line: &str) -> VmError {
+    VmError::Asset(format!("invalid macro line: {}", line))
+}
+
+fn unsupported_version(version: u32) -> VmError {
+    VmError::Asset(format!(
+        "unsupported macro file version {}, expected {}",
+        version, VERSION
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_buffers::ByteOrder;
+
+    use super::{decode, encode, MacroFormat};
+    use crate::{
+        commands,
+        error::VmError,
+        macros::{ArgumentKind, Macro, MacroCommand},
+    };
+
+    fn demo_macro() -> Macro {
+        Macro {
+            slots: vec![ArgumentKind::Color, ArgumentKind::Transform],
+            commands: vec![
+                MacroCommand {
+                    id: commands::gapi::SET_COLOR_PIPELINE,
+                    payload: vec![0, 0, 128, 63, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 63],
+                    argument: Some(0),
+                },
+                MacroCommand {
+                    id: commands::gapi::SET_VIEWPORT,
+                    payload: vec![0; 16],
+                    argument: None,
+                },
+                MacroCommand {
+                    id: commands::gapi::DRAW_QUADS,
+                    payload: vec![0; 8],
+                    argument: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let recorded = demo_macro();
+        let data = encode(&recorded, ByteOrder::BigEndian, MacroFormat::Binary);
+
+        assert_eq!(decode(&data).unwrap(), (recorded, ByteOrder::BigEndian));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let recorded = demo_macro();
+        let data = encode(&recorded, ByteOrder::LittleEndian, MacroFormat::Text);
+        let text = String::from_utf8(data.clone()).unwrap();
+
+        assert!(text.starts_with("tpvm-macro 1\nbyte_order little\nslots color transform\n"));
+        assert!(text.contains("command 0x00020006 0 0000803f00000000000000000000803f"));
+        assert_eq!(decode(&data).unwrap(), (recorded, ByteOrder::LittleEndian));
+    }
+
+    #[test]
+    fn text_comments() {
+        let text = "# Pre-baked background\n\
+                    tpvm-macro 1\n\
+                    \n\
+                    slots\n\
+                    command 0x00020008 - 00000000000000006400000064000000\n";
+
+        let (recorded, byte_order) = decode(text.as_bytes()).unwrap();
+        assert_eq!(byte_order, ByteOrder::LittleEndian);
+        assert!(recorded.slots.is_empty());
+        assert_eq!(recorded.commands.len(), 1);
+        assert_eq!(recorded.commands[0].id, commands::gapi::SET_VIEWPORT);
+        assert_eq!(recorded.commands[0].payload.len(), 16);
+    }
+
+    #[test]
+    fn invalid_files() {
+        let data = encode(&demo_macro(), ByteOrder::LittleEndian, MacroFormat::Binary);
+        assert_eq!(
+            decode(&data[..data.len() - 1]).err(),
+            Some(VmError::Asset(String::from("truncated macro file")))
+        );
+
+        let mut data = data;
+        data[8] = 2;
+        assert_eq!(
+            decode(&data).err(),
+            Some(VmError::Asset(String::from(
+                "unsupported macro file version 2, expected 1"
+            )))
+        );
+
+        let text = "tpvm-macro 1\nslots text\ncommand 0x00020006 0 0000803f\n";
+        assert_eq!(
+            decode(text.as_bytes()).err(),
+            Some(VmError::Asset(String::from(
+                "command 0x20006 is bound to invalid argument slot 0"
+            )))
+        );
+
+        let text = "tpvm-macro 1\ncommand 0x00010003 - \n";
+        assert_eq!(
+            decode(text.as_bytes()).err(),
+            Some(VmError::Asset(String::from(
+                "macro command 0x10003 can't be recorded in the macro"
+            )))
+        );
+
+        let text = "tpvm-macro 1\ncommand 0x00020006 - 0000803\n";
+        assert_eq!(
+            decode(text.as_bytes()).err(),
+            Some(VmError::Asset(String::from(
+                "invalid macro line: command 0x00020006 - 0000803"
+            )))
+        );
+    }
+}
@@ -0,0 +1,1037 @@
+//! Macros - cached display lists.
+//!
+//! GAPI commands sent between [`commands::BEGIN_MACRO`] and [`commands::END_MACRO`]
+//! are recorded VM-side into a named macro instead of being sent to the client.
+//! [`commands::EXECUTE_MACRO`] replays the recorded commands, so static UI
+//! doesn't have to be encoded every frame, and [`commands::assets::REMOVE_MACRO`]
+//! frees the macro.
+//!
+//! A macro can declare typed argument slots, [`commands::MACRO_ARGUMENT`] binds
+//! the next recorded command to a slot and [`commands::EXECUTE_MACRO`] fills
+//! the slots per call:
+//!
+//! - [`ArgumentKind::Transform`] is multiplied with the matrices of the draw command;
+//! - [`ArgumentKind::Color`] replaces the color of the color pipeline;
+//! - [`ArgumentKind::Text`] replaces the texts of the draw texts command.
+//!
+//! Macros can be saved to disk and loaded back with [`commands::assets::LOAD_MACRO`],
+//! see [`crate::macro_file`]. The file is read synchronously while the frame
+//! is rendered by [`crate::state::VMState::process_commands`], so load macros
+//! ahead of time, e.g. when the module is initialized, rather than every frame.
+//!
+//! Macros are expanded after each module has rendered, a macro recording
+//! has to be ended by the module that has begun it. The client receives
+//! plain render commands.
+
+use std::collections::HashMap;
+
+use vm_buffers::{ByteOrder, BytesReader, BytesWriter, IntoVMBuffers};
+use vm_math::{Mat4f, Vec4f};
+
+use crate::{
+    commands::{self, Source},
+    commands_bus::{CommandsBus, COMMAND_HEADER_SIZE},
+    commands_reader::{Command, CommandsReader, ReadError},
+    error::{Result, VmError},
+    macro_file,
+    module::ModuleCommands,
+    render_commands::{RenderCommand, MAT4F_SIZE, VEC4F_SIZE},
+};
+
+/// Type of the macro argument slot.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ArgumentKind {
+    /// Transformation matrix, see [`MacroArgument::Transform`].
+    Transform = 0,
+    /// Color, see [`MacroArgument::Color`].
+    Color = 1,
+    /// Text, see [`MacroArgument::Text`].
+    Text = 2,
+}
+
+impl ArgumentKind {
+    /// Decode the kind, returns `None` if the `value` isn't a valid kind.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(ArgumentKind::Transform),
+            1 => Some(ArgumentKind::Color),
+            2 => Some(ArgumentKind::Text),
+            _ => None,
+        }
+    }
+
+    /// Whether the command with the `id` can be bound to the argument of this kind.
+    pub fn accepts(&self, id: u64) -> bool {
+        match self {
+            ArgumentKind::Transform => {
+                matches!(
+                    id,
+                    commands::gapi::DRAW_QUADS
+                        | commands::gapi::DRAW_CENTERED_QUADS
+                        | commands::gapi::DRAW_LINES
+                        | commands::gapi::DRAW_PATH
+                        | commands::gapi::DRAW_TEXTS
+                )
+            }
+            ArgumentKind::Color => id == commands::gapi::SET_COLOR_PIPELINE,
+            ArgumentKind::Text => id == commands::gapi::DRAW_TEXTS,
+        }
+    }
+}
+
+/// Value of the macro argument slot passed to [`commands::EXECUTE_MACRO`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroArgument {
+    /// Pre-multiplies the matrices of the bound draw command.
+    Transform(Mat4f),
+    /// Replaces the color of the bound [`commands::gapi::SET_COLOR_PIPELINE`].
+    Color(Vec4f),
+    /// Replaces texts of the bound [`commands::gapi::DRAW_TEXTS`].
+    Text(String),
+}
+
+impl MacroArgument {
+    /// Kind of the slot the argument fills.
+    pub fn kind(&self) -> ArgumentKind {
+        match self {
+            MacroArgument::Transform(_) => ArgumentKind::Transform,
+            MacroArgument::Color(_) => ArgumentKind::Color,
+            MacroArgument::Text(_) => ArgumentKind::Text,
+        }
+    }
+
+    /// Size of the encoded argument in bytes.
+    pub fn encoded_size(&self) -> u64 {
+        1 + match self {
+            MacroArgument::Transform(_) => MAT4F_SIZE,
+            MacroArgument::Color(_) => VEC4F_SIZE,
+            MacroArgument::Text(text) => 8 + text.len() as u64,
+        }
+    }
+
+    /// Encode the argument - `kind u8 + value`.
+    pub fn write_to_buffers(&self, bytes_writer: &mut BytesWriter) {
+        bytes_writer.write_byte(self.kind() as u8);
+
+        match self {
+            MacroArgument::Transform(transform) => transform.write_to_buffers(bytes_writer),
+            MacroArgument::Color(color) => color.write_to_buffers(bytes_writer),
+            MacroArgument::Text(text) => text.write_to_buffers(bytes_writer),
+        }
+    }
+}
+
+/// Command recorded into a macro.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroCommand {
+    /// Command id.
+    pub id: u64,
+    /// Encoded command payload.
+    pub payload: Vec<u8>,
+    /// Index of the argument slot the command is bound to.
+    pub argument: Option<usize>,
+}
+
+/// Recorded run of GAPI commands.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Macro {
+    /// Argument slots that should be filled by [`commands::EXECUTE_MACRO`].
+    pub slots: Vec<ArgumentKind>,
+    /// Commands in the order they have been recorded.
+    pub commands: Vec<MacroCommand>,
+}
+
+impl Macro {
+    /// Commands of the macro with the slots filled with the `arguments`,
+    /// `byte_order` is the byte order of the recorded payloads.
+    pub fn expand(
+        &self,
+        arguments: &[MacroArgument],
+        byte_order: ByteOrder,
+    ) -> Result<Vec<MacroCommand>> {
+        let kinds = arguments.iter().map(MacroArgument::kind);
+
+        if !kinds.eq(self.slots.iter().copied()) {
+            return Err(VmError::MalformedCommand(
+                "macro arguments don't match the macro slots",
+            ));
+        }
+
+        self.commands
+            .iter()
+            .map(|command| {
+                match command.argument {
+                    Some(slot) => {
+                        Ok(MacroCommand {
+                            id: command.id,
+                            payload: apply_argument(command, &arguments[slot], byte_order)?,
+                            argument: None,
+                        })
+                    }
+                    None => Ok(command.clone()),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Macro that is being recorded.
+struct Recording {
+    name: String,
+    recorded: Macro,
+    argument: Option<usize>,
+}
+
+/// Macros stored by the VM.
+#[derive(Default)]
+pub struct Macros {
+    macros: HashMap<String, Macro>,
+}
+
+impl Macros {
+    /// Create an empty macros storage.
+    pub fn new() -> Self {
+        Macros::default()
+    }
+
+    /// Get the macro with the `name`.
+    pub fn get(&self, name: &str) -> Option<&Macro> {
+        self.macros.get(name)
+    }
+
+    /// Store the macro with the `name`, the previous macro with
+    /// the same name is replaced.
+    pub fn insert(&mut self, name: String, recorded: Macro) {
+        self.macros.insert(name, recorded);
+    }
+
+    /// Free the macro with the `name`.
+    pub fn remove(&mut self, name: &str) -> Option<Macro> {
+        self.macros.remove(name)
+    }
+
+    /// Number of stored macros.
+    pub fn len(&self) -> usize {
+        self.macros.len()
+    }
+
+    /// Whether there are no stored macros.
+    pub fn is_empty(&self) -> bool {
+        self.macros.is_empty()
+    }
+
+    /// Record, execute and remove macros from the `commands` buffer of the module
+    /// at the `address`. The buffer is rewritten with `commands_bus`,
+    /// macros commands are replaced with the commands they produce.
+    ///
+    /// The buffer is kept untouched if the expanded commands don't fit in its budget.
+    pub fn expand(
+        &mut self,
+        commands: &ModuleCommands,
+        commands_bus: &CommandsBus,
+        address: &str,
+    ) -> Result<()> {
+        let has_macros = commands.read(|commands_reader| {
+            while let Some(command) = commands_reader.next()? {
+                if is_macro_command(command.id) {
+                    return Ok(true);
+                }
+            }
+
+            Ok(false)
+        })?;
+
+        if !has_macros {
+            return Ok(());
+        }
+
+        let byte_order = commands.byte_order();
+        let output = commands.read(|commands_reader| self.process(commands_reader, byte_order))?;
+        rewrite_commands(commands, commands_bus, address, &output)
+    }
+
+    fn process(
+        &mut self,
+        commands_reader: &mut CommandsReader,
+        byte_order: ByteOrder,
+    ) -> Result<Vec<MacroCommand>> {
+        let mut output = Vec::new();
+        let mut recording: Option<Recording> = None;
+
+        while let Some(mut command) = commands_reader.next()? {
+            match command.id {
+                commands::BEGIN_MACRO => {
+                    if recording.is_some() {
+                        return Err(VmError::MalformedCommand("nested macro recording"));
+                    }
+
+                    let name = read_name(&mut command)?;
+                    let slots = read_slots(&mut command, 8 + name.len() as u64)?;
+
+                    recording = Some(Recording {
+                        name,
+                        recorded: Macro {
+                            slots,
+                            commands: Vec::new(),
+                        },
+                        argument: None,
+                    });
+                }
+                commands::END_MACRO => {
+                    let recording = recording.take().ok_or(VmError::MalformedCommand(
+                        "macro recording hasn't been started",
+                    ))?;
+
+                    if recording.argument.is_some() {
+                        return Err(VmError::MalformedCommand(
+                            "macro argument isn't bound to a command",
+                        ));
+                    }
+
+                    self.macros.insert(recording.name, recording.recorded);
+                }
+                commands::MACRO_ARGUMENT => {
+                    let recording = recording.as_mut().ok_or(VmError::MalformedCommand(
+                        "macro argument outside of macro recording",
+                    ))?;
+
+                    if command.len < 8 {
+                        return Err(ReadError::TruncatedCommand.into());
+                    }
+
+                    let slot = command.bytes_reader.read_u64() as usize;
+
+                    if slot >= recording.recorded.slots.len() {
+                        return Err(VmError::MalformedCommand("invalid macro argument slot"));
+                    }
+
+                    recording.argument = Some(slot);
+                }
+                commands::EXECUTE_MACRO => {
+                    let name = read_name(&mut command)?;
+                    let arguments = read_arguments(&mut command, 8 + name.len() as u64)?;
+
+                    let recorded = match self.macros.get(&name) {
+                        Some(recorded) => recorded,
+                        None => {
+                            log::warn!("execute unknown macro: {}", name);
+                            continue;
+                        }
+                    };
+
+                    let expanded = recorded.expand(&arguments, byte_order)?;
+
+                    match &mut recording {
+                        Some(recording) if recording.argument.is_some() => {
+                            return Err(VmError::MalformedCommand(
+                                "macro argument can't be bound to a macro",
+                            ));
+                        }
+                        Some(recording) => recording.recorded.commands.extend(expanded),
+                        None => output.extend(expanded),
+                    }
+                }
+                commands::assets::LOAD_MACRO => {
+                    let name = read_name(&mut command)?;
+                    let path = read_payload_string(&mut command, 8 + name.len() as u64)?;
+                    let (loaded, loaded_byte_order) = macro_file::load(&path)?;
+
+                    if loaded_byte_order != byte_order {
+                        return Err(VmError::Asset(format!(
+                            "{}: macro byte order doesn't match the commands buffer",
+                            path
+                        )));
+                    }
+
+                    self.macros.insert(name, loaded);
+                }
+                commands::assets::REMOVE_MACRO => {
+                    let name = read_name(&mut command)?;
+
+                    if self.macros.remove(&name).is_none() {
+                        log::warn!("remove unknown macro: {}", name);
+                    }
+                }
+                _ => {
+                    let mut recorded_command = MacroCommand {
+                        id: command.id,
+                        payload: read_payload(command),
+                        argument: None,
+                    };
+
+                    match &mut recording {
+                        Some(recording) => {
+                            if let Some(slot) = recording.argument.take() {
+                                if !recording.recorded.slots[slot].accepts(recorded_command.id) {
+                                    return Err(VmError::MalformedCommand(
+                                        "macro argument doesn't match the command",
+                                    ));
+                                }
+
+                                recorded_command.argument = Some(slot);
+                            }
+
+                            recording.recorded.commands.push(recorded_command);
+                        }
+                        None => output.push(recorded_command),
+                    }
+                }
+            }
+        }
+
+        if recording.is_some() {
+            return Err(VmError::MalformedCommand(
+                "macro recording hasn't been ended",
+            ));
+        }
+
+        Ok(output)
+    }
+}
+
+/// Replace the `commands` buffer of the module at the `address`
+/// with the GAPI `output` commands sent with `commands_bus`.
+///
+/// Returns [`VmError::BufferOverflow`] and keeps the buffer untouched
+/// if the `output` exceeds the buffer budget.
+pub(crate) fn rewrite_commands(
+    commands: &ModuleCommands,
+    commands_bus: &CommandsBus,
+    address: &str,
+    output: &[MacroCommand],
+) -> Result<()> {
+    let size = output
+        .iter()
+        .map(|command| COMMAND_HEADER_SIZE + command.payload.len() as u64)
+        .sum::<u64>();
+
+    if commands.empty_size() + size > commands.budget() {
+        return Err(VmError::BufferOverflow);
+    }
+
+    commands.clear()?;
+
+    for command in output.iter() {
+        commands_bus.push_sized_command(
+            address,
+            command.id,
+            Source::GAPI,
+            command.payload.len() as u64,
+            |bytes_writer| {
+                for byte in command.payload.iter() {
+                    bytes_writer.write_byte(*byte);
+                }
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+fn is_macro_command(id: u64) -> bool {
+    matches!(
+        id,
+        commands::BEGIN_MACRO
+            | commands::END_MACRO
+            | commands::MACRO_ARGUMENT
+            | commands::EXECUTE_MACRO
+            | commands::assets::LOAD_MACRO
+            | commands::assets::REMOVE_MACRO
+    )
+}
+
+/// Read the macro name - the command payload starts with a string.
+fn read_name(command: &mut Command) -> Result<String> {
+    read_payload_string(command, 0)
+}
+
+/// Read a string from the command payload, `read` bytes
+/// of the payload have already been read.
+fn read_payload_string(command: &mut Command, read: u64) -> Result<String> {
+    let remaining = command.len - read;
+
+    if remaining < 8 {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    let len = command.bytes_reader.read_u64();
+
+    if len > remaining - 8 {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    read_string(command.bytes_reader, len)
+}
+
+fn read_string(bytes_reader: &mut BytesReader, len: u64) -> Result<String> {
+    let bytes = (0..len)
+        .map(|_| bytes_reader.read_byte())
+        .collect::<Vec<u8>>();
+
+    String::from_utf8(bytes).map_err(|_| VmError::InvalidUtf8)
+}
+
+/// Read the slots kinds - `count u64 + [kind u8]*`, `read` bytes
+/// of the payload have already been read. The slots are optional.
+fn read_slots(command: &mut Command, read: u64) -> Result<Vec<ArgumentKind>> {
+    let remaining = command.len - read;
+
+    if remaining == 0 {
+        return Ok(Vec::new());
+    }
+
+    if remaining < 8 {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    let count = command.bytes_reader.read_u64();
+
+    if count > remaining - 8 {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    (0..count)
+        .map(|_| {
+            ArgumentKind::from_u8(command.bytes_reader.read_byte())
+                .ok_or(VmError::MalformedCommand("invalid macro argument kind"))
+        })
+        .collect()
+}
+
+/// Read the macro arguments - `count u64 + [kind u8, value]*`, `read` bytes
+/// of the payload have already been read. The arguments are optional.
+fn read_arguments(command: &mut Command, read: u64) -> Result<Vec<MacroArgument>> {
+    let mut remaining = command.len - read;
+
+    if remaining == 0 {
+        return Ok(Vec::new());
+    }
+
+    if remaining < 8 {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    let count = command.bytes_reader.read_u64();
+    remaining -= 8;
+
+    let mut arguments = Vec::new();
+
+    for _ in 0..count {
+        if remaining < 1 {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        let kind = ArgumentKind::from_u8(command.bytes_reader.read_byte())
+            .ok_or(VmError::MalformedCommand("invalid macro argument kind"))?;
+        remaining -= 1;
+
+        let size = match kind {
+            ArgumentKind::Transform => MAT4F_SIZE,
+            ArgumentKind::Color => VEC4F_SIZE,
+            ArgumentKind::Text => 8,
+        };
+
+        if remaining < size {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        remaining -= size;
+
+        let argument = match kind {
+            ArgumentKind::Transform => {
+                MacroArgument::Transform(Mat4f::read_from_buffers(command.bytes_reader))
+            }
+            ArgumentKind::Color => {
+                MacroArgument::Color(Vec4f::read_from_buffers(command.bytes_reader))
+            }
+            ArgumentKind::Text => {
+                let len = command.bytes_reader.read_u64();
+
+                if len > remaining {
+                    return Err(ReadError::TruncatedCommand.into());
+                }
+
+                remaining -= len;
+                MacroArgument::Text(read_string(command.bytes_reader, len)?)
+            }
+        };
+
+        arguments.push(argument);
+    }
+
+    Ok(arguments)
+}
+
+fn read_payload(command: Command) -> Vec<u8> {
+    (0..command.len)
+        .map(|_| command.bytes_reader.read_byte())
+        .collect()
+}
+
+/// Encode the payload of the recorded `command` with the `argument` applied.
+fn apply_argument(
+    command: &MacroCommand,
+    argument: &MacroArgument,
+    byte_order: ByteOrder,
+) -> Result<Vec<u8>> {
+    let mut render_command = RenderCommand::from_bytes(command.id, &command.payload, byte_order)?;
+
+    match (&mut render_command, argument) {
+        (RenderCommand::SetColorPipeline { color }, MacroArgument::Color(new_color)) => {
+            *color = *new_color;
+        }
+        (RenderCommand::DrawTexts { texts, .. }, MacroArgument::Text(new_text)) => {
+            for text in texts.iter_mut() {
+                text.text = new_text.clone();
+            }
+        }
+        (render_command, MacroArgument::Transform(transform)) => {
+            if !render_command.transform(transform) {
+                return Err(VmError::MalformedCommand("command can't be transformed"));
+            }
+        }
+        _ => {
+            return Err(VmError::MalformedCommand(
+                "macro argument doesn't match the command",
+            ))
+        }
+    }
+
+    Ok(render_command.to_bytes(byte_order))
+}
+
+/// Encode the `payload` of the draw command with the `id` with the `transform`
+/// applied in its model space - `matrix * transform`.
+pub(crate) fn transform_payload(
+    id: u64,
+    payload: &[u8],
+    transform: &Mat4f,
+    byte_order: ByteOrder,
+) -> Result<Vec<u8>> {
+    let mut render_command = RenderCommand::from_bytes(id, payload, byte_order)?;
+
+    if !render_command.transform_model(transform) {
+        return Err(VmError::MalformedCommand("command can't be transformed"));
+    }
+
+    Ok(render_command.to_bytes(byte_order))
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_buffers::{ByteOrder, IntoVMBuffers};
+    use vm_math::{Mat4f, Vec4f};
+
+    use super::{ArgumentKind, MacroArgument, MacroCommand, Macros};
+    use crate::{
+        commands::{self, Source},
+        error::{Result, VmError},
+        gapi::{self, GApiContext},
+        macro_file::MacroFormat,
+        module::{self, Module, ModuleOptions, ModuleState, StepState},
+        render_commands::encode,
+        transforms::translation,
+        Vm,
+    };
+
+    const RENDER_ID: &str = "tech.paws.tests.render";
+
+    type RenderFn = Box<dyn FnMut(&GApiContext, usize) -> Result<()>>;
+
+    /// Renders the frame with the `render` callback, the callback gets the frame number.
+    struct RenderModule {
+        frame: usize,
+        render: RenderFn,
+    }
+
+    impl Module for RenderModule {
+        fn id(&self) -> &'static str {
+            RENDER_ID
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, _: &mut ModuleState) -> StepState {
+            StepState::None
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            let context = GApiContext {
+                from: RENDER_ID,
+                address: module::CLIENT_ID,
+                commands_bus: &mut state.commands_bus,
+            };
+
+            (self.render)(&context, self.frame).unwrap();
+            self.frame += 1;
+        }
+    }
+
+    fn create_vm(render: RenderFn) -> Vm {
+        let mut vm = Vm::new();
+        vm.register_module(Box::new(RenderModule { frame: 0, render }));
+        vm
+    }
+
+    /// Render the frame and return the commands sent to the client.
+    fn render_commands(vm: &mut Vm) -> Vec<MacroCommand> {
+        vm.state_mut().process_commands(Source::GAPI).unwrap();
+        vm.state_mut()
+            .module_state_mut(module::CLIENT_ID)
+            .unwrap()
+            .gapi_commands
+            .read(|commands_reader| {
+                let mut commands = Vec::new();
+
+                while let Some(command) = commands_reader.next()? {
+                    commands.push(MacroCommand {
+                        id: command.id,
+                        payload: (0..command.len)
+                            .map(|_| command.bytes_reader.read_byte())
+                            .collect(),
+                        argument: None,
+                    });
+                }
+
+                Ok(commands)
+            })
+            .unwrap()
+    }
+
+    /// Render the frame and return ids of the commands sent to the client.
+    fn render_frame(vm: &mut Vm) -> Vec<u64> {
+        render_commands(vm)
+            .iter()
+            .map(|command| command.id)
+            .collect()
+    }
+
+    fn draw_quad(context: &GApiContext) -> Result<()> {
+        gapi::set_color_pipeline(context, Vec4f::new(1.0, 0.0, 0.0, 1.0))?;
+        gapi::draw_quads(context, &[Mat4f::IDENT])
+    }
+
+    #[test]
+    fn record_and_execute_macro() {
+        let mut vm = create_vm(Box::new(|context, frame| {
+            if frame == 0 {
+                gapi::begin_macro(context, "quad")?;
+                draw_quad(context)?;
+                gapi::end_macro(context)?;
+            }
+
+            gapi::set_viewport(context, 0, 0, 100, 100)?;
+            gapi::execute_macro(context, "quad")?;
+            gapi::execute_macro(context, "quad")
+        }));
+
+        let expected = vec![
+            commands::gapi::SET_VIEWPORT,
+            commands::gapi::SET_COLOR_PIPELINE,
+            commands::gapi::DRAW_QUADS,
+            commands::gapi::SET_COLOR_PIPELINE,
+            commands::gapi::DRAW_QUADS,
+        ];
+
+        assert_eq!(render_frame(&mut vm), expected);
+        assert_eq!(render_frame(&mut vm), expected);
+        assert_eq!(vm.state().macros.len(), 1);
+    }
+
+    #[test]
+    fn remove_macro() {
+        let mut vm = create_vm(Box::new(|context, frame| {
+            match frame {
+                0 => {
+                    gapi::begin_macro(context, "quad")?;
+                    draw_quad(context)?;
+                    gapi::end_macro(context)
+                }
+                1 => gapi::remove_macro(context, "quad"),
+                _ => gapi::execute_macro(context, "quad"),
+            }
+        }));
+
+        assert!(render_frame(&mut vm).is_empty());
+        assert_eq!(vm.state().macros.len(), 1);
+        assert!(render_frame(&mut vm).is_empty());
+        assert!(vm.state().macros.is_empty());
+        assert!(render_frame(&mut vm).is_empty());
+    }
+
+    #[test]
+    fn nested_macros_recording() {
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::begin_macro(context, "outer")?;
+            gapi::begin_macro(context, "inner")?;
+            gapi::end_macro(context)?;
+            gapi::end_macro(context)
+        }));
+
+        let result = vm.state_mut().process_commands(Source::GAPI);
+        assert_eq!(
+            result.err(),
+            Some(VmError::MalformedCommand("nested macro recording"))
+        );
+    }
+
+    #[test]
+    fn unended_macro_recording() {
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::begin_macro(context, "quad")?;
+            draw_quad(context)
+        }));
+
+        let result = vm.state_mut().process_commands(Source::GAPI);
+        assert_eq!(
+            result.err(),
+            Some(VmError::MalformedCommand(
+                "macro recording hasn't been ended"
+            ))
+        );
+        assert!(vm.state().macros.is_empty());
+    }
+
+    #[test]
+    fn expand_overflow_keeps_commands() {
+        let options = ModuleOptions {
+            gapi_commands_capacity: 1024,
+            gapi_commands_budget: 4096,
+            ..ModuleOptions::default()
+        };
+        let (commands, mut commands_bus) = module::create_commands_bus(RENDER_ID, options);
+        let context = GApiContext {
+            from: RENDER_ID,
+            address: RENDER_ID,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::begin_macro(&context, "quads").unwrap();
+        gapi::draw_quads(&context, &[Mat4f::IDENT; 10]).unwrap();
+        gapi::end_macro(&context).unwrap();
+
+        for _ in 0..10 {
+            gapi::execute_macro(&context, "quads").unwrap();
+        }
+
+        let result = Macros::new().expand(&commands, &commands_bus, RENDER_ID);
+        assert_eq!(result, Err(VmError::BufferOverflow));
+
+        let commands_count = commands
+            .read(|commands_reader| Ok(commands_reader.len()))
+            .unwrap();
+        assert_eq!(commands_count, 13);
+    }
+
+    #[test]
+    fn execute_macro_with_arguments() {
+        let mut vm = create_vm(Box::new(|context, frame| {
+            if frame == 0 {
+                gapi::begin_macro_with_slots(
+                    context,
+                    "button",
+                    &[
+                        ArgumentKind::Transform,
+                        ArgumentKind::Color,
+                        ArgumentKind::Text,
+                    ],
+                )?;
+                gapi::macro_argument(context, 1)?;
+                gapi::set_color_pipeline(context, Vec4f::new(1.0, 1.0, 1.0, 1.0))?;
+                gapi::macro_argument(context, 0)?;
+                gapi::draw_quads(context, &[Mat4f::IDENT])?;
+                gapi::macro_argument(context, 2)?;
+                gapi::draw_texts(
+                    context,
+                    &[gapi::TextData {
+                        font_id: 0,
+                        font_size: 12,
+                        mvp_matrix: Mat4f::IDENT,
+                        text: String::from("label"),
+                    }],
+                )?;
+                gapi::end_macro(context)?;
+            }
+
+            gapi::execute_macro_with_arguments(
+                context,
+                "button",
+                &[
+                    MacroArgument::Transform(translation(10.0, 20.0)),
+                    MacroArgument::Color(Vec4f::new(1.0, 0.0, 0.0, 1.0)),
+                    MacroArgument::Text(String::from("OK")),
+                ],
+            )?;
+            gapi::execute_macro_with_arguments(
+                context,
+                "button",
+                &[
+                    MacroArgument::Transform(translation(10.0, 60.0)),
+                    MacroArgument::Color(Vec4f::new(0.0, 0.0, 1.0, 1.0)),
+                    MacroArgument::Text(String::from("Cancel")),
+                ],
+            )
+        }));
+
+        let expected_commands = |y: f32, color: Vec4f, text: &str| {
+            let quads = encode(ByteOrder::LittleEndian, 72, |bytes_writer| {
+                bytes_writer.write_u64(1);
+                translation(10.0, y).write_to_buffers(bytes_writer);
+            });
+            let texts = encode(ByteOrder::LittleEndian, 1024, |bytes_writer| {
+                String::from(RENDER_ID).write_to_buffers(bytes_writer);
+                bytes_writer.write_u64(1);
+                gapi::TextData {
+                    font_id: 0,
+                    font_size: 12,
+                    mvp_matrix: Mat4f::IDENT,
+                    text: String::from(text),
+                }
+                .write_to_buffers(bytes_writer);
+            });
+
+            vec![
+                (
+                    commands::gapi::SET_COLOR_PIPELINE,
+                    encode(ByteOrder::LittleEndian, 16, |bytes_writer| {
+                        color.write_to_buffers(bytes_writer)
+                    }),
+                ),
+                (commands::gapi::DRAW_QUADS, quads),
+                (commands::gapi::DRAW_TEXTS, texts),
+            ]
+        };
+
+        let mut expected = expected_commands(20.0, Vec4f::new(1.0, 0.0, 0.0, 1.0), "OK");
+        expected.extend(expected_commands(
+            60.0,
+            Vec4f::new(0.0, 0.0, 1.0, 1.0),
+            "Cancel",
+        ));
+
+        let commands = render_commands(&mut vm)
+            .into_iter()
+            .map(|command| (command.id, command.payload))
+            .collect::<Vec<(u64, Vec<u8>)>>();
+
+        assert_eq!(commands, expected);
+    }
+
+    #[test]
+    fn arguments_mismatch() {
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::begin_macro_with_slots(context, "quad", &[ArgumentKind::Transform])?;
+            gapi::macro_argument(context, 0)?;
+            gapi::draw_quads(context, &[Mat4f::IDENT])?;
+            gapi::end_macro(context)?;
+            gapi::execute_macro_with_arguments(
+                context,
+                "quad",
+                &[MacroArgument::Color(Vec4f::new(1.0, 0.0, 0.0, 1.0))],
+            )
+        }));
+
+        let result = vm.state_mut().process_commands(Source::GAPI);
+        assert_eq!(
+            result.err(),
+            Some(VmError::MalformedCommand(
+                "macro arguments don't match the macro slots"
+            ))
+        );
+
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::begin_macro_with_slots(context, "quad", &[ArgumentKind::Color])?;
+            gapi::macro_argument(context, 0)?;
+            gapi::draw_quads(context, &[Mat4f::IDENT])?;
+            gapi::end_macro(context)
+        }));
+
+        let result = vm.state_mut().process_commands(Source::GAPI);
+        assert_eq!(
+            result.err(),
+            Some(VmError::MalformedCommand(
+                "macro argument doesn't match the command"
+            ))
+        );
+    }
+
+    #[test]
+    fn load_macro() {
+        let record = |context: &GApiContext, frame: usize| {
+            if frame == 0 {
+                gapi::begin_macro_with_slots(context, "quad", &[ArgumentKind::Color])?;
+                gapi::macro_argument(context, 0)?;
+                draw_quad(context)?;
+                gapi::end_macro(context)?;
+            }
+
+            gapi::execute_macro_with_arguments(
+                context,
+                "quad",
+                &[MacroArgument::Color(Vec4f::new(0.0, 1.0, 0.0, 1.0))],
+            )
+        };
+
+        let mut vm = create_vm(Box::new(record));
+        let expected = render_commands(&mut vm);
+
+        for (format, extension) in [(MacroFormat::Binary, "tpm"), (MacroFormat::Text, "txt")].iter()
+        {
+            let path = std::env::temp_dir().join(format!(
+                "tech_paws_vm_load_macro_{}.{}",
+                std::process::id(),
+                extension
+            ));
+            let path = path.to_str().unwrap().to_string();
+            vm.state().save_macro("quad", &path, *format).unwrap();
+
+            let load_path = path.clone();
+            let mut loaded_vm = create_vm(Box::new(move |context, frame| {
+                if frame == 0 {
+                    gapi::load_macro(context, "quad", &load_path)?;
+                }
+
+                record(context, 1)
+            }));
+
+            assert_eq!(render_commands(&mut loaded_vm), expected);
+            assert_eq!(render_commands(&mut loaded_vm), expected);
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::load_macro(context, "quad", "/nonexistent/tech_paws_vm.tpm")
+        }));
+
+        let result = vm.state_mut().process_commands(Source::GAPI);
+        assert!(matches!(result, Err(VmError::Asset(_))));
+    }
+
+    #[test]
+    fn transform_macro_commands() {
+        let mut vm = create_vm(Box::new(|context, _| {
+            gapi::begin_macro(context, "quad")?;
+            gapi::translate(context, 1.0, 2.0)?;
+            gapi::draw_quads(context, &[Mat4f::IDENT])?;
+            gapi::end_macro(context)?;
+
+            gapi::push_transform(context)?;
+            gapi::translate(context, 10.0, 20.0)?;
+            gapi::execute_macro(context, "quad")?;
+            gapi::pop_transform(context)
+        }));
+
+        let expected = encode(ByteOrder::LittleEndian, 72, |bytes_writer| {
+            bytes_writer.write_u64(1);
+            translation(11.0, 22.0).write_to_buffers(bytes_writer);
+        });
+
+        let commands = render_commands(&mut vm);
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].id, commands::gapi::DRAW_QUADS);
+        assert_eq!(commands[0].payload, expected);
+    }
+}
@@ -1,15 +1,26 @@
 //! Module interface.
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    collections::HashMap,
+    mem, slice,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use parking_lot::Mutex;
 use vm_buffers::{ByteOrder, BytesReader, BytesWriter, IntoVMBuffers};
-use vm_memory::RegionAllocator;
+use vm_memory::{BufferAccessor, RegionAllocator};
 
 use crate::{
     commands::{self, Source},
-    commands_bus::CommandsBus,
-    commands_reader::CommandsReader,
+    commands_bus::{CommandsBus, CommandsRouter},
+    commands_reader::{Command, CommandsReader},
+    error::{Result, VmError},
+    gestures::Gesture,
+    wire,
 };
 
 /// Debug services module id.
@@ -21,17 +32,117 @@ pub enum StepState {
     RenderUpdate,
 }
 
+/// Memory options of the module, see [`Module::options`].
+///
+/// Capacities smaller than the commands buffer header are raised to fit
+/// the header and budgets smaller than the capacity are raised to the capacity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModuleOptions {
+    /// Initial capacity of the GAPI commands buffer in bytes.
+    pub gapi_commands_capacity: u64,
+
+    /// The GAPI commands buffer can't grow larger than the budget.
+    pub gapi_commands_budget: u64,
+
+    /// Initial capacity of the processor commands buffer in bytes.
+    pub processor_commands_capacity: u64,
+
+    /// The processor commands buffer can't grow larger than the budget.
+    pub processor_commands_budget: u64,
+
+    /// Capacity of the text boundaries allocator in bytes.
+    pub text_boundaries_capacity: u64,
+}
+
+impl Default for ModuleOptions {
+    fn default() -> Self {
+        ModuleOptions {
+            gapi_commands_capacity: 1024 * 10,
+            gapi_commands_budget: 1024 * 1024,
+            processor_commands_capacity: 1024 * 10,
+            processor_commands_budget: 1024 * 1024,
+            text_boundaries_capacity: 1024 * 1024,
+        }
+    }
+}
+
+/// Memory usage of the commands buffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MemoryUsage {
+    /// Bytes written to the buffer.
+    pub size: u64,
+
+    /// Current capacity of the buffer.
+    pub capacity: u64,
+
+    /// Maximum capacity of the buffer.
+    pub budget: u64,
+}
+
+/// Memory usage of the module.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ModuleMemoryUsage {
+    /// GAPI commands buffer memory usage.
+    pub gapi_commands: MemoryUsage,
+
+    /// Processor commands buffer memory usage.
+    pub processor_commands: MemoryUsage,
+
+    /// Capacity of the text boundaries allocator.
+    pub text_boundaries_capacity: u64,
+}
+
+/// Result of the command handling, see [`Module::handle_command`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CommandStatus {
+    /// Command has been handled, the whole payload has been read.
+    Handled,
+    /// Module doesn't know the command.
+    Unhandled,
+}
+
+/// Report of the commands dispatched to the module at the last step.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DispatchReport {
+    /// Ids of the commands that module hasn't handled.
+    pub unhandled: Vec<u64>,
+
+    /// Ids of the commands which payload has been read partially or overread.
+    pub malformed: Vec<u64>,
+}
+
+impl DispatchReport {
+    /// Whether all commands have been handled correctly.
+    pub fn is_ok(&self) -> bool {
+        self.unhandled.is_empty() && self.malformed.is_empty()
+    }
+}
+
 /// Module interface.
 pub trait Module {
     /// Unique module ID
     fn id(&self) -> &'static str;
 
+    /// Memory options, used once when the module is registered.
+    fn options(&self) -> ModuleOptions {
+        ModuleOptions::default()
+    }
+
     /// Initialize module, e.g. run process or server
     fn init(&mut self, state: &mut ModuleState);
 
     /// Shutdown module, e.g. stop process, or stop server, free resources
     fn shutdown(&mut self, state: &mut ModuleState);
 
+    /// Handle the command sent to the module with [`Source::Processor`].
+    ///
+    /// The VM calls the handler for every command delivered to the module
+    /// since the previous step, right before [`Module::step`].
+    /// The handler should read exactly the whole command payload.
+    fn handle_command(&mut self, _state: &mut ModuleState, _command: Command) -> CommandStatus {
+        CommandStatus::Unhandled
+    }
+
     /// Progress, put here some computations
     fn step(&mut self, state: &mut ModuleState) -> StepState;
 
@@ -39,6 +150,10 @@ pub trait Module {
     fn render(&mut self, state: &mut ModuleState);
 }
 
+/// Commands buffer of the module.
+///
+/// The buffer layout is `count + address + [id, len, payload]*`,
+/// the buffer grows when there is not enough space to write a command.
 pub struct ModuleCommands {
     /// Rendering commands.
     pub allocator: Mutex<RegionAllocator>,
@@ -46,14 +161,39 @@ pub struct ModuleCommands {
     pub bytes_writer: Mutex<BytesWriter>,
 
     pub bytes_reader: Mutex<BytesReader>,
+
+    capacity: AtomicU64,
+
+    budget: u64,
+
+    byte_order: ByteOrder,
+
+    module_id: &'static str,
 }
 
 impl ModuleCommands {
-    pub fn new(module_id: &'static str, capacity: usize) -> Self {
-        let allocator = RegionAllocator::new(capacity);
-        let mut bytes_writer = BytesWriter::new(ByteOrder::LittleEndian, &allocator);
-        let bytes_reader = BytesReader::new(ByteOrder::LittleEndian, &allocator);
+    /// Create a new commands buffer with initial `capacity`,
+    /// the buffer can grow up to `budget` bytes.
+    ///
+    /// The `capacity` is raised to fit the buffer header
+    /// and the `budget` is raised to the `capacity`.
+    pub fn new(module_id: &'static str, capacity: u64, budget: u64) -> Self {
+        ModuleCommands::with_byte_order(module_id, capacity, budget, ByteOrder::LittleEndian)
+    }
 
+    /// Create a new commands buffer that is written with `byte_order`.
+    pub fn with_byte_order(
+        module_id: &'static str,
+        capacity: u64,
+        budget: u64,
+        byte_order: ByteOrder,
+    ) -> Self {
+        let capacity = capacity.max(ModuleCommands::header_size(module_id));
+        let allocator = RegionAllocator::new(capacity as usize);
+        let mut bytes_writer = BytesWriter::new(byte_order, &allocator);
+        let bytes_reader = BytesReader::new(byte_order, &allocator);
+
+        wire::write_header(&mut bytes_writer);
         bytes_writer.write_u64(0); // Commands count
         module_id.to_string().write_to_buffers(&mut bytes_writer);
 
@@ -61,52 +201,490 @@ impl ModuleCommands {
             allocator: Mutex::new(allocator),
             bytes_writer: Mutex::new(bytes_writer),
             bytes_reader: Mutex::new(bytes_reader),
+            capacity: AtomicU64::new(capacity),
+            budget: budget.max(capacity),
+            byte_order,
+            module_id,
         }
     }
+
+    /// Size of the wire header, the commands count and the module id.
+    fn header_size(module_id: &str) -> u64 {
+        wire::HEADER_SIZE + 8 + 8 + module_id.len() as u64
+    }
+
+    /// Size of the buffer without commands.
+    pub(crate) fn empty_size(&self) -> u64 {
+        ModuleCommands::header_size(self.module_id)
+    }
+
+    /// Read commands from the beginning of the buffer.
+    pub fn read<F, R>(&self, commands_reader_callback: F) -> Result<R>
+    where
+        F: FnOnce(&mut CommandsReader) -> Result<R>,
+    {
+        let size = self.bytes_writer.lock().current_offset();
+        let mut bytes_reader = self.bytes_reader.lock();
+        bytes_reader.reset();
+
+        let mut commands_reader = CommandsReader::new(&mut bytes_reader, size)?;
+        commands_reader_callback(&mut commands_reader)
+    }
+
+    /// Clear all commands and their data.
+    pub fn clear(&self) -> Result<()> {
+        let mut bytes_writer = self.bytes_writer.lock();
+        let mut allocator = self.allocator.lock();
+        let mut bytes_reader = self.bytes_reader.lock();
+
+        allocator.clear().map_err(VmError::Memory)?;
+        bytes_writer.clear();
+        bytes_reader.reset();
+
+        wire::write_header(&mut bytes_writer);
+        // Write current commands count
+        bytes_writer.write_u64(0);
+        self.module_id
+            .to_string()
+            .write_to_buffers(&mut bytes_writer);
+
+        Ok(())
+    }
+
+    /// Drop the data written after the first `size` bytes with the `bytes_writer`
+    /// of this buffer, e.g. the command that has overrun its payload reserve.
+    pub(crate) fn truncate(&self, bytes_writer: &mut BytesWriter, size: u64) {
+        let allocator = self.allocator.lock();
+        let data =
+            unsafe { slice::from_raw_parts(allocator.get_buffer_ptr(), size as usize) }.to_vec();
+
+        bytes_writer.clear();
+
+        for byte in data.iter() {
+            bytes_writer.write_byte(*byte);
+        }
+    }
+
+    /// Exchange commands of this buffer with commands of the `other` buffer,
+    /// both buffers should have the same byte order.
+    pub fn swap(&self, other: &ModuleCommands) {
+        let mut bytes_writer = self.bytes_writer.lock();
+        let mut allocator = self.allocator.lock();
+        let mut bytes_reader = self.bytes_reader.lock();
+
+        let mut other_bytes_writer = other.bytes_writer.lock();
+        let mut other_allocator = other.allocator.lock();
+        let mut other_bytes_reader = other.bytes_reader.lock();
+
+        mem::swap(&mut *bytes_writer, &mut *other_bytes_writer);
+        mem::swap(&mut *allocator, &mut *other_allocator);
+        mem::swap(&mut *bytes_reader, &mut *other_bytes_reader);
+
+        let capacity = self.capacity();
+        self.capacity.store(other.capacity(), Ordering::Relaxed);
+        other.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// Memory usage of the buffer.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            size: self.bytes_writer.lock().current_offset(),
+            capacity: self.capacity(),
+            budget: self.budget,
+        }
+    }
+
+    /// Maximum capacity of the buffer in bytes.
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    /// Byte order the buffer is written with.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Current capacity of the buffer in bytes.
+    pub fn capacity(&self) -> u64 {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Make sure that at least `additional` bytes can be written
+    /// with `bytes_writer`, the buffer is reallocated if needed.
+    ///
+    /// `bytes_writer` should be the locked writer of this buffer.
+    /// All data and the writer and reader offsets are preserved, so
+    /// commands that have already been written stay readable.
+    ///
+    /// Returns [`VmError::BufferOverflow`] if the buffer would exceed its budget.
+    pub fn reserve(&self, bytes_writer: &mut BytesWriter, additional: u64) -> Result<()> {
+        let size = bytes_writer.current_offset();
+        let required = size + additional;
+        let capacity = self.capacity();
+
+        if required <= capacity {
+            return Ok(());
+        }
+
+        if required > self.budget {
+            return Err(VmError::BufferOverflow);
+        }
+
+        let capacity = required.next_power_of_two().min(self.budget);
+
+        let mut allocator = self.allocator.lock();
+        let mut bytes_reader = self.bytes_reader.lock();
+
+        let new_allocator = RegionAllocator::new(capacity as usize);
+        let mut new_bytes_writer = BytesWriter::new(self.byte_order, &new_allocator);
+        let mut new_bytes_reader = BytesReader::new(self.byte_order, &new_allocator);
+
+        let data = unsafe { slice::from_raw_parts(allocator.get_buffer_ptr(), size as usize) };
+
+        for byte in data.iter() {
+            new_bytes_writer.write_byte(*byte);
+        }
+
+        new_bytes_reader.skip(bytes_reader.current_offset());
+
+        *bytes_writer = new_bytes_writer;
+        *bytes_reader = new_bytes_reader;
+        *allocator = new_allocator;
+        self.capacity.store(capacity, Ordering::Relaxed);
+
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ClientInfo {
     pub events: Vec<ClientEvent>,
+
+    /// State of the pointers by the pointer id, touch pointers are removed
+    /// when released, mouse and pen pointers stay to track hovering.
+    pub pointers: HashMap<u64, PointerState>,
+
+    /// Gestures recognized from the events, see [`crate::gestures`].
+    pub gestures: Vec<Gesture>,
+
+    /// Frame time the events have been received at, see [`crate::clock`].
+    pub time: Duration,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum ClientEvent {
-    MouseMove { x: f32, y: f32 },
-    MouseDown { button: MouseButton, x: f32, y: f32 },
-    MouseUp { button: MouseButton, x: f32, y: f32 },
-    WindowResize { w: f32, h: f32 },
+    MouseMove {
+        x: f32,
+        y: f32,
+        /// Pointer that has moved.
+        pointer: Pointer,
+    },
+    MouseDown {
+        button: MouseButton,
+        x: f32,
+        y: f32,
+        /// Pointer that has been pressed.
+        pointer: Pointer,
+    },
+    MouseUp {
+        button: MouseButton,
+        x: f32,
+        y: f32,
+        /// Pointer that has been released.
+        pointer: Pointer,
+    },
+    WindowResize {
+        w: f32,
+        h: f32,
+    },
+    /// Scroll wheel or trackpad scroll.
+    Scroll {
+        /// Horizontal scroll delta.
+        dx: f32,
+        /// Vertical scroll delta.
+        dy: f32,
+        /// Deltas are in pixels (trackpad) rather than in lines (wheel).
+        precise: bool,
+    },
+    /// Pinch (zoom) gesture.
+    Pinch {
+        /// Scale factor relative to the previous event.
+        scale: f32,
+        /// Focus point x.
+        x: f32,
+        /// Focus point y.
+        y: f32,
+    },
+    /// Rotate gesture.
+    Rotate {
+        /// Rotation since the previous event in radians.
+        angle: f32,
+        /// Focus point x.
+        x: f32,
+        /// Focus point y.
+        y: f32,
+    },
+    /// Key pressed.
+    KeyDown {
+        /// Key code of the host.
+        key_code: u32,
+        /// Modifier keys held.
+        modifiers: KeyModifiers,
+        /// Auto-repeated press.
+        repeat: bool,
+    },
+    /// Key released.
+    KeyUp {
+        /// Key code of the host.
+        key_code: u32,
+        /// Modifier keys held.
+        modifiers: KeyModifiers,
+    },
+    /// Committed text, e.g. typed characters or the result of the IME composition.
+    TextInput {
+        /// UTF-8 text.
+        text: String,
+    },
+    /// IME composition text has changed.
+    ImeComposition {
+        /// Composed text, the empty text ends the composition.
+        text: String,
+        /// Start of the selected range in bytes.
+        cursor_start: u32,
+        /// End of the selected range in bytes.
+        cursor_end: u32,
+    },
+}
+
+/// Kind of the pointer device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointerKind {
+    /// Mouse or trackpad.
+    Mouse,
+    /// Finger on the touch screen.
+    Touch,
+    /// Stylus.
+    Pen {
+        /// Pressure in `[0, 1]`.
+        pressure: f32,
+        /// Tilt along the x axis in radians.
+        tilt_x: f32,
+        /// Tilt along the y axis in radians.
+        tilt_y: f32,
+    },
+}
+
+/// Pointer of the touch events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pointer {
+    /// Id of the pointer, unique among the active pointers, e.g. per finger.
+    pub id: u64,
+    /// Kind of the pointer.
+    pub kind: PointerKind,
+}
+
+impl Pointer {
+    /// Mouse pointer of the events that come without the pointer.
+    pub const MOUSE: Pointer = Pointer {
+        id: 0,
+        kind: PointerKind::Mouse,
+    };
+
+    /// Touch pointer with the `id`.
+    pub fn touch(id: u64) -> Self {
+        Pointer {
+            id,
+            kind: PointerKind::Touch,
+        }
+    }
+}
+
+impl Default for Pointer {
+    fn default() -> Self {
+        Pointer::MOUSE
+    }
+}
+
+impl IntoVMBuffers for Pointer {
+    fn read_from_buffers(bytes_reader: &mut BytesReader) -> Self {
+        let id = bytes_reader.read_u64();
+        let kind = bytes_reader.read_byte();
+        let pressure = bytes_reader.read_f32();
+        let tilt_x = bytes_reader.read_f32();
+        let tilt_y = bytes_reader.read_f32();
+
+        let kind = match kind {
+            commands::POINTER_KIND_TOUCH => PointerKind::Touch,
+            commands::POINTER_KIND_PEN => {
+                PointerKind::Pen {
+                    pressure,
+                    tilt_x,
+                    tilt_y,
+                }
+            }
+            _ => PointerKind::Mouse,
+        };
+
+        Pointer { id, kind }
+    }
+
+    fn write_to_buffers(&self, bytes_writer: &mut BytesWriter) {
+        let (kind, pressure, tilt_x, tilt_y) = match self.kind {
+            PointerKind::Mouse => (commands::POINTER_KIND_MOUSE, 0.0, 0.0, 0.0),
+            PointerKind::Touch => (commands::POINTER_KIND_TOUCH, 0.0, 0.0, 0.0),
+            PointerKind::Pen {
+                pressure,
+                tilt_x,
+                tilt_y,
+            } => (commands::POINTER_KIND_PEN, pressure, tilt_x, tilt_y),
+        };
+
+        bytes_writer.write_u64(self.id);
+        bytes_writer.write_byte(kind);
+        bytes_writer.write_f32(pressure);
+        bytes_writer.write_f32(tilt_x);
+        bytes_writer.write_f32(tilt_y);
+    }
+}
+
+/// State of the pointer in [`ClientInfo::pointers`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PointerState {
+    /// Kind of the pointer at the last event.
+    pub kind: PointerKind,
+    /// Last position x.
+    pub x: f32,
+    /// Last position y.
+    pub y: f32,
+    /// Pressed button, `None` if the pointer is hovering.
+    pub pressed: Option<MouseButton>,
+}
+
+/// Modifier keys held during the key event.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KeyModifiers {
+    /// Shift key.
+    pub shift: bool,
+    /// Control key.
+    pub control: bool,
+    /// Alt (Option) key.
+    pub alt: bool,
+    /// Super (Command, Windows) key.
+    pub super_key: bool,
+}
+
+impl KeyModifiers {
+    /// Modifiers from the `commands::KEY_MODIFIER_*` bits.
+    pub fn from_bits(bits: u8) -> Self {
+        KeyModifiers {
+            shift: bits & commands::KEY_MODIFIER_SHIFT != 0,
+            control: bits & commands::KEY_MODIFIER_CONTROL != 0,
+            alt: bits & commands::KEY_MODIFIER_ALT != 0,
+            super_key: bits & commands::KEY_MODIFIER_SUPER != 0,
+        }
+    }
+
+    /// `commands::KEY_MODIFIER_*` bits of the modifiers.
+    pub fn bits(&self) -> u8 {
+        let mut bits = 0;
+
+        for (set, bit) in [
+            (self.shift, commands::KEY_MODIFIER_SHIFT),
+            (self.control, commands::KEY_MODIFIER_CONTROL),
+            (self.alt, commands::KEY_MODIFIER_ALT),
+            (self.super_key, commands::KEY_MODIFIER_SUPER),
+        ]
+        .iter()
+        {
+            if *set {
+                bits |= bit;
+            }
+        }
+
+        bits
+    }
 }
 
 impl ClientInfo {
     pub fn new() -> Self {
         Self {
             events: Vec::with_capacity(10),
+            pointers: HashMap::new(),
+            gestures: Vec::new(),
+            time: Duration::from_secs(0),
         }
     }
+
+    /// Update the pointer state table with the touch `event`.
+    pub fn track_pointer(&mut self, event: &ClientEvent) {
+        let (pointer, x, y, pressed) = match event {
+            ClientEvent::MouseDown {
+                button,
+                x,
+                y,
+                pointer,
+            } => (pointer, *x, *y, Some(button.clone())),
+            ClientEvent::MouseUp { x, y, pointer, .. } => {
+                if pointer.kind == PointerKind::Touch {
+                    self.pointers.remove(&pointer.id);
+                    return;
+                }
+
+                (pointer, *x, *y, None)
+            }
+            ClientEvent::MouseMove { x, y, pointer } => {
+                let pressed = self
+                    .pointers
+                    .get(&pointer.id)
+                    .and_then(|state| state.pressed.clone());
+                (pointer, *x, *y, pressed)
+            }
+            _ => return,
+        };
+
+        self.pointers.insert(
+            pointer.id,
+            PointerState {
+                kind: pointer.kind,
+                x,
+                y,
+                pressed,
+            },
+        );
+    }
 }
 
 /// Module state.
 pub struct ModuleState {
     pub id: String,
 
+    /// Memory options the module has been registered with.
+    pub options: ModuleOptions,
+
     pub text_boundaries_allocator: Mutex<RegionAllocator>,
 
-    pub gapi_commands: ModuleCommands,
+    pub gapi_commands: Arc<ModuleCommands>,
+
+    pub processor_commands: Arc<ModuleCommands>,
+
+    /// Processor commands delivered to the module at the current step.
+    pub inbox: Arc<ModuleCommands>,
 
-    pub processor_commands: ModuleCommands,
+    /// Report of the commands dispatched at the last step.
+    pub dispatch_report: DispatchReport,
 
     /// Commands bus to communicate with other modules.
     pub commands_bus: CommandsBus,
 
-    ///
-    pub last_time: Instant,
-
+    /// Time since the previous frame in seconds, see [`crate::clock`].
+    /// In the fixed-timestep mode it's the step duration during [`Module::step`].
     pub delta_time: f32,
 
-    pub client_info: ClientInfo,
+    /// Interpolation factor between the previous and the current step
+    /// for [`Module::render`] in the fixed-timestep mode, otherwise `1.0`.
+    pub alpha: f32,
 
-    pub last_time_initialized: bool,
+    pub client_info: ClientInfo,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -150,88 +728,83 @@ impl IntoVMBuffers for MouseButton {
 }
 
 impl ModuleState {
-    /// Create a new module state.
-    pub fn new(module_id: &'static str) -> Self {
+    /// Create a new module state, `router` is used by the module
+    /// commands bus to reach other modules of the same VM.
+    pub fn new(module_id: &'static str, options: ModuleOptions, router: CommandsRouter) -> Self {
         ModuleState {
             id: module_id.to_string(),
-            text_boundaries_allocator: Mutex::new(RegionAllocator::new(1024 * 1024)),
-            gapi_commands: ModuleCommands::new(module_id, 1024 * 10),
-            processor_commands: ModuleCommands::new(module_id, 1024 * 10),
-            commands_bus: CommandsBus::new(),
-            last_time: Instant::now(),
+            text_boundaries_allocator: Mutex::new(RegionAllocator::new(
+                options.text_boundaries_capacity as usize,
+            )),
+            gapi_commands: Arc::new(ModuleCommands::new(
+                module_id,
+                options.gapi_commands_capacity,
+                options.gapi_commands_budget,
+            )),
+            processor_commands: Arc::new(ModuleCommands::new(
+                module_id,
+                options.processor_commands_capacity,
+                options.processor_commands_budget,
+            )),
+            inbox: Arc::new(ModuleCommands::new(
+                module_id,
+                options.processor_commands_capacity,
+                options.processor_commands_budget,
+            )),
+            options,
+            dispatch_report: DispatchReport::default(),
+            commands_bus: CommandsBus::new(router),
             delta_time: 0.,
-            last_time_initialized: false,
+            alpha: 1.,
             client_info: ClientInfo::new(),
         }
     }
 
-    pub fn get_commands_new<F>(&mut self, source: Source, commands_reader_callback: F)
+    /// Read commands of the module from the `source`.
+    pub fn get_commands_new<F>(&mut self, source: Source, commands_reader_callback: F) -> Result<()>
     where
         F: FnOnce(&mut CommandsReader),
     {
-        let mut bytes_reader = match source {
-            Source::GAPI => self.gapi_commands.bytes_reader.lock(),
-            Source::Processor => self.processor_commands.bytes_reader.lock(),
+        let commands = match source {
+            Source::GAPI => &self.gapi_commands,
+            Source::Processor => &self.processor_commands,
         };
 
-        // let commands_allocator = match source {
-        //     Source::GAPI => self.gapi_commands.allocator.lock(),
-        //     Source::Processor => self.processor_commands.allocator.lock(),
-        // };
-
-        // if source == Source::Processor {
-        //     println!(
-        //         "Dump: -------------------------------------------------------------------------"
-        //     );
-
-        //     let bytes = unsafe {
-        //         std::slice::from_raw_parts(
-        //             commands_allocator_new.get_buffer_ptr(),
-        //             commands_allocator_new.get_buffer_size() as usize,
-        //         )
-        //     };
-        //     hexdump::hexdump(bytes);
-        // }
-
-        let mut commands_reader = CommandsReader::new(&mut bytes_reader);
-        commands_reader_callback(&mut commands_reader);
+        commands.read(|commands_reader| {
+            commands_reader_callback(commands_reader);
+            Ok(())
+        })
     }
 
     /// Clear all commands and ther data from source.
-    pub fn clear_commands(&mut self, source: Source) -> Result<(), &'static str> {
-        let (mut commands_allocator, mut commands_bytes_writer, mut commands_bytes_reader) =
-            match source {
-                Source::GAPI => {
-                    (
-                        self.gapi_commands.allocator.lock(),
-                        self.gapi_commands.bytes_writer.lock(),
-                        self.gapi_commands.bytes_reader.lock(),
-                    )
-                }
-                Source::Processor => {
-                    (
-                        self.processor_commands.allocator.lock(),
-                        self.processor_commands.bytes_writer.lock(),
-                        self.processor_commands.bytes_reader.lock(),
-                    )
-                }
-            };
-
-        commands_allocator.clear()?;
-        commands_bytes_writer.clear();
-        commands_bytes_reader.reset();
-
-        // Write current commands count
-        commands_bytes_writer.write_u64(0);
-        self.id
-            .to_string()
-            .write_to_buffers(&mut commands_bytes_writer);
+    pub fn clear_commands(&mut self, source: Source) -> Result<()> {
+        match source {
+            Source::GAPI => self.gapi_commands.clear(),
+            Source::Processor => self.processor_commands.clear(),
+        }
+    }
 
+    /// Move commands sent to the module to the [`ModuleState::inbox`].
+    pub fn deliver_inbox(&mut self) -> Result<()> {
+        self.inbox.clear()?;
+        self.inbox.swap(&self.processor_commands);
         Ok(())
     }
 
-    pub fn clear_text_boundaries(&mut self) -> Result<(), &'static str> {
-        self.text_boundaries_allocator.lock().clear()
+    /// Memory usage of the module.
+    pub fn memory_usage(&self) -> ModuleMemoryUsage {
+        ModuleMemoryUsage {
+            gapi_commands: self.gapi_commands.memory_usage(),
+            processor_commands: self.processor_commands.memory_usage(),
+            text_boundaries_capacity: self.options.text_boundaries_capacity,
+        }
+    }
+
+    pub fn clear_text_boundaries(&mut self) -> Result<()> {
+        self.text_boundaries_allocator
+            .lock()
+            .clear()
+            .map_err(VmError::Memory)
     }
 }
 
@@ -256,6 +829,15 @@ impl Module for ClientModule {
         CLIENT_ID
     }
 
+    fn options(&self) -> ModuleOptions {
+        // All modules send render commands to the client.
+        ModuleOptions {
+            gapi_commands_capacity: 1024 * 1024,
+            gapi_commands_budget: 64 * 1024 * 1024,
+            ..ModuleOptions::default()
+        }
+    }
+
     fn init(&mut self, _: &mut ModuleState) {}
 
     fn shutdown(&mut self, _: &mut ModuleState) {}
@@ -266,3 +848,51 @@ impl Module for ClientModule {
 
     fn render(&mut self, _: &mut ModuleState) {}
 }
+
+/// Create the commands buffers of the module at the `address` and the commands bus
+/// that reaches them, returns the GAPI commands buffer and the bus.
+#[cfg(test)]
+pub(crate) fn create_commands_bus(
+    address: &'static str,
+    options: ModuleOptions,
+) -> (Arc<ModuleCommands>, CommandsBus) {
+    let router = CommandsRouter::new();
+    let state = ModuleState::new(address, options, router.clone());
+    router.register(
+        address,
+        state.gapi_commands.clone(),
+        state.processor_commands.clone(),
+    );
+
+    (state.gapi_commands, CommandsBus::new(router))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ModuleCommands;
+    use crate::error::VmError;
+
+    const ADDRESS: &str = "tech.paws.tests.module";
+
+    #[test]
+    fn degenerate_commands_buffer_options() {
+        let commands = ModuleCommands::new(ADDRESS, 0, 0);
+        let capacity = commands.capacity();
+        assert_eq!(commands.budget(), capacity);
+        assert_eq!(commands.memory_usage().size, capacity);
+
+        let mut bytes_writer = commands.bytes_writer.lock();
+        assert_eq!(commands.reserve(&mut bytes_writer, 0), Ok(()));
+        assert_eq!(
+            commands.reserve(&mut bytes_writer, 1),
+            Err(VmError::BufferOverflow)
+        );
+        drop(bytes_writer);
+
+        let commands = ModuleCommands::new(ADDRESS, 0, 256);
+        let mut bytes_writer = commands.bytes_writer.lock();
+        assert_eq!(commands.reserve(&mut bytes_writer, 100), Ok(()));
+        assert!(commands.capacity() >= capacity + 100);
+        assert!(commands.capacity() <= 256);
+    }
+}
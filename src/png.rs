@@ -0,0 +1,140 @@
+//! PNG codec for the [`Image`]s, backed by the `png` crate.
+//!
+//! Images of any color type and bit depth are decoded to 8-bit RGBA,
+//! images are encoded as 8-bit RGBA.
+
+use std::{fs, path::Path};
+
+use ::png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+use crate::{
+    error::{Result, VmError},
+    rasterizer::Image,
+};
+
+/// Load the PNG image from the file at the `path`.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Image> {
+    let path = path.as_ref();
+    let bytes =
+        fs::read(path).map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))?;
+
+    decode(&bytes)
+}
+
+/// Save the `image` to the file at the `path` as PNG.
+pub fn save<P: AsRef<Path>>(path: P, image: &Image) -> Result<()> {
+    let path = path.as_ref();
+
+    fs::write(path, encode(image)?)
+        .map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))
+}
+
+/// Encode the `image` as RGBA PNG.
+pub fn encode(image: &Image) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes, image.width, image.height);
+    encoder.set_color(ColorType::Rgba);
+    encoder.set_depth(BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&image.pixels).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+
+    Ok(bytes)
+}
+
+/// Decode PNG image.
+pub fn decode(bytes: &[u8]) -> Result<Image> {
+    let mut decoder = Decoder::new(bytes);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(png_error)?;
+    data.truncate(info.buffer_size());
+
+    let mut image = Image::new(info.width, info.height);
+
+    image.pixels = match info.color_type {
+        ColorType::Rgba => data,
+        ColorType::Rgb => {
+            data.chunks(3)
+                .flat_map(|rgb| vec![rgb[0], rgb[1], rgb[2], 255])
+                .collect()
+        }
+        ColorType::GrayscaleAlpha => {
+            data.chunks(2)
+                .flat_map(|ga| vec![ga[0], ga[0], ga[0], ga[1]])
+                .collect()
+        }
+        ColorType::Grayscale => data.iter().flat_map(|g| vec![*g, *g, *g, 255]).collect(),
+        ColorType::Indexed => {
+            return Err(VmError::Asset(
+                "invalid png: palette hasn't been expanded".to_string(),
+            ))
+        }
+    };
+
+    Ok(image)
+}
+
+fn png_error<E: std::fmt::Display>(err: E) -> VmError {
+    VmError::Asset(format!("invalid png: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use ::png::{BitDepth, ColorType, Encoder};
+
+    use super::{decode, encode};
+    use crate::{error::VmError, rasterizer::Image};
+
+    #[test]
+    fn round_trip() {
+        let mut image = Image::new(5, 3);
+
+        for y in 0..3 {
+            for x in 0..5 {
+                image.set_pixel(x, y, [x as u8 * 50, y as u8 * 100, 7, 255 - x as u8]);
+            }
+        }
+
+        image.set_pixel(4, 2, [1, 2, 3, 4]);
+
+        assert_eq!(decode(&encode(&image).unwrap()).unwrap(), image);
+        assert_eq!(
+            decode(&encode(&Image::new(64, 64)).unwrap()).unwrap(),
+            Image::new(64, 64)
+        );
+    }
+
+    #[test]
+    fn decode_rgb() {
+        let mut png = Vec::new();
+        let mut encoder = Encoder::new(&mut png, 2, 1);
+        encoder.set_color(ColorType::Rgb);
+        encoder.set_depth(BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&[1, 2, 3, 4, 5, 6])
+            .unwrap();
+
+        let image = decode(&png).unwrap();
+        assert_eq!(image.pixel(0, 0), [1, 2, 3, 255]);
+        assert_eq!(image.pixel(1, 0), [4, 5, 6, 255]);
+    }
+
+    #[test]
+    fn decode_invalid_files() {
+        let png = encode(&Image::new(2, 2)).unwrap();
+        let is_asset_error = |result| matches!(result, Err(VmError::Asset(_)));
+
+        assert!(is_asset_error(decode(b"GIF89a")));
+        assert!(is_asset_error(decode(&png[..png.len() / 2])));
+
+        let mut corrupted = png.clone();
+        corrupted[20] ^= 1;
+        assert!(is_asset_error(decode(&corrupted)));
+    }
+}
@@ -0,0 +1,675 @@
+//! Headless software rasterizer.
+//!
+//! [`Rasterizer`] renders [`RenderCommand`]s into an RGBA [`Image`] on the CPU,
+//! so frames can be rendered without the native host, e.g. on CI.
+//!
+//! Matrices map the geometry to the clip space - `x` to the right and `y` up,
+//! both in `[-1, 1]`, the clip space is mapped to the current viewport.
+//! [`crate::transforms::orthographic`] maps the pixel coordinates to the clip space.
+//!
+//! - Quads are the unit square `[0, 1]`, centered quads are `[-0.5, 0.5]`,
+//!   the texture coordinates follow the square - `(0, 0)` is the first pixel of the texture;
+//! - Lines are one pixel wide;
+//! - Colors are blended with the framebuffer by the alpha channel;
+//! - Texts aren't rendered, the rasterizer has no fonts.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use vm::{rasterizer::Rasterizer, render_commands::RenderCommand, transforms};
+//! use vm_math::*;
+//!
+//! let mut rasterizer = Rasterizer::new(4, 4);
+//! rasterizer.render(&[
+//!     RenderCommand::SetColorPipeline {
+//!         color: Vec4f::new(1.0, 0.0, 0.0, 1.0),
+//!     },
+//!     RenderCommand::DrawQuads {
+//!         mvp_matrices: vec![transforms::orthographic(4.0, 4.0) * transforms::scaling(2.0, 2.0)],
+//!     },
+//! ]);
+//!
+//! assert_eq!(rasterizer.framebuffer().pixel(1, 1), [255, 0, 0, 255]);
+//! assert_eq!(rasterizer.framebuffer().pixel(2, 2), [0, 0, 0, 0]);
+//! ```
+
+use std::collections::HashMap;
+
+use vm_math::{Mat4f, Vec2f, Vec4f};
+
+use crate::{
+    error::Result,
+    module::ModuleCommands,
+    render_commands::{read_render_commands, RenderCommand},
+};
+
+/// RGBA image, 4 bytes per pixel, rows go from top to bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    /// Width in pixels.
+    pub width: u32,
+
+    /// Height in pixels.
+    pub height: u32,
+
+    /// Pixels data, `width * height * 4` bytes.
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Create a new transparent image.
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    /// Pixel at `x`, `y`.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let offset = self.offset(x, y);
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[offset..offset + 4]);
+        pixel
+    }
+
+    /// Replace pixel at `x`, `y`.
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let offset = self.offset(x, y);
+        self.pixels[offset..offset + 4].copy_from_slice(&pixel);
+    }
+
+    /// Fill the image with the `color`.
+    pub fn fill(&mut self, color: Vec4f) {
+        let pixel = to_pixel(color);
+
+        for chunk in self.pixels.chunks_mut(4) {
+            chunk.copy_from_slice(&pixel);
+        }
+    }
+
+    fn offset(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height);
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+}
+
+/// Current pipeline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pipeline {
+    Color(Vec4f),
+    Texture(u64),
+}
+
+/// Viewport in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Viewport {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+/// CPU rasterizer of the render commands.
+pub struct Rasterizer {
+    framebuffer: Image,
+    textures: HashMap<u64, Image>,
+    viewport: Viewport,
+    pipeline: Pipeline,
+}
+
+impl Rasterizer {
+    /// Create a new rasterizer with the transparent `width` x `height` framebuffer.
+    pub fn new(width: u32, height: u32) -> Self {
+        Rasterizer {
+            framebuffer: Image::new(width, height),
+            textures: HashMap::new(),
+            viewport: Viewport {
+                x: 0.0,
+                y: 0.0,
+                w: width as f32,
+                h: height as f32,
+            },
+            pipeline: Pipeline::Color(Vec4f::new(1.0, 1.0, 1.0, 1.0)),
+        }
+    }
+
+    /// Rendered frame.
+    pub fn framebuffer(&self) -> &Image {
+        &self.framebuffer
+    }
+
+    /// Add texture with the asset `id` to be used by the texture pipeline.
+    pub fn add_texture(&mut self, id: u64, texture: Image) {
+        self.textures.insert(id, texture);
+    }
+
+    /// Remove texture with the asset `id`.
+    pub fn remove_texture(&mut self, id: u64) -> Option<Image> {
+        self.textures.remove(&id)
+    }
+
+    /// Fill the framebuffer with the `color`.
+    pub fn clear(&mut self, color: Vec4f) {
+        self.framebuffer.fill(color);
+    }
+
+    /// Render all commands from the `commands` buffer, e.g. the client GAPI commands.
+    pub fn render_buffer(&mut self, commands: &ModuleCommands) -> Result<()> {
+        let render_commands = commands.read(read_render_commands)?;
+        self.render(&render_commands);
+        Ok(())
+    }
+
+    /// Render the `render_commands` into the framebuffer.
+    pub fn render(&mut self, render_commands: &[RenderCommand]) {
+        for render_command in render_commands.iter() {
+            match render_command {
+                RenderCommand::SetViewport { x, y, w, h } => {
+                    self.viewport = Viewport {
+                        x: *x as f32,
+                        y: *y as f32,
+                        w: *w as f32,
+                        h: *h as f32,
+                    };
+                }
+                RenderCommand::SetColorPipeline { color } => {
+                    self.pipeline = Pipeline::Color(*color);
+                }
+                RenderCommand::SetTexturePipeline { texture_id } => {
+                    if !self.textures.contains_key(texture_id) {
+                        log::warn!("unknown texture: {}", texture_id);
+                    }
+
+                    self.pipeline = Pipeline::Texture(*texture_id);
+                }
+                RenderCommand::DrawQuads { mvp_matrices } => {
+                    for mvp_matrix in mvp_matrices.iter() {
+                        self.draw_quad(mvp_matrix, 0.0);
+                    }
+                }
+                RenderCommand::DrawCenteredQuads { mvp_matrices } => {
+                    for mvp_matrix in mvp_matrices.iter() {
+                        self.draw_quad(mvp_matrix, -0.5);
+                    }
+                }
+                RenderCommand::DrawLines { mvp_matrix, points } => {
+                    for line in points.chunks_exact(2) {
+                        self.draw_line(mvp_matrix, &line[0], &line[1]);
+                    }
+                }
+                RenderCommand::DrawPath { mvp_matrix, points } => {
+                    for line in points.windows(2) {
+                        self.draw_line(mvp_matrix, &line[0], &line[1]);
+                    }
+                }
+                RenderCommand::DrawTexts { .. } => (),
+                RenderCommand::Other { id, .. } => {
+                    log::debug!("skipped command {:#x}", id);
+                }
+            }
+        }
+    }
+
+    /// Map the point in the model space to the framebuffer.
+    fn project(&self, mvp_matrix: &Mat4f, x: f32, y: f32) -> (f32, f32) {
+        let clip = *mvp_matrix * Vec4f::new(x, y, 0.0, 1.0);
+        let w = if clip.w == 0.0 { 1.0 } else { clip.w };
+
+        (
+            self.viewport.x + (clip.x / w + 1.0) * 0.5 * self.viewport.w,
+            self.viewport.y + (1.0 - clip.y / w) * 0.5 * self.viewport.h,
+        )
+    }
+
+    /// Framebuffer area covered by the viewport - `(x0, y0, x1, y1)`, `x1` and `y1` are exclusive.
+    fn bounds(&self) -> (i64, i64, i64, i64) {
+        let clamp_x = |x: f32| (x.max(0.0) as i64).min(self.framebuffer.width as i64);
+        let clamp_y = |y: f32| (y.max(0.0) as i64).min(self.framebuffer.height as i64);
+
+        (
+            clamp_x(self.viewport.x),
+            clamp_y(self.viewport.y),
+            clamp_x(self.viewport.x + self.viewport.w),
+            clamp_y(self.viewport.y + self.viewport.h),
+        )
+    }
+
+    /// Draw the unit square moved by `offset` and transformed by the `mvp_matrix`.
+    ///
+    /// The square is a parallelogram on the screen, pixel centers are mapped back
+    /// to the square coordinates, the pixel is covered if the coordinates are in `[0, 1)`,
+    /// so adjacent quads don't overlap.
+    fn draw_quad(&mut self, mvp_matrix: &Mat4f, offset: f32) {
+        let origin = self.project(mvp_matrix, offset, offset);
+        let right = self.project(mvp_matrix, offset + 1.0, offset);
+        let bottom = self.project(mvp_matrix, offset, offset + 1.0);
+        let u_axis = (right.0 - origin.0, right.1 - origin.1);
+        let v_axis = (bottom.0 - origin.0, bottom.1 - origin.1);
+        let det = u_axis.0 * v_axis.1 - u_axis.1 * v_axis.0;
+
+        if det == 0.0 || !det.is_finite() {
+            return;
+        }
+
+        let corners = [
+            origin,
+            right,
+            bottom,
+            (right.0 + v_axis.0, right.1 + v_axis.1),
+        ];
+        let (x0, y0, x1, y1) = self.bounds();
+        let min_x = corners.iter().map(|c| c.0).fold(f32::INFINITY, f32::min);
+        let max_x = corners
+            .iter()
+            .map(|c| c.0)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let min_y = corners.iter().map(|c| c.1).fold(f32::INFINITY, f32::min);
+        let max_y = corners
+            .iter()
+            .map(|c| c.1)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        let x0 = x0.max(min_x.floor() as i64);
+        let x1 = x1.min(max_x.ceil() as i64);
+        let y0 = y0.max(min_y.floor() as i64);
+        let y1 = y1.min(max_y.ceil() as i64);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let dx = x as f32 + 0.5 - origin.0;
+                let dy = y as f32 + 0.5 - origin.1;
+                let u = (dx * v_axis.1 - dy * v_axis.0) / det;
+                let v = (u_axis.0 * dy - u_axis.1 * dx) / det;
+
+                if (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v) {
+                    if let Some(color) = self.shade(u, v) {
+                        self.blend(x as u32, y as u32, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw the line from `a` to `b` transformed by the `mvp_matrix`, one pixel per
+    /// column or row along the major axis whose center the line passes.
+    fn draw_line(&mut self, mvp_matrix: &Mat4f, a: &Vec2f, b: &Vec2f) {
+        let from = self.project(mvp_matrix, a.x, a.y);
+        let to = self.project(mvp_matrix, b.x, b.y);
+        let (from, to) = ((from.0 as f64, from.1 as f64), (to.0 as f64, to.1 as f64));
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let (x0, y0, x1, y1) = self.bounds();
+
+        if dx == 0.0 && dy == 0.0 {
+            self.plot(from.0.floor() as i64, from.1.floor() as i64, 0.0);
+            return;
+        }
+
+        // Only the visible part of the line is stepped, so far off-screen
+        // points don't cost more than the on-screen ones.
+        let rect = (x0 as f64, y0 as f64, x1 as f64, y1 as f64);
+        let (t0, t1) = match clip_segment(from, to, rect) {
+            Some(range) => range,
+            None => return,
+        };
+
+        let horizontal = dx.abs() >= dy.abs();
+        let (start, delta) = if horizontal {
+            (from.0, dx)
+        }
+        else {
+            (from.1, dy)
+        };
+        let (a, b) = (start + delta * t0, start + delta * t1);
+        let first = (a.min(b) - 0.5).ceil() as i64;
+        let last = (a.max(b) - 0.5).floor() as i64;
+
+        for major in first..=last {
+            let t = (major as f64 + 0.5 - start) / delta;
+
+            if horizontal {
+                self.plot(major, (from.1 + dy * t).floor() as i64, t as f32);
+            }
+            else {
+                self.plot((from.0 + dx * t).floor() as i64, major, t as f32);
+            }
+        }
+    }
+
+    /// Blend the pixel of the line at the segment parameter `t` if it's in the viewport.
+    fn plot(&mut self, x: i64, y: i64, t: f32) {
+        let (x0, y0, x1, y1) = self.bounds();
+
+        if x >= x0 && x < x1 && y >= y0 && y < y1 {
+            if let Some(color) = self.shade(t, 0.0) {
+                self.blend(x as u32, y as u32, color);
+            }
+        }
+    }
+
+    /// Color of the current pipeline at the texture coordinates `u`, `v`.
+    fn shade(&self, u: f32, v: f32) -> Option<Vec4f> {
+        match self.pipeline {
+            Pipeline::Color(color) => Some(color),
+            Pipeline::Texture(texture_id) => {
+                let texture = self.textures.get(&texture_id)?;
+
+                if texture.width == 0 || texture.height == 0 {
+                    return None;
+                }
+
+                let x = ((u * texture.width as f32) as u32).min(texture.width - 1);
+                let y = ((v * texture.height as f32) as u32).min(texture.height - 1);
+                let [r, g, b, a] = texture.pixel(x, y);
+
+                Some(Vec4f::new(
+                    r as f32 / 255.0,
+                    g as f32 / 255.0,
+                    b as f32 / 255.0,
+                    a as f32 / 255.0,
+                ))
+            }
+        }
+    }
+
+    /// Blend the `color` with the framebuffer pixel - source over.
+    fn blend(&mut self, x: u32, y: u32, color: Vec4f) {
+        let [r, g, b, a] = self.framebuffer.pixel(x, y);
+        let alpha = color.w.clamp(0.0, 1.0);
+        let mix = |src: f32, dst: u8| src * alpha + dst as f32 / 255.0 * (1.0 - alpha);
+
+        let blended = Vec4f::new(
+            mix(color.x, r),
+            mix(color.y, g),
+            mix(color.z, b),
+            alpha + a as f32 / 255.0 * (1.0 - alpha),
+        );
+
+        self.framebuffer.set_pixel(x, y, to_pixel(blended));
+    }
+}
+
+/// Convert the `color` with components in `[0, 1]` to the RGBA pixel.
+fn to_pixel(color: Vec4f) -> [u8; 4] {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    [
+        channel(color.x),
+        channel(color.y),
+        channel(color.z),
+        channel(color.w),
+    ]
+}
+
+/// Clip the segment `from` - `to` to the `(x0, y0, x1, y1)` rect with
+/// the Liang-Barsky algorithm, returns the segment parameters of the visible part.
+fn clip_segment(
+    from: (f64, f64),
+    to: (f64, f64),
+    (x0, y0, x1, y1): (f64, f64, f64, f64),
+) -> Option<(f64, f64)> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let edges = [
+        (-dx, from.0 - x0),
+        (dx, x1 - from.0),
+        (-dy, from.1 - y0),
+        (dy, y1 - from.1),
+    ];
+    let (mut t0, mut t1) = (0.0, 1.0);
+
+    for (p, q) in edges.iter() {
+        if !p.is_finite() || !q.is_finite() {
+            return None;
+        }
+
+        if *p == 0.0 {
+            if *q < 0.0 {
+                return None;
+            }
+        }
+        else if *p < 0.0 {
+            t0 = f64::max(t0, q / p);
+        }
+        else {
+            t1 = f64::min(t1, q / p);
+        }
+    }
+
+    if t0 > t1 {
+        None
+    }
+    else {
+        Some((t0, t1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_math::{Mat4f, Vec2f, Vec4f};
+
+    use super::{Image, Rasterizer};
+    use crate::{
+        gapi::{self, GApiContext},
+        module::{create_commands_bus, ModuleOptions},
+        render_commands::RenderCommand,
+        transforms::{orthographic, rotation, scaling, translation},
+    };
+
+    const ADDRESS: &str = "tech.paws.tests.rasterizer";
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const EMPTY: [u8; 4] = [0, 0, 0, 0];
+
+    /// Rect in pixels.
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> Mat4f {
+        orthographic(8.0, 8.0) * translation(x, y) * scaling(w, h)
+    }
+
+    fn color(color: [u8; 4]) -> RenderCommand {
+        let channel = |value: u8| value as f32 / 255.0;
+
+        RenderCommand::SetColorPipeline {
+            color: Vec4f::new(
+                channel(color[0]),
+                channel(color[1]),
+                channel(color[2]),
+                channel(color[3]),
+            ),
+        }
+    }
+
+    /// Covered pixels, `#` is a covered pixel.
+    fn coverage(image: &Image) -> Vec<String> {
+        (0..image.height)
+            .map(|y| {
+                (0..image.width)
+                    .map(|x| {
+                        if image.pixel(x, y) == EMPTY {
+                            '.'
+                        }
+                        else {
+                            '#'
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn draw_quads() {
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.render(&[
+            color(RED),
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![rect(1.0, 1.0, 3.0, 2.0), rect(4.0, 1.0, 1.0, 2.0)],
+            },
+            color(BLUE),
+            RenderCommand::DrawCenteredQuads {
+                mvp_matrices: vec![rect(6.0, 6.0, 2.0, 2.0)],
+            },
+        ]);
+
+        let framebuffer = rasterizer.framebuffer();
+        assert_eq!(
+            coverage(framebuffer),
+            vec![
+                "........", ".####...", ".####...", "........", "........", ".....##.", ".....##.",
+                "........",
+            ]
+        );
+        assert_eq!(framebuffer.pixel(4, 2), RED);
+        assert_eq!(framebuffer.pixel(6, 6), BLUE);
+    }
+
+    #[test]
+    fn rotated_quad() {
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.render(&[
+            color(RED),
+            RenderCommand::DrawCenteredQuads {
+                mvp_matrices: vec![
+                    orthographic(8.0, 8.0)
+                        * translation(4.0, 4.0)
+                        * rotation(std::f32::consts::FRAC_PI_4)
+                        * scaling(4.0, 4.0),
+                ],
+            },
+        ]);
+
+        let framebuffer = rasterizer.framebuffer();
+        assert_eq!(framebuffer.pixel(4, 4), RED);
+        assert_eq!(framebuffer.pixel(4, 2), RED);
+        assert_eq!(framebuffer.pixel(1, 1), EMPTY);
+        assert_eq!(framebuffer.pixel(6, 6), EMPTY);
+    }
+
+    #[test]
+    fn viewport_and_blending() {
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.clear(Vec4f::new(0.0, 0.0, 1.0, 1.0));
+        rasterizer.render(&[
+            RenderCommand::SetViewport {
+                x: 4,
+                y: 0,
+                w: 4,
+                h: 4,
+            },
+            RenderCommand::SetColorPipeline {
+                color: Vec4f::new(1.0, 0.0, 0.0, 0.5),
+            },
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![translation(-1.0, -1.0) * scaling(4.0, 4.0)],
+            },
+        ]);
+
+        let framebuffer = rasterizer.framebuffer();
+        assert_eq!(framebuffer.pixel(4, 0), [128, 0, 128, 255]);
+        assert_eq!(framebuffer.pixel(7, 3), [128, 0, 128, 255]);
+        assert_eq!(framebuffer.pixel(3, 0), BLUE);
+        assert_eq!(framebuffer.pixel(4, 4), BLUE);
+    }
+
+    #[test]
+    fn draw_lines_and_path() {
+        let mut rasterizer = Rasterizer::new(8, 8);
+        let projection = orthographic(8.0, 8.0);
+        rasterizer.render(&[
+            color(RED),
+            RenderCommand::DrawLines {
+                mvp_matrix: projection,
+                points: vec![Vec2f::new(0.5, 0.5), Vec2f::new(7.5, 0.5)],
+            },
+            RenderCommand::DrawPath {
+                mvp_matrix: projection,
+                points: vec![
+                    Vec2f::new(0.5, 2.5),
+                    Vec2f::new(0.5, 7.5),
+                    Vec2f::new(5.5, 7.5),
+                ],
+            },
+        ]);
+
+        assert_eq!(
+            coverage(rasterizer.framebuffer()),
+            vec![
+                "########", "........", "#.......", "#.......", "#.......", "#.......", "#.......",
+                "######..",
+            ]
+        );
+    }
+
+    #[test]
+    fn clip_off_screen_lines() {
+        let mut rasterizer = Rasterizer::new(8, 8);
+        let projection = orthographic(8.0, 8.0);
+        rasterizer.render(&[
+            color(RED),
+            RenderCommand::DrawLines {
+                mvp_matrix: projection,
+                points: vec![
+                    Vec2f::new(-1e9, 1.5),
+                    Vec2f::new(1e9, 1.5),
+                    Vec2f::new(4.5, -1e9),
+                    Vec2f::new(4.5, 1e9),
+                    Vec2f::new(-1e9, -1e9),
+                    Vec2f::new(1e9, -1e9),
+                ],
+            },
+        ]);
+
+        assert_eq!(
+            coverage(rasterizer.framebuffer()),
+            vec![
+                "....#...", "########", "....#...", "....#...", "....#...", "....#...", "....#...",
+                "....#...",
+            ]
+        );
+    }
+
+    #[test]
+    fn textured_quad() {
+        let mut texture = Image::new(2, 2);
+        texture.set_pixel(0, 0, RED);
+        texture.set_pixel(1, 1, BLUE);
+
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.add_texture(1, texture);
+        rasterizer.render(&[
+            RenderCommand::SetTexturePipeline { texture_id: 1 },
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![rect(0.0, 0.0, 4.0, 4.0)],
+            },
+            RenderCommand::SetTexturePipeline { texture_id: 2 },
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![rect(4.0, 4.0, 4.0, 4.0)],
+            },
+        ]);
+
+        let framebuffer = rasterizer.framebuffer();
+        assert_eq!(framebuffer.pixel(0, 0), RED);
+        assert_eq!(framebuffer.pixel(1, 1), RED);
+        assert_eq!(framebuffer.pixel(2, 0), EMPTY);
+        assert_eq!(framebuffer.pixel(3, 3), BLUE);
+        assert_eq!(framebuffer.pixel(5, 5), EMPTY);
+    }
+
+    #[test]
+    fn render_gapi_buffer() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::set_color_pipeline(&context, Vec4f::new(1.0, 0.0, 0.0, 1.0)).unwrap();
+        gapi::draw_quads(&context, &[rect(0.0, 0.0, 2.0, 2.0)]).unwrap();
+
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.render_buffer(&commands).unwrap();
+
+        assert_eq!(rasterizer.framebuffer().pixel(1, 1), RED);
+        assert_eq!(rasterizer.framebuffer().pixel(2, 2), EMPTY);
+    }
+}
@@ -0,0 +1,698 @@
+//! Render commands.
+//!
+//! [`RenderCommand`] is the canonical definition of the GAPI commands payloads:
+//! [`crate::gapi`] functions encode commands with the same `write_*` functions
+//! and backends decode the client commands buffer with [`read_render_commands`].
+//!
+//! # Examples
+//!
+//! ```rust
+//! use vm::{commands::Source, gapi, module, render_commands, Vm};
+//! use vm_math::*;
+//!
+//! let mut vm = Vm::new();
+//! let mut commands_bus = vm.commands_bus();
+//!
+//! let gapi_context = gapi::GApiContext {
+//!     from: "my_module_id",
+//!     address: module::CLIENT_ID,
+//!     commands_bus: &mut commands_bus,
+//! };
+//!
+//! gapi::set_color_pipeline(&gapi_context, Vec4f::new(1.0, 1.0, 0.0, 1.0))?;
+//! gapi::draw_quads(&gapi_context, &[Mat4f::IDENT])?;
+//!
+//! let render_commands = vm
+//!     .state()
+//!     .router
+//!     .commands(module::CLIENT_ID, Source::GAPI)?
+//!     .read(render_commands::read_render_commands)?;
+//!
+//! assert_eq!(render_commands.len(), 2);
+//! # Ok::<(), vm::error::VmError>(())
+//! ```
+
+use std::slice;
+
+use vm_buffers::{ByteOrder, BytesReader, BytesWriter, IntoVMBuffers};
+use vm_math::{Mat4f, Vec2f, Vec4f};
+use vm_memory::{BufferAccessor, RegionAllocator};
+
+use crate::{
+    commands::{self, Source},
+    commands_reader::{Command, CommandsReader, ReadError},
+    error::{Result, VmError},
+    gapi::{GApiContext, TextData},
+};
+
+/// Size of the encoded [`Mat4f`] in bytes.
+pub const MAT4F_SIZE: u64 = 64;
+
+/// Size of the encoded [`Vec2f`] in bytes.
+pub const VEC2F_SIZE: u64 = 8;
+
+/// Size of the encoded [`Vec4f`] in bytes.
+pub const VEC4F_SIZE: u64 = 16;
+
+/// Decoded GAPI command.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderCommand {
+    /// See [`crate::gapi::set_viewport`].
+    SetViewport {
+        /// Viewport x.
+        x: u32,
+        /// Viewport y.
+        y: u32,
+        /// Viewport width.
+        w: u32,
+        /// Viewport height.
+        h: u32,
+    },
+    /// See [`crate::gapi::set_color_pipeline`].
+    SetColorPipeline {
+        /// Color of the objects.
+        color: Vec4f,
+    },
+    /// See [`crate::gapi::set_texture_pipeline`].
+    SetTexturePipeline {
+        /// Texture asset id.
+        texture_id: u64,
+    },
+    /// See [`crate::gapi::draw_quads`].
+    DrawQuads {
+        /// Transformation matrix of every quad.
+        mvp_matrices: Vec<Mat4f>,
+    },
+    /// See [`crate::gapi::draw_centered_quads`].
+    DrawCenteredQuads {
+        /// Transformation matrix of every quad.
+        mvp_matrices: Vec<Mat4f>,
+    },
+    /// See [`crate::gapi::draw_lines`].
+    DrawLines {
+        /// Transformation matrix of the lines.
+        mvp_matrix: Mat4f,
+        /// Every two points describe a line.
+        points: Vec<Vec2f>,
+    },
+    /// See [`crate::gapi::draw_path`].
+    DrawPath {
+        /// Transformation matrix of the path.
+        mvp_matrix: Mat4f,
+        /// Points of the path.
+        points: Vec<Vec2f>,
+    },
+    /// See [`crate::gapi::draw_texts`].
+    DrawTexts {
+        /// Address of the module that has sent the texts.
+        from: String,
+        /// Texts to render.
+        texts: Vec<TextData>,
+    },
+    /// Command that isn't a render command, kept as is.
+    Other {
+        /// Command id.
+        id: u64,
+        /// Encoded command payload.
+        payload: Vec<u8>,
+    },
+}
+
+impl RenderCommand {
+    /// Command id.
+    pub fn id(&self) -> u64 {
+        match self {
+            RenderCommand::SetViewport { .. } => commands::gapi::SET_VIEWPORT,
+            RenderCommand::SetColorPipeline { .. } => commands::gapi::SET_COLOR_PIPELINE,
+            RenderCommand::SetTexturePipeline { .. } => commands::gapi::SET_TEXTURE_PIPELINE,
+            RenderCommand::DrawQuads { .. } => commands::gapi::DRAW_QUADS,
+            RenderCommand::DrawCenteredQuads { .. } => commands::gapi::DRAW_CENTERED_QUADS,
+            RenderCommand::DrawLines { .. } => commands::gapi::DRAW_LINES,
+            RenderCommand::DrawPath { .. } => commands::gapi::DRAW_PATH,
+            RenderCommand::DrawTexts { .. } => commands::gapi::DRAW_TEXTS,
+            RenderCommand::Other { id, .. } => *id,
+        }
+    }
+
+    /// Size of the encoded payload in bytes.
+    pub fn encoded_size(&self) -> u64 {
+        match self {
+            RenderCommand::SetViewport { .. } => 16,
+            RenderCommand::SetColorPipeline { .. } => VEC4F_SIZE,
+            RenderCommand::SetTexturePipeline { .. } => 8,
+            RenderCommand::DrawQuads { mvp_matrices }
+            | RenderCommand::DrawCenteredQuads { mvp_matrices } => matrices_size(mvp_matrices),
+            RenderCommand::DrawLines { points, .. } | RenderCommand::DrawPath { points, .. } => {
+                lines_size(points)
+            }
+            RenderCommand::DrawTexts { from, texts } => texts_size(from, texts),
+            RenderCommand::Other { payload, .. } => payload.len() as u64,
+        }
+    }
+
+    /// Encode the command payload.
+    pub fn write_to_buffers(&self, bytes_writer: &mut BytesWriter) {
+        match self {
+            RenderCommand::SetViewport { x, y, w, h } => {
+                write_viewport(bytes_writer, *x, *y, *w, *h)
+            }
+            RenderCommand::SetColorPipeline { color } => color.write_to_buffers(bytes_writer),
+            RenderCommand::SetTexturePipeline { texture_id } => bytes_writer.write_u64(*texture_id),
+            RenderCommand::DrawQuads { mvp_matrices }
+            | RenderCommand::DrawCenteredQuads { mvp_matrices } => {
+                write_matrices(bytes_writer, mvp_matrices)
+            }
+            RenderCommand::DrawLines { mvp_matrix, points }
+            | RenderCommand::DrawPath { mvp_matrix, points } => {
+                write_lines(bytes_writer, mvp_matrix, points)
+            }
+            RenderCommand::DrawTexts { from, texts } => write_texts(bytes_writer, from, texts),
+            RenderCommand::Other { payload, .. } => {
+                for byte in payload.iter() {
+                    bytes_writer.write_byte(*byte);
+                }
+            }
+        }
+    }
+
+    /// Decode the command, the payload is validated against the command length.
+    pub fn read(command: Command) -> Result<Self> {
+        RenderCommand::decode(command.id, command.bytes_reader, command.len)
+    }
+
+    /// Decode the command with the `id` and the payload of `len` bytes from `bytes_reader`.
+    pub fn decode(id: u64, bytes_reader: &mut BytesReader, len: u64) -> Result<Self> {
+        let mut payload = PayloadReader::new(bytes_reader, len);
+
+        let render_command = match id {
+            commands::gapi::SET_VIEWPORT => {
+                RenderCommand::SetViewport {
+                    x: payload.read_u32()?,
+                    y: payload.read_u32()?,
+                    w: payload.read_u32()?,
+                    h: payload.read_u32()?,
+                }
+            }
+            commands::gapi::SET_COLOR_PIPELINE => {
+                RenderCommand::SetColorPipeline {
+                    color: payload.read_vec4f()?,
+                }
+            }
+            commands::gapi::SET_TEXTURE_PIPELINE => {
+                RenderCommand::SetTexturePipeline {
+                    texture_id: payload.read_u64()?,
+                }
+            }
+            commands::gapi::DRAW_QUADS => {
+                RenderCommand::DrawQuads {
+                    mvp_matrices: payload.read_matrices()?,
+                }
+            }
+            commands::gapi::DRAW_CENTERED_QUADS => {
+                RenderCommand::DrawCenteredQuads {
+                    mvp_matrices: payload.read_matrices()?,
+                }
+            }
+            commands::gapi::DRAW_LINES => {
+                RenderCommand::DrawLines {
+                    mvp_matrix: payload.read_mat4f()?,
+                    points: payload.read_points()?,
+                }
+            }
+            commands::gapi::DRAW_PATH => {
+                RenderCommand::DrawPath {
+                    mvp_matrix: payload.read_mat4f()?,
+                    points: payload.read_points()?,
+                }
+            }
+            commands::gapi::DRAW_TEXTS => {
+                let from = payload.read_string()?;
+                let count = payload.read_u64()?;
+                let mut texts = Vec::new();
+
+                for _ in 0..count {
+                    texts.push(payload.read_text_data()?);
+                }
+
+                RenderCommand::DrawTexts { from, texts }
+            }
+            id => {
+                RenderCommand::Other {
+                    id,
+                    payload: payload.read_bytes(len)?,
+                }
+            }
+        };
+
+        Ok(render_command)
+    }
+
+    /// Decode the command with the `id` from the `payload` encoded with `byte_order`.
+    pub fn from_bytes(id: u64, payload: &[u8], byte_order: ByteOrder) -> Result<Self> {
+        let allocator = allocator_from_bytes(payload, byte_order);
+        let mut bytes_reader = BytesReader::new(byte_order, &allocator);
+
+        RenderCommand::decode(id, &mut bytes_reader, payload.len() as u64)
+    }
+
+    /// Encode the command payload with `byte_order`.
+    pub fn to_bytes(&self, byte_order: ByteOrder) -> Vec<u8> {
+        encode(byte_order, self.encoded_size(), |bytes_writer| {
+            self.write_to_buffers(bytes_writer);
+        })
+    }
+
+    /// Send the command the same way as the corresponding [`crate::gapi`] function.
+    pub fn push(&self, context: &GApiContext) -> Result<()> {
+        context.commands_bus.push_sized_command(
+            context.address,
+            self.id(),
+            Source::GAPI,
+            self.encoded_size(),
+            |bytes_writer| self.write_to_buffers(bytes_writer),
+        )
+    }
+
+    /// Multiply the matrices of the draw command by the `transform` - `transform * matrix`.
+    ///
+    /// Returns `false` if the command has no matrices.
+    pub fn transform(&mut self, transform: &Mat4f) -> bool {
+        self.map_matrices(|matrix| *transform * matrix)
+    }
+
+    /// Multiply the matrices of the draw command by the `transform` in the model
+    /// space of the command - `matrix * transform`.
+    ///
+    /// Returns `false` if the command has no matrices.
+    pub fn transform_model(&mut self, transform: &Mat4f) -> bool {
+        self.map_matrices(|matrix| matrix * *transform)
+    }
+
+    fn map_matrices<F>(&mut self, map: F) -> bool
+    where
+        F: Fn(Mat4f) -> Mat4f,
+    {
+        match self {
+            RenderCommand::DrawQuads { mvp_matrices }
+            | RenderCommand::DrawCenteredQuads { mvp_matrices } => {
+                for matrix in mvp_matrices.iter_mut() {
+                    *matrix = map(*matrix);
+                }
+            }
+            RenderCommand::DrawLines { mvp_matrix, .. }
+            | RenderCommand::DrawPath { mvp_matrix, .. } => {
+                *mvp_matrix = map(*mvp_matrix);
+            }
+            RenderCommand::DrawTexts { texts, .. } => {
+                for text in texts.iter_mut() {
+                    text.mvp_matrix = map(text.mvp_matrix);
+                }
+            }
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Decode all commands of the commands buffer.
+pub fn read_render_commands(commands_reader: &mut CommandsReader) -> Result<Vec<RenderCommand>> {
+    let mut render_commands = Vec::new();
+
+    while let Some(command) = commands_reader.next()? {
+        render_commands.push(RenderCommand::read(command)?);
+    }
+
+    Ok(render_commands)
+}
+
+/// Encode [`commands::gapi::SET_VIEWPORT`] payload.
+pub fn write_viewport(bytes_writer: &mut BytesWriter, x: u32, y: u32, w: u32, h: u32) {
+    bytes_writer.write_u32(x);
+    bytes_writer.write_u32(y);
+    bytes_writer.write_u32(w);
+    bytes_writer.write_u32(h);
+}
+
+/// Size of the encoded quads payload in bytes.
+pub fn matrices_size(mvp_matrices: &[Mat4f]) -> u64 {
+    8 + MAT4F_SIZE * mvp_matrices.len() as u64
+}
+
+/// Encode [`commands::gapi::DRAW_QUADS`] and [`commands::gapi::DRAW_CENTERED_QUADS`] payload.
+pub fn write_matrices(bytes_writer: &mut BytesWriter, mvp_matrices: &[Mat4f]) {
+    bytes_writer.write_u64(mvp_matrices.len() as u64);
+
+    for mat in mvp_matrices.iter() {
+        mat.write_to_buffers(bytes_writer);
+    }
+}
+
+/// Size of the encoded lines payload in bytes.
+pub fn lines_size(points: &[Vec2f]) -> u64 {
+    MAT4F_SIZE + 8 + VEC2F_SIZE * points.len() as u64
+}
+
+/// Encode [`commands::gapi::DRAW_LINES`] and [`commands::gapi::DRAW_PATH`] payload.
+pub fn write_lines(bytes_writer: &mut BytesWriter, mvp_matrix: &Mat4f, points: &[Vec2f]) {
+    mvp_matrix.write_to_buffers(bytes_writer);
+    bytes_writer.write_u64(points.len() as u64);
+
+    for point in points.iter() {
+        point.write_to_buffers(bytes_writer);
+    }
+}
+
+/// Size of the encoded texts payload in bytes.
+pub fn texts_size(from: &str, texts: &[TextData]) -> u64 {
+    8 + from.len() as u64 + 8 + texts.iter().map(TextData::encoded_size).sum::<u64>()
+}
+
+/// Encode [`commands::gapi::DRAW_TEXTS`] payload.
+pub fn write_texts(bytes_writer: &mut BytesWriter, from: &str, texts: &[TextData]) {
+    from.to_string().write_to_buffers(bytes_writer);
+    bytes_writer.write_u64(texts.len() as u64);
+
+    for text in texts.iter() {
+        text.write_to_buffers(bytes_writer);
+    }
+}
+
+/// Reads the command payload, every read is checked against the payload length.
+pub(crate) struct PayloadReader<'a> {
+    bytes_reader: &'a mut BytesReader,
+    remaining: u64,
+}
+
+impl<'a> PayloadReader<'a> {
+    /// Create a reader of the payload of `len` bytes.
+    pub(crate) fn new(bytes_reader: &'a mut BytesReader, len: u64) -> Self {
+        PayloadReader {
+            bytes_reader,
+            remaining: len,
+        }
+    }
+
+    fn take(&mut self, size: u64) -> Result<()> {
+        if size > self.remaining {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        self.remaining -= size;
+        Ok(())
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        self.take(4)?;
+        Ok(self.bytes_reader.read_u32())
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        self.take(8)?;
+        Ok(self.bytes_reader.read_u64())
+    }
+
+    fn read_vec4f(&mut self) -> Result<Vec4f> {
+        self.take(VEC4F_SIZE)?;
+        Ok(Vec4f::read_from_buffers(self.bytes_reader))
+    }
+
+    fn read_mat4f(&mut self) -> Result<Mat4f> {
+        self.take(MAT4F_SIZE)?;
+        Ok(Mat4f::read_from_buffers(self.bytes_reader))
+    }
+
+    fn read_bytes(&mut self, len: u64) -> Result<Vec<u8>> {
+        self.take(len)?;
+        Ok((0..len).map(|_| self.bytes_reader.read_byte()).collect())
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let len = self.read_u64()?;
+        String::from_utf8(self.read_bytes(len)?).map_err(|_| VmError::InvalidUtf8)
+    }
+
+    pub(crate) fn read_text_data(&mut self) -> Result<TextData> {
+        Ok(TextData {
+            font_id: self.read_u64()?,
+            font_size: self.read_u32()?,
+            mvp_matrix: self.read_mat4f()?,
+            text: self.read_string()?,
+        })
+    }
+
+    fn read_matrices(&mut self) -> Result<Vec<Mat4f>> {
+        let count = self.read_u64()?;
+
+        if count > self.remaining / MAT4F_SIZE {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        (0..count).map(|_| self.read_mat4f()).collect()
+    }
+
+    fn read_points(&mut self) -> Result<Vec<Vec2f>> {
+        let count = self.read_u64()?;
+
+        if count > self.remaining / VEC2F_SIZE {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        self.take(count * VEC2F_SIZE)?;
+        Ok((0..count)
+            .map(|_| Vec2f::read_from_buffers(self.bytes_reader))
+            .collect())
+    }
+}
+
+/// Create allocator that contains a copy of the `bytes`.
+pub(crate) fn allocator_from_bytes(bytes: &[u8], byte_order: ByteOrder) -> RegionAllocator {
+    let allocator = RegionAllocator::new(bytes.len().max(1));
+    let mut bytes_writer = BytesWriter::new(byte_order, &allocator);
+
+    for byte in bytes.iter() {
+        bytes_writer.write_byte(*byte);
+    }
+
+    allocator
+}
+
+/// Encode the payload of `size` bytes with the `payload_writer`.
+pub(crate) fn encode<F>(byte_order: ByteOrder, size: u64, payload_writer: F) -> Vec<u8>
+where
+    F: FnOnce(&mut BytesWriter),
+{
+    let allocator = RegionAllocator::new(size.max(1) as usize);
+    let mut bytes_writer = BytesWriter::new(byte_order, &allocator);
+    payload_writer(&mut bytes_writer);
+
+    let size = bytes_writer.current_offset() as usize;
+    unsafe { slice::from_raw_parts(allocator.get_buffer_ptr(), size) }.to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_buffers::{ByteOrder, BytesReader, IntoVMBuffers};
+    use vm_math::{Mat4f, Vec2f, Vec4f};
+
+    use super::{allocator_from_bytes, encode, read_render_commands, RenderCommand};
+    use crate::{
+        commands,
+        commands_reader::ReadError,
+        error::VmError,
+        gapi::{self, GApiContext, TextData},
+        module::{create_commands_bus, ModuleOptions},
+        transforms::{scaling, translation},
+    };
+
+    const ADDRESS: &str = "tech.paws.tests.render_commands";
+
+    fn texts() -> Vec<TextData> {
+        vec![TextData {
+            font_id: 1,
+            font_size: 14,
+            mvp_matrix: translation(1.0, 2.0),
+            text: "Hello".to_string(),
+        }]
+    }
+
+    fn expected_commands() -> Vec<RenderCommand> {
+        let points = vec![Vec2f::new(0.0, 0.0), Vec2f::new(10.0, 20.0)];
+
+        vec![
+            RenderCommand::SetViewport {
+                x: 0,
+                y: 0,
+                w: 640,
+                h: 480,
+            },
+            RenderCommand::SetColorPipeline {
+                color: Vec4f::new(1.0, 0.0, 0.0, 1.0),
+            },
+            RenderCommand::SetTexturePipeline { texture_id: 42 },
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![Mat4f::IDENT, translation(10.0, 0.0)],
+            },
+            RenderCommand::DrawCenteredQuads {
+                mvp_matrices: vec![translation(0.0, 10.0)],
+            },
+            RenderCommand::DrawLines {
+                mvp_matrix: Mat4f::IDENT,
+                points: points.clone(),
+            },
+            RenderCommand::DrawPath {
+                mvp_matrix: translation(5.0, 5.0),
+                points,
+            },
+            RenderCommand::DrawTexts {
+                from: ADDRESS.to_string(),
+                texts: texts(),
+            },
+        ]
+    }
+
+    #[test]
+    fn decode_gapi_commands() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+        let points = [Vec2f::new(0.0, 0.0), Vec2f::new(10.0, 20.0)];
+
+        gapi::set_viewport(&context, 0, 0, 640, 480).unwrap();
+        gapi::set_color_pipeline(&context, Vec4f::new(1.0, 0.0, 0.0, 1.0)).unwrap();
+        gapi::set_texture_pipeline(&context, 42).unwrap();
+        gapi::draw_quads(&context, &[Mat4f::IDENT, translation(10.0, 0.0)]).unwrap();
+        gapi::draw_centered_quads(&context, &[translation(0.0, 10.0)]).unwrap();
+        gapi::draw_lines(&context, &Mat4f::IDENT, &points).unwrap();
+        gapi::draw_path(&context, &translation(5.0, 5.0), &points).unwrap();
+        gapi::draw_texts(&context, &texts()).unwrap();
+
+        assert_eq!(
+            commands.read(read_render_commands).unwrap(),
+            expected_commands()
+        );
+    }
+
+    #[test]
+    fn encode_render_commands() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+        let mut expected = expected_commands();
+        expected.push(RenderCommand::Other {
+            id: commands::assets::LOAD_TEXTURE,
+            payload: vec![1, 2, 3],
+        });
+
+        for render_command in expected.iter() {
+            render_command.push(&context).unwrap();
+
+            for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian].iter() {
+                let bytes = render_command.to_bytes(*byte_order);
+
+                assert_eq!(bytes.len() as u64, render_command.encoded_size());
+                assert_eq!(
+                    &RenderCommand::from_bytes(render_command.id(), &bytes, *byte_order).unwrap(),
+                    render_command
+                );
+            }
+        }
+
+        assert_eq!(commands.read(read_render_commands).unwrap(), expected);
+    }
+
+    #[test]
+    fn transform_render_commands() {
+        let mut render_command = RenderCommand::DrawQuads {
+            mvp_matrices: vec![Mat4f::IDENT],
+        };
+        assert!(render_command.transform(&translation(1.0, 2.0)));
+        assert_eq!(
+            render_command,
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![translation(1.0, 2.0)],
+            }
+        );
+
+        let mut render_command = RenderCommand::DrawLines {
+            mvp_matrix: scaling(2.0, 2.0),
+            points: vec![],
+        };
+        assert!(render_command.transform_model(&translation(1.0, 2.0)));
+        assert_eq!(
+            render_command,
+            RenderCommand::DrawLines {
+                mvp_matrix: scaling(2.0, 2.0) * translation(1.0, 2.0),
+                points: vec![],
+            }
+        );
+
+        let mut render_command = RenderCommand::SetTexturePipeline { texture_id: 1 };
+        assert!(!render_command.transform(&translation(1.0, 2.0)));
+        assert!(!render_command.transform_model(&translation(1.0, 2.0)));
+    }
+
+    #[test]
+    fn truncated_payload() {
+        let quads = RenderCommand::DrawQuads {
+            mvp_matrices: vec![Mat4f::IDENT],
+        }
+        .to_bytes(ByteOrder::LittleEndian);
+
+        assert_eq!(
+            RenderCommand::from_bytes(
+                commands::gapi::DRAW_QUADS,
+                &quads[..quads.len() - 1],
+                ByteOrder::LittleEndian
+            )
+            .err(),
+            Some(VmError::from(ReadError::TruncatedCommand))
+        );
+        assert_eq!(
+            RenderCommand::from_bytes(
+                commands::gapi::SET_VIEWPORT,
+                &[0; 12],
+                ByteOrder::LittleEndian
+            )
+            .err(),
+            Some(VmError::from(ReadError::TruncatedCommand))
+        );
+    }
+
+    #[test]
+    fn truncated_text_data() {
+        let text = texts().remove(0);
+        let bytes = encode(ByteOrder::BigEndian, text.encoded_size(), |bytes_writer| {
+            text.write_to_buffers(bytes_writer)
+        });
+        let read = |bytes: &[u8], len: u64| {
+            let allocator = allocator_from_bytes(bytes, ByteOrder::BigEndian);
+            let mut bytes_reader = BytesReader::new(ByteOrder::BigEndian, &allocator);
+            TextData::read(&mut bytes_reader, len)
+        };
+
+        assert_eq!(read(&bytes, bytes.len() as u64), Ok(text.clone()));
+
+        let allocator = allocator_from_bytes(&bytes, ByteOrder::BigEndian);
+        let mut bytes_reader = BytesReader::new(ByteOrder::BigEndian, &allocator);
+        assert_eq!(TextData::read_from_buffers(&mut bytes_reader), text);
+        assert_eq!(
+            read(&bytes, bytes.len() as u64 - 1),
+            Err(VmError::from(ReadError::TruncatedCommand))
+        );
+
+        // Text length prefix is beyond the payload.
+        let mut bytes = bytes;
+        bytes[8 + 4 + 64] = 0xFF;
+        assert_eq!(
+            read(&bytes, bytes.len() as u64),
+            Err(VmError::from(ReadError::TruncatedCommand))
+        );
+    }
+}
@@ -0,0 +1,406 @@
+//! Input sessions.
+//!
+//! While recording the VM saves every input command the client pushes
+//! to the client module ([`RECORDED_COMMANDS`]) together with the frame time,
+//! the session can be saved to a file, e.g. attached to a bug report.
+//! In the replay mode the VM ignores the client input and feeds the session
+//! back through [`crate::state::VMState::process_commands`] frame by frame
+//! with the recorded frame times, so the modules receive the exact same
+//! [`crate::module::ClientEvent`] sequence.
+//!
+//! # File format
+//!
+//! All numbers are little-endian:
+//!
+//! ```text
+//! magic        [u8; 8] = "TPVMSESS"
+//! version      u32     = 1
+//! byte_order   u8      - byte order of the payloads, 0 - little-endian, 1 - big-endian
+//! frames_count u64
+//! frames       [time u64 (nanoseconds), count u64, commands; frames_count]
+//! commands     [id u64, payload_len u64, payload [u8; payload_len]; count]
+//! ```
+
+use std::{fs, path::Path, time::Duration};
+
+use vm_buffers::ByteOrder;
+
+use crate::{
+    commands::{self, Source},
+    commands_bus::CommandsBus,
+    commands_reader::CommandsReader,
+    error::{Result, VmError},
+    module,
+};
+
+/// Magic bytes at the beginning of the session file.
+pub const MAGIC: [u8; 8] = *b"TPVMSESS";
+
+/// Current version of the session file format.
+pub const VERSION: u32 = 1;
+
+/// Commands of the client module that are recorded.
+pub const RECORDED_COMMANDS: [u64; 11] = [
+    commands::COMMAND_TOUCH_START,
+    commands::COMMAND_TOUCH_END,
+    commands::COMMAND_TOUCH_MOVE,
+    commands::COMMAND_SCROLL,
+    commands::COMMAND_PINCH,
+    commands::COMMAND_ROTATE,
+    commands::UPDATE_VIEWPORT,
+    commands::COMMAND_KEY_DOWN,
+    commands::COMMAND_KEY_UP,
+    commands::COMMAND_TEXT_INPUT,
+    commands::COMMAND_IME_COMPOSITION,
+];
+
+/// Recorded input command.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionCommand {
+    /// Command id.
+    pub id: u64,
+
+    /// Encoded command payload.
+    pub payload: Vec<u8>,
+}
+
+/// Input commands of the frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionFrame {
+    /// Frame time, see [`crate::clock`].
+    pub time: Duration,
+
+    /// Commands received during the frame.
+    pub commands: Vec<SessionCommand>,
+}
+
+/// Recorded input session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    /// Byte order of the recorded payloads.
+    pub byte_order: ByteOrder,
+
+    /// Recorded frames.
+    pub frames: Vec<SessionFrame>,
+}
+
+impl Session {
+    /// Create an empty session.
+    pub fn new(byte_order: ByteOrder) -> Self {
+        Session {
+            byte_order,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Save the session to the file at the `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+
+        fs::write(path, self.encode())
+            .map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))
+    }
+
+    /// Load the session from the file at the `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data =
+            fs::read(path).map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))?;
+
+        Session::decode(&data).map_err(|err| {
+            match err {
+                VmError::Asset(reason) => VmError::Asset(format!("{}: {}", path.display(), reason)),
+                err => err,
+            }
+        })
+    }
+
+    /// Encode the session.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        data.extend_from_slice(&MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.push(match self.byte_order {
+            ByteOrder::LittleEndian => 0,
+            ByteOrder::BigEndian => 1,
+        });
+
+        data.extend_from_slice(&(self.frames.len() as u64).to_le_bytes());
+
+        for frame in self.frames.iter() {
+            data.extend_from_slice(&(frame.time.as_nanos() as u64).to_le_bytes());
+            data.extend_from_slice(&(frame.commands.len() as u64).to_le_bytes());
+
+            for command in frame.commands.iter() {
+                data.extend_from_slice(&command.id.to_le_bytes());
+                data.extend_from_slice(&(command.payload.len() as u64).to_le_bytes());
+                data.extend_from_slice(&command.payload);
+            }
+        }
+
+        data
+    }
+
+    /// Decode the session.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(&MAGIC) {
+            return Err(VmError::Asset(String::from("invalid session file magic")));
+        }
+
+        let mut reader = BinaryReader {
+            data: &data[MAGIC.len()..],
+        };
+
+        let version = reader.read_u32()?;
+
+        if version != VERSION {
+            return Err(VmError::Asset(format!(
+                "unsupported session file version {}, expected {}",
+                version, VERSION
+            )));
+        }
+
+        let byte_order = match reader.read_bytes(1)?[0] {
+            0 => ByteOrder::LittleEndian,
+            1 => ByteOrder::BigEndian,
+            _ => return Err(VmError::Asset(String::from("invalid session byte order"))),
+        };
+
+        let frames_count = reader.read_u64()?;
+        let mut frames = Vec::new();
+
+        for _ in 0..frames_count {
+            let time = Duration::from_nanos(reader.read_u64()?);
+            let count = reader.read_u64()?;
+            let mut commands = Vec::new();
+
+            for _ in 0..count {
+                let id = reader.read_u64()?;
+                let payload_len = reader.read_u64()?;
+                let payload = reader.read_bytes(payload_len)?.to_vec();
+
+                commands.push(SessionCommand { id, payload });
+            }
+
+            frames.push(SessionFrame { time, commands });
+        }
+
+        Ok(Session { byte_order, frames })
+    }
+}
+
+/// Read the [`RECORDED_COMMANDS`] from the client module commands.
+pub fn read_commands(commands_reader: &mut CommandsReader) -> Result<Vec<SessionCommand>> {
+    let mut commands = Vec::new();
+
+    while let Some(command) = commands_reader.next()? {
+        if RECORDED_COMMANDS.contains(&command.id) {
+            let payload = (0..command.len)
+                .map(|_| command.bytes_reader.read_byte())
+                .collect();
+
+            commands.push(SessionCommand {
+                id: command.id,
+                payload,
+            });
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Send the commands of the `frame` to the client module with `commands_bus`.
+pub fn write_commands(commands_bus: &CommandsBus, frame: &SessionFrame) -> Result<()> {
+    for command in frame.commands.iter() {
+        commands_bus.push_sized_command(
+            module::CLIENT_ID,
+            command.id,
+            Source::Processor,
+            command.payload.len() as u64,
+            |bytes_writer| {
+                for byte in command.payload.iter() {
+                    bytes_writer.write_byte(*byte);
+                }
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Reads little-endian numbers from the session file.
+struct BinaryReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BinaryReader<'a> {
+    fn read_bytes(&mut self, len: u64) -> Result<&'a [u8]> {
+        if len > self.data.len() as u64 {
+            return Err(VmError::Asset(String::from("truncated session file")));
+        }
+
+        let (bytes, data) = self.data.split_at(len as usize);
+        self.data = data;
+        Ok(bytes)
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.read_bytes(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.read_bytes(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Session that is fed back to the VM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    /// Replayed session.
+    pub session: Session,
+
+    /// Index of the next frame.
+    pub next_frame: usize,
+}
+
+impl Replay {
+    /// Replay the `session` from the first frame.
+    pub fn new(session: Session) -> Self {
+        Replay {
+            session,
+            next_frame: 0,
+        }
+    }
+
+    /// Take the next frame, `None` when the session is over.
+    pub fn take_frame(&mut self) -> Option<&SessionFrame> {
+        let frame = self.session.frames.get(self.next_frame)?;
+        self.next_frame += 1;
+        Some(frame)
+    }
+
+    /// Whether all frames have been replayed.
+    pub fn is_finished(&self) -> bool {
+        self.next_frame >= self.session.frames.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use vm_buffers::ByteOrder;
+
+    use super::{Session, SessionCommand, SessionFrame};
+    use crate::{
+        commands,
+        error::VmError,
+        module::{self, ClientEvent, MouseButton, Pointer},
+        test_vm::TestVm,
+    };
+
+    /// Events and delta times received by the modules during the frames.
+    fn run_frames(vm: &mut TestVm, live_events: bool) -> Vec<String> {
+        let deltas = [16, 16, 33, 8, 16];
+        let mut frames = Vec::new();
+
+        for (index, delta) in deltas.iter().enumerate() {
+            if live_events {
+                vm.send_event(ClientEvent::MouseMove {
+                    x: index as f32,
+                    y: 2.0 * index as f32,
+                    pointer: Pointer::touch(index as u64),
+                })
+                .unwrap();
+            }
+
+            if live_events && index == 2 {
+                vm.send_event(ClientEvent::MouseDown {
+                    button: MouseButton::Right,
+                    x: 5.0,
+                    y: 6.0,
+                    pointer: Pointer::MOUSE,
+                })
+                .unwrap();
+                vm.send_event(ClientEvent::WindowResize { w: 320.0, h: 240.0 })
+                    .unwrap();
+            }
+
+            let frame = vm.advance(Duration::from_millis(*delta)).unwrap();
+            let delta_time = vm.module_state(module::CLIENT_ID).unwrap().delta_time;
+            frames.push(format!("{:?} {}", frame.events, delta_time));
+        }
+
+        frames
+    }
+
+    #[test]
+    fn encode_decode() {
+        let session = Session {
+            byte_order: ByteOrder::BigEndian,
+            frames: vec![
+                SessionFrame {
+                    time: Duration::from_nanos(0),
+                    commands: Vec::new(),
+                },
+                SessionFrame {
+                    time: Duration::from_nanos(16_666_667),
+                    commands: vec![SessionCommand {
+                        id: commands::COMMAND_TOUCH_MOVE,
+                        payload: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                    }],
+                },
+            ],
+        };
+
+        let data = session.encode();
+        assert_eq!(Session::decode(&data), Ok(session));
+
+        assert_eq!(
+            Session::decode(&data[..data.len() - 1]),
+            Err(VmError::Asset(String::from("truncated session file")))
+        );
+        assert_eq!(
+            Session::decode(b"TPVMMACR"),
+            Err(VmError::Asset(String::from("invalid session file magic")))
+        );
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut vm = TestVm::new();
+        vm.vm_mut().state_mut().start_recording().unwrap();
+        let recorded = run_frames(&mut vm, true);
+        let session = vm.vm_mut().state_mut().stop_recording().unwrap();
+
+        assert_eq!(session.frames.len(), 5);
+        assert_eq!(session.frames[2].commands.len(), 3);
+
+        let path =
+            std::env::temp_dir().join(format!("tech_paws_vm_session_{}.tps", std::process::id()));
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, session);
+
+        let mut replay_vm = TestVm::new();
+        replay_vm.frames(3).unwrap();
+        replay_vm.vm_mut().state_mut().start_replay(loaded).unwrap();
+
+        let replayed = run_frames(&mut replay_vm, false);
+        assert_eq!(replayed, recorded);
+        assert!(replay_vm.vm().state().is_replaying());
+
+        replay_vm
+            .send_event(ClientEvent::WindowResize { w: 1.0, h: 1.0 })
+            .unwrap();
+        let frame = replay_vm.frame().unwrap();
+        assert!(!replay_vm.vm().state().is_replaying());
+        assert_eq!(frame.events.len(), 1);
+    }
+}
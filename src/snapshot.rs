@@ -0,0 +1,412 @@
+//! Golden-image snapshot testing.
+//!
+//! [`Snapshot`] runs a module in a fresh [`Vm`] for a number of frames with
+//! scripted client events, rasterizes the client GAPI commands of the last frame
+//! with the [`Rasterizer`] and compares the frame with the golden PNG image
+//! `<golden_dir>/<name>.png`.
+//!
+//! On mismatch `<name>.actual.png` and `<name>.diff.png` are written next to
+//! the golden image, mismatched pixels are red in the diff image.
+//! Set `UPDATE_GOLDEN=1` environment variable to write the golden images
+//! instead of comparing them.
+//!
+//! The module is available with the `snapshot` feature, enable it for the `vm`
+//! dev-dependency of the tested crate. The golden images of this crate are
+//! checked with `cargo test --features snapshot`.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use vm::{
+//!     module::{ClientEvent, Pointer},
+//!     snapshot::Snapshot,
+//! };
+//! # use vm::module::{Module, ModuleState, StepState};
+//! # struct MyModule;
+//! # impl Module for MyModule {
+//! #     fn id(&self) -> &'static str { "my_module_id" }
+//! #     fn init(&mut self, _: &mut ModuleState) {}
+//! #     fn shutdown(&mut self, _: &mut ModuleState) {}
+//! #     fn step(&mut self, _: &mut ModuleState) -> StepState { StepState::None }
+//! #     fn render(&mut self, _: &mut ModuleState) {}
+//! # }
+//!
+//! let mut snapshot = Snapshot::new("my_module", 64, 64);
+//! snapshot.frames = 2;
+//! snapshot.tolerance = 2;
+//! snapshot.inputs.push((
+//!     1,
+//!     ClientEvent::MouseMove {
+//!         x: 10.0,
+//!         y: 10.0,
+//!         pointer: Pointer::MOUSE,
+//!     },
+//! ));
+//!
+//! snapshot.assert_matches(Box::new(MyModule));
+//! ```
+
+use std::{env, path::PathBuf};
+
+use vm_math::Vec4f;
+
+use crate::{
+    clock::Clock,
+    commands::Source,
+    error::{Result, VmError},
+    module::{self, ClientEvent, Module},
+    png,
+    rasterizer::{Image, Rasterizer},
+    state,
+    test_vm::FRAME_DURATION,
+    Vm,
+};
+
+/// Environment variable to write the golden images instead of comparing them.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// Snapshot test of a module.
+pub struct Snapshot {
+    /// Name of the golden image without the extension.
+    pub name: String,
+
+    /// Framebuffer width in pixels, the module receives it with the window resize event.
+    pub width: u32,
+
+    /// Framebuffer height in pixels, the module receives it with the window resize event.
+    pub height: u32,
+
+    /// Number of frames to run, the last frame is compared.
+    pub frames: usize,
+
+    /// Client events sent before the frame with the index.
+    pub inputs: Vec<(usize, ClientEvent)>,
+
+    /// Max difference of the pixel channels that is considered equal.
+    pub tolerance: u8,
+
+    /// Number of the mismatched pixels that is allowed.
+    pub max_mismatched_pixels: usize,
+
+    /// Directory of the golden images.
+    pub golden_dir: PathBuf,
+
+    /// Color the framebuffer is cleared with before rendering.
+    pub clear_color: Vec4f,
+}
+
+/// Result of the images comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    /// Number of the mismatched pixels.
+    pub mismatched_pixels: usize,
+
+    /// Diff image, mismatched pixels are red and the rest are dimmed.
+    pub diff: Image,
+}
+
+impl Snapshot {
+    /// Create a new snapshot test that renders 1 frame and compares it exactly
+    /// with the golden image `tests/golden/<name>.png`.
+    pub fn new(name: &str, width: u32, height: u32) -> Self {
+        Snapshot {
+            name: name.to_string(),
+            width,
+            height,
+            frames: 1,
+            inputs: Vec::new(),
+            tolerance: 0,
+            max_mismatched_pixels: 0,
+            golden_dir: PathBuf::from("tests/golden"),
+            clear_color: Vec4f::new(0.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    /// Path of the golden image.
+    pub fn golden_path(&self) -> PathBuf {
+        self.golden_dir.join(format!("{}.png", self.name))
+    }
+
+    /// Run the `module` and render the last frame, frames are [`FRAME_DURATION`] apart.
+    pub fn render(&self, module: Box<dyn Module>) -> Result<Image> {
+        let mut vm = Vm::new();
+        vm.set_clock(Clock::fixed(FRAME_DURATION));
+        vm.register_module(module);
+
+        let commands_bus = vm.commands_bus();
+        let mut rasterizer = Rasterizer::new(self.width, self.height);
+        rasterizer.clear(self.clear_color);
+
+        state::write_client_event(
+            &commands_bus,
+            &ClientEvent::WindowResize {
+                w: self.width as f32,
+                h: self.height as f32,
+            },
+        )?;
+
+        for frame in 0..self.frames {
+            for (_, event) in self.inputs.iter().filter(|(at, _)| *at == frame) {
+                state::write_client_event(&commands_bus, event)?;
+            }
+
+            vm.state_mut().process_commands(Source::Processor)?;
+            vm.state_mut().process_commands(Source::GAPI)?;
+
+            if frame + 1 == self.frames {
+                let client_commands = vm
+                    .state()
+                    .router
+                    .commands(module::CLIENT_ID, Source::GAPI)?;
+                rasterizer.render_buffer(&client_commands)?;
+            }
+
+            vm.state_mut().flush()?;
+        }
+
+        Ok(rasterizer.framebuffer().clone())
+    }
+
+    /// Run the `module` and compare the last frame with the golden image.
+    ///
+    /// Returns error if the golden image can't be loaded or has a different size.
+    pub fn check(&self, module: Box<dyn Module>) -> Result<Comparison> {
+        let actual = self.render(module)?;
+        let golden_path = self.golden_path();
+
+        if env::var(UPDATE_GOLDEN_ENV).as_deref() == Ok("1") {
+            png::save(&golden_path, &actual)?;
+        }
+
+        let golden = png::load(&golden_path)?;
+
+        if golden.width != actual.width || golden.height != actual.height {
+            return Err(VmError::Asset(format!(
+                "{}: expected {}x{} image, got {}x{}",
+                golden_path.display(),
+                golden.width,
+                golden.height,
+                actual.width,
+                actual.height
+            )));
+        }
+
+        let comparison = compare(&actual, &golden, self.tolerance);
+
+        if comparison.mismatched_pixels > self.max_mismatched_pixels {
+            png::save(self.output_path("actual"), &actual)?;
+            png::save(self.output_path("diff"), &comparison.diff)?;
+        }
+
+        Ok(comparison)
+    }
+
+    /// Run the `module` and panic if the last frame doesn't match the golden image.
+    pub fn assert_matches(&self, module: Box<dyn Module>) {
+        let comparison = match self.check(module) {
+            Ok(comparison) => comparison,
+            Err(err) => panic!("snapshot {}: {}", self.name, err),
+        };
+
+        assert!(
+            comparison.mismatched_pixels <= self.max_mismatched_pixels,
+            "snapshot {}: {} pixels mismatched, see {}",
+            self.name,
+            comparison.mismatched_pixels,
+            self.output_path("diff").display()
+        );
+    }
+
+    fn output_path(&self, suffix: &str) -> PathBuf {
+        self.golden_dir
+            .join(format!("{}.{}.png", self.name, suffix))
+    }
+}
+
+/// Compare images of the same size, pixels are equal if the difference
+/// of every channel is not greater than the `tolerance`.
+pub fn compare(actual: &Image, golden: &Image, tolerance: u8) -> Comparison {
+    assert!(actual.width == golden.width && actual.height == golden.height);
+
+    let mut diff = Image::new(actual.width, actual.height);
+    let mut mismatched_pixels = 0;
+
+    for y in 0..actual.height {
+        for x in 0..actual.width {
+            let pixel = actual.pixel(x, y);
+            let expected = golden.pixel(x, y);
+            let matches = pixel
+                .iter()
+                .zip(expected.iter())
+                .all(|(a, b)| (*a as i16 - *b as i16).abs() <= tolerance as i16);
+
+            if matches {
+                let gray = ((pixel[0] as u16 + pixel[1] as u16 + pixel[2] as u16) / 12) as u8;
+                diff.set_pixel(x, y, [gray, gray, gray, 255]);
+            }
+            else {
+                mismatched_pixels += 1;
+                diff.set_pixel(x, y, [255, 0, 0, 255]);
+            }
+        }
+    }
+
+    Comparison {
+        mismatched_pixels,
+        diff,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use vm_math::{Mat4f, Vec4f};
+
+    use super::{compare, Snapshot};
+    use crate::{
+        error::VmError,
+        gapi::{self, GApiContext},
+        module::{self, ClientEvent, Module, ModuleState, MouseButton, Pointer, StepState},
+        png,
+        rasterizer::Image,
+        transforms::{orthographic, scaling, translation},
+    };
+
+    /// Draws a quad under the last mouse down position
+    /// over a background that fills the window.
+    struct QuadsModule {
+        size: (f32, f32),
+        position: (f32, f32),
+    }
+
+    impl QuadsModule {
+        fn new() -> Box<Self> {
+            Box::new(QuadsModule {
+                size: (0.0, 0.0),
+                position: (0.0, 0.0),
+            })
+        }
+    }
+
+    impl Module for QuadsModule {
+        fn id(&self) -> &'static str {
+            "tech.paws.tests.snapshot"
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, state: &mut ModuleState) -> StepState {
+            for event in state.client_info.events.iter() {
+                match event {
+                    ClientEvent::WindowResize { w, h } => self.size = (*w, *h),
+                    ClientEvent::MouseDown { x, y, .. } => self.position = (*x, *y),
+                    _ => (),
+                }
+            }
+
+            StepState::RenderUpdate
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            let context = GApiContext {
+                from: self.id(),
+                address: module::CLIENT_ID,
+                commands_bus: &mut state.commands_bus,
+            };
+            let projection = orthographic(self.size.0, self.size.1);
+
+            gapi::set_color_pipeline(&context, Vec4f::new(0.2, 0.4, 0.6, 1.0)).unwrap();
+            gapi::draw_quads(
+                &context,
+                &[projection * scaling(self.size.0, self.size.1 / 2.0)],
+            )
+            .unwrap();
+            gapi::set_color_pipeline(&context, Vec4f::new(1.0, 0.5, 0.0, 0.5)).unwrap();
+            gapi::draw_centered_quads(
+                &context,
+                &[projection * translation(self.position.0, self.position.1) * scaling(8.0, 8.0)],
+            )
+            .unwrap();
+            gapi::draw_lines(
+                &context,
+                &Mat4f::IDENT,
+                &[
+                    vm_math::Vec2f::new(-1.0, -0.99),
+                    vm_math::Vec2f::new(1.0, -0.99),
+                ],
+            )
+            .unwrap();
+        }
+    }
+
+    fn snapshot() -> Snapshot {
+        let mut snapshot = Snapshot::new("quads", 32, 24);
+        snapshot.frames = 3;
+        snapshot.inputs.push((
+            1,
+            ClientEvent::MouseDown {
+                button: MouseButton::Left,
+                x: 12.0,
+                y: 10.0,
+                pointer: Pointer::MOUSE,
+            },
+        ));
+        snapshot
+    }
+
+    #[test]
+    fn golden_image() {
+        snapshot().assert_matches(QuadsModule::new());
+    }
+
+    #[test]
+    fn mismatched_snapshot() {
+        let golden_dir =
+            std::env::temp_dir().join(format!("tech_paws_vm_snapshot_{}", std::process::id()));
+        fs::create_dir_all(&golden_dir).unwrap();
+
+        let mut snapshot = snapshot();
+        snapshot.golden_dir = golden_dir.clone();
+
+        let mut golden = snapshot.render(QuadsModule::new()).unwrap();
+        golden.set_pixel(0, 0, [0, 0, 0, 255]);
+        golden.set_pixel(1, 0, [0, 0, 0, 255]);
+        png::save(snapshot.golden_path(), &golden).unwrap();
+
+        let comparison = snapshot.check(QuadsModule::new()).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 2);
+        assert_eq!(comparison.diff.pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(
+            png::load(golden_dir.join("quads.diff.png")).unwrap(),
+            comparison.diff
+        );
+        assert!(golden_dir.join("quads.actual.png").exists());
+
+        snapshot.max_mismatched_pixels = 2;
+        snapshot.assert_matches(QuadsModule::new());
+
+        snapshot.golden_dir = golden_dir.join("missing");
+        assert!(matches!(
+            snapshot.check(QuadsModule::new()).err(),
+            Some(VmError::Asset(_))
+        ));
+
+        fs::remove_dir_all(golden_dir).unwrap();
+    }
+
+    #[test]
+    fn compare_with_tolerance() {
+        let mut actual = Image::new(2, 1);
+        let mut golden = Image::new(2, 1);
+        actual.set_pixel(0, 0, [10, 20, 30, 255]);
+        golden.set_pixel(0, 0, [12, 18, 30, 255]);
+        actual.set_pixel(1, 0, [10, 20, 30, 255]);
+        golden.set_pixel(1, 0, [13, 20, 30, 255]);
+
+        assert_eq!(compare(&actual, &golden, 2).mismatched_pixels, 1);
+        assert_eq!(compare(&actual, &golden, 3).mismatched_pixels, 0);
+    }
+}
@@ -1,21 +1,36 @@
 //! Virtual machine state.
 
-use std::{collections::HashMap, time::Instant};
-use vm_buffers::IntoVMBuffers;
+use std::{collections::HashMap, time::Duration};
+use vm_buffers::{BytesReader, BytesWriter, IntoVMBuffers};
 use vm_memory::BufferAccessor;
 
 use crate::{
+    clock::{Clock, FixedTimestep},
     commands::{self, Source},
+    commands_reader::{CommandsReader, ReadError},
     data::MutBytesBuffer,
-    module::{self, ClientEvent, MouseButton, StepState},
+    error::{Result, VmError},
+    gestures::GestureRecognizer,
+    macro_file::{self, MacroFormat},
+    macros::Macros,
+    module::{
+        self, ClientEvent, ClientInfo, CommandStatus, DispatchReport, KeyModifiers, MouseButton,
+        Pointer, StepState,
+    },
+    session::{self, Replay, Session, SessionFrame},
+    transforms,
 };
 use crate::{
-    commands_bus::CommandsBus,
-    module::{Module, ModuleState},
+    commands_bus::{CommandsBus, CommandsRouter},
+    module::{Module, ModuleMemoryUsage, ModuleState},
 };
 
 /// State structure.
 pub struct VMState {
+    /// Routing table shared by all commands buses of this VM.
+    pub router: CommandsRouter,
+
+    /// Commands bus used by the client (host) to send commands to modules.
     pub client_command_bus: CommandsBus,
 
     /// Connected modules.
@@ -23,6 +38,37 @@ pub struct VMState {
 
     /// Module states.
     pub module_states: HashMap<&'static str, ModuleState>,
+
+    /// Macros recorded by the modules.
+    pub macros: Macros,
+
+    /// Clock the modules delta time is computed from.
+    pub clock: Clock,
+
+    /// Fixed-timestep simulation, `None` to step modules once per frame.
+    pub fixed_timestep: Option<FixedTimestep>,
+
+    /// Recognizer of the client gestures, see [`crate::gestures`].
+    pub gestures: GestureRecognizer,
+
+    /// Input session being recorded, see [`session`].
+    pub recording: Option<Session>,
+
+    /// Input session being replayed, see [`session`].
+    pub replay: Option<Replay>,
+
+    /// Clock replaced for the time of the replay.
+    live_clock: Option<Clock>,
+
+    /// Time of the last rendered frame.
+    last_frame_time: Option<Duration>,
+
+    /// Frame time read by the processor, the render uses the same time
+    /// so the clock is read once per frame.
+    processor_frame_time: Option<Duration>,
+
+    /// Client events weren't consumed by any step yet.
+    pending_client_events: bool,
 }
 
 impl Default for VMState {
@@ -34,10 +80,23 @@ impl Default for VMState {
 impl VMState {
     /// Create a new state.
     pub fn new() -> Self {
+        let router = CommandsRouter::new();
+
         VMState {
-            client_command_bus: CommandsBus::new(),
+            client_command_bus: CommandsBus::new(router.clone()),
+            router,
             modules: Vec::new(),
             module_states: HashMap::new(),
+            macros: Macros::new(),
+            clock: Clock::default(),
+            fixed_timestep: None,
+            gestures: GestureRecognizer::default(),
+            recording: None,
+            replay: None,
+            live_clock: None,
+            last_frame_time: None,
+            processor_frame_time: None,
+            pending_client_events: false,
         }
     }
 
@@ -45,7 +104,12 @@ impl VMState {
     pub fn register_module(&mut self, mut module: Box<dyn Module>) {
         assert!(self.modules.len() == self.module_states.len());
 
-        let mut module_state = ModuleState::new(module.id());
+        let mut module_state = ModuleState::new(module.id(), module.options(), self.router.clone());
+        self.router.register(
+            module.id(),
+            module_state.gapi_commands.clone(),
+            module_state.processor_commands.clone(),
+        );
         module.init(&mut module_state);
 
         self.module_states.insert(module.id(), module_state);
@@ -60,9 +124,8 @@ impl VMState {
     pub fn render() {}
 
     /// Get commands from the root module.
-    pub fn get_commands_buffer(&mut self, source: Source) -> MutBytesBuffer {
-        // TODO(sysint64): handle unwraps.
-        let client_module_state = self.module_states.get_mut(&module::CLIENT_ID).unwrap();
+    pub fn get_commands_buffer(&mut self, source: Source) -> Result<MutBytesBuffer> {
+        let client_module_state = self.module_state_mut(module::CLIENT_ID)?;
         let commands_allocator = match source {
             Source::GAPI => client_module_state.gapi_commands.allocator.lock(),
             Source::Processor => client_module_state.processor_commands.allocator.lock(),
@@ -78,85 +141,262 @@ impl VMState {
         // };
         // hexdump::hexdump(bytes);
 
-        MutBytesBuffer {
+        Ok(MutBytesBuffer {
             base: commands_allocator.get_buffer_ptr(),
             size: commands_allocator.get_buffer_size(),
+        })
+    }
+
+    /// Save the macro with the `name` recorded by the modules to the file at the `path`.
+    pub fn save_macro(&self, name: &str, path: &str, format: MacroFormat) -> Result<()> {
+        let recorded = self
+            .macros
+            .get(name)
+            .ok_or_else(|| VmError::Asset(format!("unknown macro: {}", name)))?;
+        let byte_order = self
+            .router
+            .commands(module::CLIENT_ID, Source::GAPI)?
+            .byte_order();
+
+        macro_file::save(path, recorded, byte_order, format)
+    }
+
+    /// Start recording the client input, see [`session`].
+    pub fn start_recording(&mut self) -> Result<()> {
+        let byte_order = self
+            .router
+            .commands(module::CLIENT_ID, Source::Processor)?
+            .byte_order();
+        self.recording = Some(Session::new(byte_order));
+        Ok(())
+    }
+
+    /// Stop recording the client input, returns the recorded session.
+    pub fn stop_recording(&mut self) -> Option<Session> {
+        self.recording.take()
+    }
+
+    /// Replay the `session` instead of the client input, the clock is replaced
+    /// with the recorded frame times until the session is over.
+    pub fn start_replay(&mut self, session: Session) -> Result<()> {
+        let byte_order = self
+            .router
+            .commands(module::CLIENT_ID, Source::Processor)?
+            .byte_order();
+
+        if session.byte_order != byte_order {
+            return Err(VmError::Asset(String::from(
+                "session byte order doesn't match the client byte order",
+            )));
+        }
+
+        if self.live_clock.is_none() {
+            self.live_clock = Some(self.clock.clone());
         }
+
+        self.replay = Some(Replay::new(session));
+        Ok(())
+    }
+
+    /// Whether the session is being replayed.
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Feed the next replayed frame to the client module, replacing the client input.
+    fn replay_frame(&mut self) -> Result<()> {
+        let frame = match self.replay.as_mut().and_then(Replay::take_frame) {
+            Some(frame) => frame.clone(),
+            None => {
+                self.replay = None;
+
+                if let Some(clock) = self.live_clock.take() {
+                    self.clock = clock;
+                }
+
+                return Ok(());
+            }
+        };
+
+        self.module_state_mut(module::CLIENT_ID)?
+            .clear_commands(Source::Processor)?;
+        session::write_commands(&self.client_command_bus, &frame)?;
+        self.clock.set_time(frame.time);
+        Ok(())
+    }
+
+    /// Memory usage of the module at the `address`.
+    pub fn memory_usage(&self, address: &str) -> Result<ModuleMemoryUsage> {
+        self.module_states
+            .get(address)
+            .map(ModuleState::memory_usage)
+            .ok_or_else(|| VmError::UnknownAddress(address.to_string()))
+    }
+
+    /// Get state of the module at the `address`.
+    pub fn module_state_mut(&mut self, address: &str) -> Result<&mut ModuleState> {
+        self.module_states
+            .get_mut(address)
+            .ok_or_else(|| VmError::UnknownAddress(address.to_string()))
     }
 
     /// Process all commands for all modules from source.
     /// This method will clear all commands from source for module.
-    pub fn process_commands(&mut self, source: Source) -> Result<bool, &'static str> {
+    ///
+    /// For [`Source::Processor`] the commands sent to the modules since the last call
+    /// are moved to the modules inboxes and dispatched to [`Module::handle_command`]
+    /// before [`Module::step`], commands sent during the step are delivered at the next call.
+    /// Commands sent to the client module are client events and are handled by the VM.
+    ///
+    /// For [`Source::GAPI`] macros in the client commands are expanded and transforms
+    /// are applied after each module has rendered, so a module can't leave
+    /// its transforms to the modules rendered after it, see [`crate::macros`]
+    /// and [`crate::transforms`].
+    pub fn process_commands(&mut self, source: Source) -> Result<bool> {
         assert!(self.modules.len() == self.module_states.len());
         let mut render_update = false;
 
+        let mut frame_time = None;
+
+        if source == Source::Processor {
+            if self.replay.is_some() {
+                self.replay_frame()?;
+            }
+
+            frame_time = Some(self.clock.frame_time());
+            self.processor_frame_time = frame_time;
+
+            for state in self.module_states.values_mut() {
+                state.deliver_inbox()?;
+            }
+        }
+
+        if let (Some(session), Some(time)) = (self.recording.as_mut(), frame_time) {
+            let client_state = self
+                .module_states
+                .get(module::CLIENT_ID)
+                .ok_or_else(|| VmError::UnknownAddress(module::CLIENT_ID.to_string()))?;
+            let commands = client_state.inbox.read(session::read_commands)?;
+            session.frames.push(SessionFrame { time, commands });
+        }
+
+        let pending_client_events = source == Source::Processor && self.pending_client_events;
+
         let client_info = {
-            let mut client_state = self.module_states.get_mut(module::CLIENT_ID).unwrap();
+            let client_state = self
+                .module_states
+                .get_mut(module::CLIENT_ID)
+                .ok_or_else(|| VmError::UnknownAddress(module::CLIENT_ID.to_string()))?;
 
-            client_state.client_info.events.clear();
+            if !pending_client_events {
+                client_state.client_info.events.clear();
+                client_state.client_info.gestures.clear();
+            }
 
             let mut client_info = client_state.client_info.clone();
 
-            if source == Source::Processor {
-                client_state.get_commands_new(Source::Processor, |commands_reader| {
-                    while let Some(command) = commands_reader.next() {
-                        match command.id {
-                            commands::COMMAND_TOUCH_START => {
-                                client_info.events.push(ClientEvent::MouseDown {
-                                    button: MouseButton::read_from_buffers(command.bytes_reader),
-                                    x: command.bytes_reader.read_u32() as f32,
-                                    y: command.bytes_reader.read_u32() as f32,
-                                });
-                            }
-                            commands::COMMAND_TOUCH_END => {
-                                client_info.events.push(ClientEvent::MouseUp {
-                                    button: MouseButton::read_from_buffers(command.bytes_reader),
-                                    x: command.bytes_reader.read_u32() as f32,
-                                    y: command.bytes_reader.read_u32() as f32,
-                                });
-                            }
-                            commands::COMMAND_TOUCH_MOVE => {
-                                client_info.events.push(ClientEvent::MouseMove {
-                                    x: command.bytes_reader.read_u32() as f32,
-                                    y: command.bytes_reader.read_u32() as f32,
-                                });
-                            }
-                            commands::UPDATE_VIEWPORT => {
-                                client_info.events.push(ClientEvent::WindowResize {
-                                    w: command.bytes_reader.read_u32() as f32,
-                                    h: command.bytes_reader.read_u32() as f32,
-                                });
-                            }
-                            _ => (),
-                        }
-                    }
-                });
+            if let Some(time) = frame_time {
+                let received = client_info.events.len();
+
+                client_state.inbox.read(|commands_reader| {
+                    read_client_events(commands_reader, &mut client_info)
+                })?;
+
+                let gestures = self.gestures.update(time, &client_info.events[received..]);
+                client_info.gestures.extend(gestures);
+                client_info.time = time;
             }
 
             client_state.client_info = client_info.clone();
-            client_info.clone()
+            client_info
+        };
+
+        let (steps, alpha) = match (frame_time, self.fixed_timestep.as_mut()) {
+            (Some(frame_time), Some(fixed_timestep)) => {
+                let steps = fixed_timestep.advance(frame_time);
+                (Some((steps, fixed_timestep.step)), fixed_timestep.alpha())
+            }
+            _ => (None, 1.0),
+        };
+
+        if source == Source::Processor {
+            self.pending_client_events = matches!(steps, Some((0, _)));
+        }
+
+        let delta_time = if source == Source::GAPI {
+            let frame_time = self
+                .processor_frame_time
+                .take()
+                .unwrap_or_else(|| self.clock.frame_time());
+            let delta_time = self
+                .last_frame_time
+                .map_or(0.0, |last| frame_time.saturating_sub(last).as_secs_f32());
+            self.last_frame_time = Some(frame_time);
+            delta_time
+        }
+        else {
+            0.0
         };
 
+        let client_commands = self.router.commands(module::CLIENT_ID, Source::GAPI)?;
+
         for module in self.modules.iter_mut() {
-            let mut state = self.module_states.get_mut(&module.id()).unwrap();
+            let state = self
+                .module_states
+                .get_mut(module.id())
+                .ok_or_else(|| VmError::UnknownAddress(module.id().to_string()))?;
 
             match source {
                 Source::GAPI => {
-                    if !state.last_time_initialized {
-                        state.last_time = Instant::now();
-                        state.last_time_initialized = true;
-                    }
-
-                    state.delta_time = state.last_time.elapsed().as_secs_f32();
-                    module.render(&mut state);
-                    state.last_time = Instant::now();
+                    state.delta_time = delta_time;
+                    module.render(state);
                     state.clear_commands(Source::GAPI)?;
+                    self.macros.expand(
+                        &client_commands,
+                        &self.client_command_bus,
+                        module::CLIENT_ID,
+                    )?;
+                    transforms::apply(
+                        &client_commands,
+                        &self.client_command_bus,
+                        module::CLIENT_ID,
+                    )?;
                 }
                 Source::Processor => {
-                    let step_state = module.step(&mut state);
                     state.client_info = client_info.clone();
-                    state.clear_commands(Source::Processor)?;
-                    render_update = render_update || step_state == StepState::RenderUpdate;
+
+                    if module.id() != module::CLIENT_ID {
+                        let inbox = state.inbox.clone();
+                        let report = inbox.read(|commands_reader| {
+                            dispatch_commands(module.as_mut(), state, commands_reader)
+                        })?;
+                        state.dispatch_report = report;
+                    }
+
+                    state.inbox.clear()?;
+
+                    match steps {
+                        Some((steps, step)) => {
+                            state.delta_time = step.as_secs_f32();
+
+                            for i in 0..steps {
+                                if i == 1 {
+                                    state.client_info.events.clear();
+                                    state.client_info.gestures.clear();
+                                }
+
+                                let step_state = module.step(state);
+                                render_update =
+                                    render_update || step_state == StepState::RenderUpdate;
+                            }
+                        }
+                        None => {
+                            let step_state = module.step(state);
+                            render_update = render_update || step_state == StepState::RenderUpdate;
+                        }
+                    }
+
+                    state.alpha = alpha;
                 }
             }
         }
@@ -164,16 +404,413 @@ impl VMState {
         Ok(render_update)
     }
 
-    pub fn flush(&mut self) -> Result<(), &'static str> {
+    /// Clear commands and text boundaries of all modules.
+    pub fn flush(&mut self) -> Result<()> {
         assert!(self.modules.len() == self.module_states.len());
 
         for module in self.modules.iter() {
-            let state = self.module_states.get_mut(&module.id()).unwrap();
+            let state = self
+                .module_states
+                .get_mut(module.id())
+                .ok_or_else(|| VmError::UnknownAddress(module.id().to_string()))?;
             state.clear_text_boundaries()?;
             state.clear_commands(Source::GAPI)?;
             state.clear_commands(Source::Processor)?;
+            state.inbox.clear()?;
         }
 
         Ok(())
     }
 }
+
+/// Pass commands to the module handlers.
+fn dispatch_commands(
+    module: &mut dyn Module,
+    state: &mut ModuleState,
+    commands_reader: &mut CommandsReader,
+) -> Result<DispatchReport> {
+    let mut report = DispatchReport::default();
+
+    while let Some(command) = commands_reader.next()? {
+        let id = command.id;
+
+        if module.handle_command(state, command) == CommandStatus::Unhandled {
+            log::warn!("{}: unhandled command {:#x}", module.id(), id);
+            report.unhandled.push(id);
+            continue;
+        }
+
+        let payload_read = commands_reader.payload_read();
+        let payload_len = commands_reader.payload_len();
+
+        if payload_read != payload_len {
+            log::warn!(
+                "{}: command {:#x} has read {} bytes of {} bytes payload",
+                module.id(),
+                id,
+                payload_read,
+                payload_len
+            );
+            report.malformed.push(id);
+            commands_reader.skip_payload();
+        }
+    }
+
+    Ok(report)
+}
+
+/// Read client events from the client module commands.
+pub fn read_client_events(
+    commands_reader: &mut CommandsReader,
+    client_info: &mut ClientInfo,
+) -> Result<()> {
+    while let Some(command) = commands_reader.next()? {
+        let payload_len = match command.id {
+            commands::COMMAND_TOUCH_START | commands::COMMAND_TOUCH_END => 9,
+            commands::COMMAND_TOUCH_MOVE | commands::UPDATE_VIEWPORT => 8,
+            commands::COMMAND_SCROLL => 9,
+            commands::COMMAND_PINCH | commands::COMMAND_ROTATE => 12,
+            commands::COMMAND_KEY_DOWN => 6,
+            commands::COMMAND_KEY_UP => 5,
+            commands::COMMAND_TEXT_INPUT => 8,
+            commands::COMMAND_IME_COMPOSITION => 16,
+            _ => 0,
+        };
+
+        if command.len < payload_len {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        let has_pointer = command.len >= payload_len + commands::POINTER_PAYLOAD_SIZE;
+
+        match command.id {
+            commands::COMMAND_TOUCH_START | commands::COMMAND_TOUCH_END => {
+                let button = MouseButton::read_from_buffers(command.bytes_reader);
+                let x = command.bytes_reader.read_u32() as f32;
+                let y = command.bytes_reader.read_u32() as f32;
+                let pointer = read_pointer(command.bytes_reader, has_pointer);
+
+                let event = match command.id {
+                    commands::COMMAND_TOUCH_START => {
+                        ClientEvent::MouseDown {
+                            button,
+                            x,
+                            y,
+                            pointer,
+                        }
+                    }
+                    _ => {
+                        ClientEvent::MouseUp {
+                            button,
+                            x,
+                            y,
+                            pointer,
+                        }
+                    }
+                };
+
+                client_info.track_pointer(&event);
+                client_info.events.push(event);
+            }
+            commands::COMMAND_TOUCH_MOVE => {
+                let event = ClientEvent::MouseMove {
+                    x: command.bytes_reader.read_u32() as f32,
+                    y: command.bytes_reader.read_u32() as f32,
+                    pointer: read_pointer(command.bytes_reader, has_pointer),
+                };
+
+                client_info.track_pointer(&event);
+                client_info.events.push(event);
+            }
+            commands::UPDATE_VIEWPORT => {
+                client_info.events.push(ClientEvent::WindowResize {
+                    w: command.bytes_reader.read_u32() as f32,
+                    h: command.bytes_reader.read_u32() as f32,
+                });
+            }
+            commands::COMMAND_SCROLL => {
+                let event = ClientEvent::Scroll {
+                    dx: command.bytes_reader.read_f32(),
+                    dy: command.bytes_reader.read_f32(),
+                    precise: command.bytes_reader.read_byte() != 0,
+                };
+                coalesce_event(&mut client_info.events, event);
+            }
+            commands::COMMAND_PINCH => {
+                let event = ClientEvent::Pinch {
+                    scale: command.bytes_reader.read_f32(),
+                    x: command.bytes_reader.read_f32(),
+                    y: command.bytes_reader.read_f32(),
+                };
+                coalesce_event(&mut client_info.events, event);
+            }
+            commands::COMMAND_ROTATE => {
+                let event = ClientEvent::Rotate {
+                    angle: command.bytes_reader.read_f32(),
+                    x: command.bytes_reader.read_f32(),
+                    y: command.bytes_reader.read_f32(),
+                };
+                coalesce_event(&mut client_info.events, event);
+            }
+            commands::COMMAND_KEY_DOWN => {
+                client_info.events.push(ClientEvent::KeyDown {
+                    key_code: command.bytes_reader.read_u32(),
+                    modifiers: KeyModifiers::from_bits(command.bytes_reader.read_byte()),
+                    repeat: command.bytes_reader.read_byte() != 0,
+                });
+            }
+            commands::COMMAND_KEY_UP => {
+                client_info.events.push(ClientEvent::KeyUp {
+                    key_code: command.bytes_reader.read_u32(),
+                    modifiers: KeyModifiers::from_bits(command.bytes_reader.read_byte()),
+                });
+            }
+            commands::COMMAND_TEXT_INPUT => {
+                let text = read_text(command.bytes_reader, command.len - payload_len)?;
+                client_info.events.push(ClientEvent::TextInput { text });
+            }
+            commands::COMMAND_IME_COMPOSITION => {
+                let cursor_start = command.bytes_reader.read_u32();
+                let cursor_end = command.bytes_reader.read_u32();
+                let text = read_text(command.bytes_reader, command.len - payload_len)?;
+
+                client_info.events.push(ClientEvent::ImeComposition {
+                    text,
+                    cursor_start,
+                    cursor_end,
+                });
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Merge the scroll or gesture `event` into the previous event if it's of the same kind,
+/// so a burst of events gets to the modules as a single event without reordering
+/// the events of other kinds.
+fn coalesce_event(events: &mut Vec<ClientEvent>, event: ClientEvent) {
+    match (events.last_mut(), &event) {
+        (
+            Some(ClientEvent::Scroll { dx, dy, precise }),
+            ClientEvent::Scroll {
+                dx: next_dx,
+                dy: next_dy,
+                precise: next_precise,
+            },
+        ) if precise == next_precise => {
+            *dx += next_dx;
+            *dy += next_dy;
+        }
+        (
+            Some(ClientEvent::Pinch { scale, x, y }),
+            ClientEvent::Pinch {
+                scale: next_scale,
+                x: next_x,
+                y: next_y,
+            },
+        ) => {
+            *scale *= next_scale;
+            *x = *next_x;
+            *y = *next_y;
+        }
+        (
+            Some(ClientEvent::Rotate { angle, x, y }),
+            ClientEvent::Rotate {
+                angle: next_angle,
+                x: next_x,
+                y: next_y,
+            },
+        ) => {
+            *angle += next_angle;
+            *x = *next_x;
+            *y = *next_y;
+        }
+        _ => events.push(event),
+    }
+}
+
+/// Read the pointer at the end of the touch command, the commands
+/// without the pointer come from the mouse.
+fn read_pointer(bytes_reader: &mut BytesReader, has_pointer: bool) -> Pointer {
+    if has_pointer {
+        Pointer::read_from_buffers(bytes_reader)
+    }
+    else {
+        Pointer::MOUSE
+    }
+}
+
+/// Read the UTF-8 text prefixed with the length,
+/// `remaining` is the payload size after the length.
+fn read_text(bytes_reader: &mut BytesReader, remaining: u64) -> Result<String> {
+    let len = bytes_reader.read_u64();
+
+    if len > remaining {
+        return Err(ReadError::TruncatedCommand.into());
+    }
+
+    let bytes = (0..len).map(|_| bytes_reader.read_byte()).collect();
+    String::from_utf8(bytes).map_err(|_| VmError::InvalidUtf8)
+}
+
+/// Size of the text payload written by [`write_text`].
+fn text_size(text: &str) -> u64 {
+    8 + text.len() as u64
+}
+
+fn write_text(bytes_writer: &mut BytesWriter, text: &str) {
+    bytes_writer.write_u64(text.len() as u64);
+
+    for byte in text.as_bytes() {
+        bytes_writer.write_byte(*byte);
+    }
+}
+
+/// Send the client `event` to the client module with `commands_bus`,
+/// the event is read by [`read_client_events`] at the next step.
+pub fn write_client_event(commands_bus: &CommandsBus, event: &ClientEvent) -> Result<()> {
+    let address = module::CLIENT_ID;
+
+    match event {
+        ClientEvent::MouseDown {
+            button,
+            x,
+            y,
+            pointer,
+        }
+        | ClientEvent::MouseUp {
+            button,
+            x,
+            y,
+            pointer,
+        } => {
+            let id = match event {
+                ClientEvent::MouseDown { .. } => commands::COMMAND_TOUCH_START,
+                _ => commands::COMMAND_TOUCH_END,
+            };
+
+            commands_bus.push_sized_command(
+                address,
+                id,
+                Source::Processor,
+                9 + commands::POINTER_PAYLOAD_SIZE,
+                |bytes_writer| {
+                    button.write_to_buffers(bytes_writer);
+                    bytes_writer.write_u32(*x as u32);
+                    bytes_writer.write_u32(*y as u32);
+                    pointer.write_to_buffers(bytes_writer);
+                },
+            )
+        }
+        ClientEvent::MouseMove { x, y, pointer } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_TOUCH_MOVE,
+                Source::Processor,
+                8 + commands::POINTER_PAYLOAD_SIZE,
+                |bytes_writer| {
+                    bytes_writer.write_u32(*x as u32);
+                    bytes_writer.write_u32(*y as u32);
+                    pointer.write_to_buffers(bytes_writer);
+                },
+            )
+        }
+        ClientEvent::WindowResize { w, h } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::UPDATE_VIEWPORT,
+                Source::Processor,
+                8,
+                |bytes_writer| {
+                    bytes_writer.write_u32(*w as u32);
+                    bytes_writer.write_u32(*h as u32);
+                },
+            )
+        }
+        ClientEvent::Scroll { dx, dy, precise } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_SCROLL,
+                Source::Processor,
+                9,
+                |bytes_writer| {
+                    bytes_writer.write_f32(*dx);
+                    bytes_writer.write_f32(*dy);
+                    bytes_writer.write_byte(*precise as u8);
+                },
+            )
+        }
+        ClientEvent::Pinch { scale: value, x, y } | ClientEvent::Rotate { angle: value, x, y } => {
+            let id = match event {
+                ClientEvent::Pinch { .. } => commands::COMMAND_PINCH,
+                _ => commands::COMMAND_ROTATE,
+            };
+
+            commands_bus.push_sized_command(address, id, Source::Processor, 12, |bytes_writer| {
+                bytes_writer.write_f32(*value);
+                bytes_writer.write_f32(*x);
+                bytes_writer.write_f32(*y);
+            })
+        }
+        ClientEvent::KeyDown {
+            key_code,
+            modifiers,
+            repeat,
+        } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_KEY_DOWN,
+                Source::Processor,
+                6,
+                |bytes_writer| {
+                    bytes_writer.write_u32(*key_code);
+                    bytes_writer.write_byte(modifiers.bits());
+                    bytes_writer.write_byte(*repeat as u8);
+                },
+            )
+        }
+        ClientEvent::KeyUp {
+            key_code,
+            modifiers,
+        } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_KEY_UP,
+                Source::Processor,
+                5,
+                |bytes_writer| {
+                    bytes_writer.write_u32(*key_code);
+                    bytes_writer.write_byte(modifiers.bits());
+                },
+            )
+        }
+        ClientEvent::TextInput { text } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_TEXT_INPUT,
+                Source::Processor,
+                text_size(text),
+                |bytes_writer| write_text(bytes_writer, text),
+            )
+        }
+        ClientEvent::ImeComposition {
+            text,
+            cursor_start,
+            cursor_end,
+        } => {
+            commands_bus.push_sized_command(
+                address,
+                commands::COMMAND_IME_COMPOSITION,
+                Source::Processor,
+                8 + text_size(text),
+                |bytes_writer| {
+                    bytes_writer.write_u32(*cursor_start);
+                    bytes_writer.write_u32(*cursor_end);
+                    write_text(bytes_writer, text);
+                },
+            )
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! SVG export of the render commands.
+//!
+//! Renders a frame as an SVG document for design review and bug reports,
+//! the geometry follows the [`crate::rasterizer`] conventions:
+//!
+//! - Quads are `<rect>`s with the matrix turned into the `transform` attribute,
+//!   textured quads are gray and have `data-texture-id` attribute;
+//! - Lines and paths are `<polyline>`s in the pixel coordinates;
+//! - Texts are `<text>`s with `data-font-id` and `font-size` attributes;
+//! - Colors are taken from the current color pipeline.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use vm::{render_commands::RenderCommand, svg, transforms};
+//! use vm_math::*;
+//!
+//! let document = svg::export(
+//!     &[
+//!         RenderCommand::SetColorPipeline {
+//!             color: Vec4f::new(1.0, 0.0, 0.0, 1.0),
+//!         },
+//!         RenderCommand::DrawQuads {
+//!             mvp_matrices: vec![transforms::orthographic(64.0, 64.0) * transforms::scaling(8.0, 8.0)],
+//!         },
+//!     ],
+//!     64,
+//!     64,
+//! );
+//!
+//! assert!(document.contains(r#"<rect x="0" y="0" width="1" height="1" transform="matrix(8 0 0 8 0 0)" fill="rgb(255,0,0)"/>"#));
+//! ```
+
+use std::{fmt::Write, fs, path::Path};
+
+use vm_math::{Mat4f, Vec2f, Vec4f};
+
+use crate::{
+    error::{Result, VmError},
+    module::ModuleCommands,
+    render_commands::{read_render_commands, RenderCommand},
+};
+
+/// Export the `render_commands` as SVG document of `width` x `height` pixels.
+pub fn export(render_commands: &[RenderCommand], width: u32, height: u32) -> String {
+    let mut exporter = Exporter {
+        document: String::new(),
+        viewport: [0.0, 0.0, width as f32, height as f32],
+        fill: Fill::Color(Vec4f::new(1.0, 1.0, 1.0, 1.0)),
+    };
+
+    exporter.push(&format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width, height
+    ));
+
+    for render_command in render_commands.iter() {
+        exporter.export(render_command);
+    }
+
+    exporter.push("</svg>");
+    exporter.document
+}
+
+/// Export all commands from the `commands` buffer, e.g. the client GAPI commands.
+pub fn export_buffer(commands: &ModuleCommands, width: u32, height: u32) -> Result<String> {
+    let render_commands = commands.read(read_render_commands)?;
+    Ok(export(&render_commands, width, height))
+}
+
+/// Export the `render_commands` to the file at the `path`.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    render_commands: &[RenderCommand],
+    width: u32,
+    height: u32,
+) -> Result<()> {
+    let path = path.as_ref();
+
+    fs::write(path, export(render_commands, width, height))
+        .map_err(|err| VmError::Asset(format!("{}: {}", path.display(), err)))
+}
+
+/// Fill of the current pipeline.
+enum Fill {
+    Color(Vec4f),
+    Texture(u64),
+}
+
+/// How the shape is painted with the current pipeline.
+#[derive(Copy, Clone)]
+enum Paint {
+    Fill,
+    Stroke,
+}
+
+impl Paint {
+    fn attribute(self) -> &'static str {
+        match self {
+            Paint::Fill => "fill",
+            Paint::Stroke => "stroke",
+        }
+    }
+}
+
+struct Exporter {
+    document: String,
+    /// Viewport in pixels - `[x, y, w, h]`.
+    viewport: [f32; 4],
+    fill: Fill,
+}
+
+impl Exporter {
+    fn push(&mut self, element: &str) {
+        self.document.push_str(element);
+        self.document.push('\n');
+    }
+
+    fn export(&mut self, render_command: &RenderCommand) {
+        match render_command {
+            RenderCommand::SetViewport { x, y, w, h } => {
+                self.viewport = [*x as f32, *y as f32, *w as f32, *h as f32];
+            }
+            RenderCommand::SetColorPipeline { color } => self.fill = Fill::Color(*color),
+            RenderCommand::SetTexturePipeline { texture_id } => {
+                self.fill = Fill::Texture(*texture_id)
+            }
+            RenderCommand::DrawQuads { mvp_matrices } => {
+                for mvp_matrix in mvp_matrices.iter() {
+                    self.export_quad(mvp_matrix, 0.0);
+                }
+            }
+            RenderCommand::DrawCenteredQuads { mvp_matrices } => {
+                for mvp_matrix in mvp_matrices.iter() {
+                    self.export_quad(mvp_matrix, -0.5);
+                }
+            }
+            RenderCommand::DrawLines { mvp_matrix, points } => {
+                for line in points.chunks_exact(2) {
+                    self.export_polyline(mvp_matrix, line);
+                }
+            }
+            RenderCommand::DrawPath { mvp_matrix, points } => {
+                self.export_polyline(mvp_matrix, points);
+            }
+            RenderCommand::DrawTexts { texts, .. } => {
+                for text in texts.iter() {
+                    let element = format!(
+                        r#"<text data-font-id="{}" font-size="{}" transform="{}" {}>{}</text>"#,
+                        text.font_id,
+                        text.font_size,
+                        self.transform(&text.mvp_matrix),
+                        self.paint_attributes(Paint::Fill),
+                        escape(&text.text)
+                    );
+                    self.push(&element);
+                }
+            }
+            RenderCommand::Other { .. } => (),
+        }
+    }
+
+    fn export_quad(&mut self, mvp_matrix: &Mat4f, offset: f32) {
+        let element = format!(
+            r#"<rect x="{0}" y="{0}" width="1" height="1" transform="{1}" {2}/>"#,
+            number(offset),
+            self.transform(mvp_matrix),
+            self.paint_attributes(Paint::Fill)
+        );
+        self.push(&element);
+    }
+
+    fn export_polyline(&mut self, mvp_matrix: &Mat4f, points: &[Vec2f]) {
+        if points.len() < 2 {
+            return;
+        }
+
+        let mut coordinates = String::new();
+
+        for point in points.iter() {
+            let (x, y) = self.project(mvp_matrix, point);
+
+            if !coordinates.is_empty() {
+                coordinates.push(' ');
+            }
+
+            let _ = write!(coordinates, "{},{}", number(x), number(y));
+        }
+
+        let stroke = self.paint_attributes(Paint::Stroke);
+        let element = format!(
+            r#"<polyline points="{}" fill="none" {} stroke-width="1"/>"#,
+            coordinates, stroke
+        );
+        self.push(&element);
+    }
+
+    /// Matrix that maps the model space to the pixels - the viewport matrix * `mvp_matrix`.
+    fn screen_matrix(&self, mvp_matrix: &Mat4f) -> Mat4f {
+        let [x, y, w, h] = self.viewport;
+        let viewport = Mat4f {
+            data: [
+                [w / 2.0, 0.0, 0.0, x + w / 2.0],
+                [0.0, -h / 2.0, 0.0, y + h / 2.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        };
+
+        viewport * *mvp_matrix
+    }
+
+    /// SVG transform of the `mvp_matrix`, the perspective is ignored.
+    fn transform(&self, mvp_matrix: &Mat4f) -> String {
+        let m = self.screen_matrix(mvp_matrix).data;
+
+        format!(
+            "matrix({} {} {} {} {} {})",
+            number(m[0][0]),
+            number(m[1][0]),
+            number(m[0][1]),
+            number(m[1][1]),
+            number(m[0][3]),
+            number(m[1][3])
+        )
+    }
+
+    fn project(&self, mvp_matrix: &Mat4f, point: &Vec2f) -> (f32, f32) {
+        let screen = self.screen_matrix(mvp_matrix) * Vec4f::new(point.x, point.y, 0.0, 1.0);
+        (screen.x, screen.y)
+    }
+
+    /// Color attributes of the current pipeline for the `paint`.
+    fn paint_attributes(&self, paint: Paint) -> String {
+        let name = paint.attribute();
+
+        match self.fill {
+            Fill::Color(color) => {
+                let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                let attributes = format!(
+                    r#"{}="rgb({},{},{})""#,
+                    name,
+                    channel(color.x),
+                    channel(color.y),
+                    channel(color.z)
+                );
+
+                if color.w < 1.0 {
+                    format!(
+                        r#"{} {}-opacity="{}""#,
+                        attributes,
+                        name,
+                        number(color.w.max(0.0))
+                    )
+                }
+                else {
+                    attributes
+                }
+            }
+            Fill::Texture(texture_id) => {
+                format!(
+                    r#"{}="rgb(128,128,128)" data-texture-id="{}""#,
+                    name, texture_id
+                )
+            }
+        }
+    }
+}
+
+/// Format the number with up to 3 decimal places.
+fn number(value: f32) -> String {
+    let rounded = (value * 1000.0).round() / 1000.0;
+
+    if rounded == 0.0 {
+        "0".to_string()
+    }
+    else {
+        rounded.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_math::{Vec2f, Vec4f};
+
+    use super::{export, export_buffer};
+    use crate::{
+        gapi::{self, GApiContext, TextData},
+        module::{create_commands_bus, ModuleOptions},
+        render_commands::RenderCommand,
+        transforms::{orthographic, scaling, translation},
+    };
+
+    const ADDRESS: &str = "tech.paws.tests.svg";
+
+    #[test]
+    fn export_render_commands() {
+        let projection = orthographic(100.0, 50.0);
+        let document = export(
+            &[
+                RenderCommand::SetColorPipeline {
+                    color: Vec4f::new(1.0, 0.5, 0.0, 0.25),
+                },
+                RenderCommand::DrawQuads {
+                    mvp_matrices: vec![projection * translation(10.0, 20.0) * scaling(30.0, 5.0)],
+                },
+                RenderCommand::DrawCenteredQuads {
+                    mvp_matrices: vec![projection * scaling(2.0, 2.0)],
+                },
+                RenderCommand::SetTexturePipeline { texture_id: 7 },
+                RenderCommand::DrawQuads {
+                    mvp_matrices: vec![projection],
+                },
+                RenderCommand::SetViewport {
+                    x: 50,
+                    y: 0,
+                    w: 50,
+                    h: 50,
+                },
+                RenderCommand::SetColorPipeline {
+                    color: Vec4f::new(0.0, 0.0, 1.0, 1.0),
+                },
+                RenderCommand::DrawLines {
+                    mvp_matrix: orthographic(50.0, 50.0),
+                    points: vec![
+                        Vec2f::new(0.0, 0.0),
+                        Vec2f::new(10.0, 10.0),
+                        Vec2f::new(20.0, 0.0),
+                        Vec2f::new(20.0, 5.0),
+                    ],
+                },
+                RenderCommand::DrawPath {
+                    mvp_matrix: orthographic(50.0, 50.0),
+                    points: vec![
+                        Vec2f::new(0.0, 0.0),
+                        Vec2f::new(1.0, 2.0),
+                        Vec2f::new(3.0, 4.0),
+                    ],
+                },
+                RenderCommand::DrawTexts {
+                    from: ADDRESS.to_string(),
+                    texts: vec![TextData {
+                        font_id: 2,
+                        font_size: 14,
+                        mvp_matrix: orthographic(50.0, 50.0) * translation(5.0, 6.0),
+                        text: "<Hello & bye>".to_string(),
+                    }],
+                },
+            ],
+            100,
+            50,
+        );
+
+        assert_eq!(
+            document.lines().collect::<Vec<&str>>(),
+            vec![
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="50" viewBox="0 0 100 50">"#,
+                r#"<rect x="0" y="0" width="1" height="1" transform="matrix(30 0 0 5 10 20)" fill="rgb(255,128,0)" fill-opacity="0.25"/>"#,
+                r#"<rect x="-0.5" y="-0.5" width="1" height="1" transform="matrix(2 0 0 2 0 0)" fill="rgb(255,128,0)" fill-opacity="0.25"/>"#,
+                r#"<rect x="0" y="0" width="1" height="1" transform="matrix(1 0 0 1 0 0)" fill="rgb(128,128,128)" data-texture-id="7"/>"#,
+                r#"<polyline points="50,0 60,10" fill="none" stroke="rgb(0,0,255)" stroke-width="1"/>"#,
+                r#"<polyline points="70,0 70,5" fill="none" stroke="rgb(0,0,255)" stroke-width="1"/>"#,
+                r#"<polyline points="50,0 51,2 53,4" fill="none" stroke="rgb(0,0,255)" stroke-width="1"/>"#,
+                r#"<text data-font-id="2" font-size="14" transform="matrix(1 0 0 1 55 6)" fill="rgb(0,0,255)">&lt;Hello &amp; bye&gt;</text>"#,
+                "</svg>",
+            ]
+        );
+    }
+
+    #[test]
+    fn export_gapi_buffer() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::set_color_pipeline(&context, Vec4f::new(0.0, 1.0, 0.0, 1.0)).unwrap();
+        gapi::draw_quads(&context, &[orthographic(10.0, 10.0) * scaling(5.0, 5.0)]).unwrap();
+
+        assert_eq!(
+            export_buffer(&commands, 10, 10).unwrap(),
+            [
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10" viewBox="0 0 10 10">"#,
+                r#"<rect x="0" y="0" width="1" height="1" transform="matrix(5 0 0 5 0 0)" fill="rgb(0,255,0)"/>"#,
+                "</svg>\n",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn stroke_attributes() {
+        let projection = orthographic(10.0, 10.0);
+        let line = RenderCommand::DrawLines {
+            mvp_matrix: projection,
+            points: vec![Vec2f::new(0.0, 0.0), Vec2f::new(5.0, 5.0)],
+        };
+        let document = export(
+            &[
+                RenderCommand::SetColorPipeline {
+                    color: Vec4f::new(1.0, 0.0, 0.0, 0.5),
+                },
+                line.clone(),
+                RenderCommand::SetTexturePipeline { texture_id: 3 },
+                line,
+            ],
+            10,
+            10,
+        );
+
+        assert!(document.contains(
+            r#"<polyline points="0,0 5,5" fill="none" stroke="rgb(255,0,0)" stroke-opacity="0.5" stroke-width="1"/>"#
+        ));
+        assert!(document.contains(
+            r#"<polyline points="0,0 5,5" fill="none" stroke="rgb(128,128,128)" data-texture-id="3" stroke-width="1"/>"#
+        ));
+    }
+}
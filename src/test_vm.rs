@@ -0,0 +1,415 @@
+//! Headless VM to drive modules in unit tests.
+//!
+//! [`TestVm`] runs frames the same way the host does - processes the processor
+//! commands and then the render commands, with a manually advanced clock,
+//! and returns the commands emitted during the frame as decoded values.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use std::time::Duration;
+//!
+//! use vm::{module::ClientEvent, test_vm::TestVm};
+//! # use vm::module::{Module, ModuleState, StepState};
+//! # struct MyModule;
+//! # impl Module for MyModule {
+//! #     fn id(&self) -> &'static str { "my_module_id" }
+//! #     fn init(&mut self, _: &mut ModuleState) {}
+//! #     fn shutdown(&mut self, _: &mut ModuleState) {}
+//! #     fn step(&mut self, _: &mut ModuleState) -> StepState { StepState::None }
+//! #     fn render(&mut self, _: &mut ModuleState) {}
+//! # }
+//!
+//! let mut vm = TestVm::new();
+//! vm.register_module(Box::new(MyModule));
+//! vm.send_event(ClientEvent::WindowResize { w: 640.0, h: 480.0 })?;
+//!
+//! let frame = vm.advance(Duration::from_millis(16))?;
+//! assert_eq!(frame.events.len(), 1);
+//! assert!(frame.render_commands.is_empty());
+//! # Ok::<(), vm::error::VmError>(())
+//! ```
+
+use std::{collections::HashMap, time::Duration};
+
+use crate::{
+    clock::Clock,
+    commands::Source,
+    commands_bus::CommandsBus,
+    error::{Result, VmError},
+    module::{self, ClientEvent, Module, ModuleState},
+    render_commands::{read_render_commands, RenderCommand},
+    state, Vm,
+};
+
+/// Default frame duration - 60 frames per second.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(16_666_667);
+
+/// Result of the frame.
+#[derive(Clone, Debug)]
+pub struct Frame {
+    /// Index of the frame starting from 0.
+    pub index: u64,
+
+    /// Time of the frame since the start of the VM.
+    pub time: Duration,
+
+    /// Whether modules requested rendering.
+    pub render_update: bool,
+
+    /// Client events received by the modules.
+    pub events: Vec<ClientEvent>,
+
+    /// Render commands received by the client.
+    pub render_commands: Vec<RenderCommand>,
+
+    /// Processor commands sent during the frame by the address of the receiver,
+    /// the commands are delivered at the next frame. Commands are decoded with
+    /// [`RenderCommand::read`], so they're [`RenderCommand::Other`] unless they're render commands.
+    pub sent_commands: HashMap<String, Vec<RenderCommand>>,
+}
+
+impl Frame {
+    /// Processor commands sent to the module at the `address` during the frame.
+    pub fn sent_to(&self, address: &str) -> &[RenderCommand] {
+        self.sent_commands
+            .get(address)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+}
+
+/// VM with manually advanced clock.
+pub struct TestVm {
+    vm: Vm,
+    commands_bus: CommandsBus,
+    time: Duration,
+    frames: u64,
+}
+
+impl Default for TestVm {
+    fn default() -> Self {
+        TestVm::new()
+    }
+}
+
+impl TestVm {
+    /// Create a new VM with the client module registered, the clock starts at 0.
+    pub fn new() -> Self {
+        let mut vm = Vm::new();
+        vm.set_clock(Clock::manual());
+        let commands_bus = vm.commands_bus();
+
+        TestVm {
+            vm,
+            commands_bus,
+            time: Duration::from_secs(0),
+            frames: 0,
+        }
+    }
+
+    /// Register a new module.
+    pub fn register_module(&mut self, module: Box<dyn Module>) {
+        self.vm.register_module(module);
+    }
+
+    /// Underlying VM.
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    /// Mutable underlying VM.
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    /// Commands bus of the client to send commands to the modules.
+    pub fn commands_bus(&self) -> &CommandsBus {
+        &self.commands_bus
+    }
+
+    /// State of the module at the `address`.
+    pub fn module_state(&self, address: &str) -> Result<&ModuleState> {
+        self.vm
+            .state()
+            .module_states
+            .get(address)
+            .ok_or_else(|| VmError::UnknownAddress(address.to_string()))
+    }
+
+    /// Current time of the clock.
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    /// Send the client `event`, modules receive it at the next frame.
+    pub fn send_event(&mut self, event: ClientEvent) -> Result<()> {
+        state::write_client_event(&self.commands_bus, &event)
+    }
+
+    /// Advance the clock by [`FRAME_DURATION`] and run a frame.
+    pub fn frame(&mut self) -> Result<Frame> {
+        self.advance(FRAME_DURATION)
+    }
+
+    /// Run `count` frames of [`FRAME_DURATION`], returns the last frame.
+    pub fn frames(&mut self, count: usize) -> Result<Option<Frame>> {
+        let mut last = None;
+
+        for _ in 0..count {
+            last = Some(self.frame()?);
+        }
+
+        Ok(last)
+    }
+
+    /// Advance the clock by `delta` and run a frame, modules delta time is `delta`
+    /// except the first frame that has zero delta time.
+    pub fn advance(&mut self, delta: Duration) -> Result<Frame> {
+        if self.frames > 0 {
+            self.time += delta;
+        }
+
+        let state = self.vm.state_mut();
+        state.clock.set_time(self.time);
+
+        let render_update = state.process_commands(Source::Processor)?;
+        let events = state
+            .module_state_mut(module::CLIENT_ID)?
+            .client_info
+            .events
+            .clone();
+        state.process_commands(Source::GAPI)?;
+
+        let client_commands = state.router.commands(module::CLIENT_ID, Source::GAPI)?;
+        let render_commands = client_commands.read(read_render_commands)?;
+        client_commands.clear()?;
+
+        let mut sent_commands = HashMap::new();
+
+        for (address, module_state) in state.module_states.iter_mut() {
+            let commands = module_state.processor_commands.read(read_render_commands)?;
+
+            if !commands.is_empty() {
+                sent_commands.insert(address.to_string(), commands);
+            }
+
+            module_state.clear_text_boundaries()?;
+        }
+
+        let frame = Frame {
+            index: self.frames,
+            time: self.time,
+            render_update,
+            events,
+            render_commands,
+            sent_commands,
+        };
+        self.frames += 1;
+
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use vm_math::{Mat4f, Vec4f};
+
+    use super::TestVm;
+    use crate::{
+        commands::{self, Source},
+        commands_reader::Command,
+        gapi::{self, GApiContext},
+        module::{
+            self, ClientEvent, CommandStatus, Module, ModuleState, MouseButton, Pointer, StepState,
+        },
+        render_commands::RenderCommand,
+        transforms::translation,
+    };
+
+    const ADDRESS: &str = "tech.paws.tests.test_vm";
+    const RECEIVER_ADDRESS: &str = "tech.paws.tests.test_vm.receiver";
+
+    /// Moves a quad by 10 units per second, sends `UPDATE_TOUCH_STATE`
+    /// to the receiver on mouse down.
+    struct AnimatedModule {
+        x: f32,
+    }
+
+    impl Module for AnimatedModule {
+        fn id(&self) -> &'static str {
+            ADDRESS
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, state: &mut ModuleState) -> StepState {
+            let clicked = state
+                .client_info
+                .events
+                .iter()
+                .any(|event| matches!(event, ClientEvent::MouseDown { .. }));
+
+            if clicked {
+                state
+                    .commands_bus
+                    .push_command(
+                        RECEIVER_ADDRESS,
+                        commands::state::UPDATE_TOUCH_STATE,
+                        Source::Processor,
+                        |bytes_writer| bytes_writer.write_byte(1),
+                    )
+                    .unwrap();
+            }
+
+            StepState::RenderUpdate
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            self.x += state.delta_time * 10.0;
+
+            let context = GApiContext {
+                from: ADDRESS,
+                address: module::CLIENT_ID,
+                commands_bus: &mut state.commands_bus,
+            };
+
+            gapi::set_color_pipeline(&context, Vec4f::new(1.0, 0.0, 0.0, 1.0)).unwrap();
+            gapi::draw_quads(&context, &[translation(self.x, 0.0)]).unwrap();
+        }
+    }
+
+    /// Counts the received `UPDATE_TOUCH_STATE` commands.
+    struct ReceiverModule {
+        received: usize,
+    }
+
+    impl Module for ReceiverModule {
+        fn id(&self) -> &'static str {
+            RECEIVER_ADDRESS
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn handle_command(&mut self, _: &mut ModuleState, command: Command) -> CommandStatus {
+            command.bytes_reader.read_byte();
+            self.received += 1;
+            CommandStatus::Handled
+        }
+
+        fn step(&mut self, _: &mut ModuleState) -> StepState {
+            StepState::None
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            let context = GApiContext {
+                from: RECEIVER_ADDRESS,
+                address: module::CLIENT_ID,
+                commands_bus: &mut state.commands_bus,
+            };
+
+            if self.received > 0 {
+                gapi::draw_quads(&context, &[Mat4f::IDENT]).unwrap();
+            }
+        }
+    }
+
+    fn create_vm() -> TestVm {
+        let mut vm = TestVm::new();
+        vm.register_module(Box::new(AnimatedModule { x: 0.0 }));
+        vm.register_module(Box::new(ReceiverModule { received: 0 }));
+        vm
+    }
+
+    fn quads(x: f32) -> Vec<RenderCommand> {
+        vec![
+            RenderCommand::SetColorPipeline {
+                color: Vec4f::new(1.0, 0.0, 0.0, 1.0),
+            },
+            RenderCommand::DrawQuads {
+                mvp_matrices: vec![translation(x, 0.0)],
+            },
+        ]
+    }
+
+    #[test]
+    fn advance_clock() {
+        let mut vm = create_vm();
+
+        let frame = vm.frame().unwrap();
+        assert_eq!(frame.index, 0);
+        assert_eq!(frame.time, Duration::from_secs(0));
+        assert!(frame.render_update);
+        assert_eq!(frame.render_commands, quads(0.0));
+
+        let frame = vm.advance(Duration::from_millis(500)).unwrap();
+        assert_eq!(frame.index, 1);
+        assert_eq!(frame.time, Duration::from_millis(500));
+        assert_eq!(frame.render_commands, quads(5.0));
+
+        let frame = vm.frames(2).unwrap().unwrap();
+        assert_eq!(frame.index, 3);
+        assert_eq!(
+            vm.time(),
+            Duration::from_millis(500) + 2 * super::FRAME_DURATION
+        );
+        assert_eq!(
+            vm.module_state(ADDRESS).unwrap().delta_time,
+            super::FRAME_DURATION.as_secs_f32()
+        );
+    }
+
+    #[test]
+    fn client_events_and_sent_commands() {
+        let mut vm = create_vm();
+        vm.frame().unwrap();
+
+        vm.send_event(ClientEvent::MouseDown {
+            button: MouseButton::Left,
+            x: 10.0,
+            y: 20.0,
+            pointer: Pointer::MOUSE,
+        })
+        .unwrap();
+
+        let frame = vm.frame().unwrap();
+        assert!(matches!(
+            frame.events.as_slice(),
+            [ClientEvent::MouseDown {
+                button: MouseButton::Left,
+                x,
+                y,
+                ..
+            }] if *x == 10.0 && *y == 20.0
+        ));
+        assert_eq!(
+            frame.sent_to(RECEIVER_ADDRESS),
+            &[RenderCommand::Other {
+                id: commands::state::UPDATE_TOUCH_STATE,
+                payload: vec![1],
+            }]
+        );
+        assert!(frame.sent_to(ADDRESS).is_empty());
+
+        let frame = vm.frame().unwrap();
+        assert!(frame.events.is_empty());
+        assert!(frame.sent_commands.is_empty());
+        assert_eq!(
+            frame.render_commands.last(),
+            Some(&RenderCommand::DrawQuads {
+                mvp_matrices: vec![Mat4f::IDENT],
+            })
+        );
+        assert!(vm
+            .module_state(RECEIVER_ADDRESS)
+            .unwrap()
+            .dispatch_report
+            .is_ok());
+    }
+}
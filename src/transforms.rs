@@ -0,0 +1,504 @@
+//! Transform stack.
+//!
+//! [`commands::transforms`] commands modify the current transform VM-side,
+//! the current transform is applied to the matrices of the subsequent draw
+//! commands in their model space - `mvp_matrix * transform`, so nested UI can be
+//! expressed relative to its parent. Transforms are in the units of the draw
+//! commands geometry, e.g. pixels for the lines drawn with the
+//! [`orthographic`] projection:
+//!
+//! - [`commands::transforms::TRANSLATE`], [`commands::transforms::ROTATE`] and
+//!   [`commands::transforms::SCALE`] multiply the current transform;
+//! - [`commands::transforms::PUSH_TRANSFORM`] saves the current transform and
+//!   [`commands::transforms::POP_TRANSFORM`] restores it.
+//!
+//! Every module starts with an empty stack, transforms are applied to the client
+//! commands after each module has rendered, so transforms left by a module don't
+//! leak into the modules rendered after it. Transforms are applied after macros
+//! have been expanded, the client receives plain render commands.
+
+use vm_buffers::ByteOrder;
+use vm_math::Mat4f;
+
+use crate::{
+    commands,
+    commands_bus::CommandsBus,
+    commands_reader::{CommandsReader, ReadError},
+    error::{Result, VmError},
+    macros::{self, MacroCommand},
+    module::ModuleCommands,
+};
+
+/// Stack of the transforms, the top of the stack is the current transform.
+pub struct TransformStack {
+    stack: Vec<Mat4f>,
+}
+
+impl Default for TransformStack {
+    fn default() -> Self {
+        TransformStack::new()
+    }
+}
+
+impl TransformStack {
+    /// Create a new stack with the identity transform.
+    pub fn new() -> Self {
+        TransformStack {
+            stack: vec![Mat4f::IDENT],
+        }
+    }
+
+    /// Current transform.
+    pub fn current(&self) -> Mat4f {
+        *self.stack.last().unwrap()
+    }
+
+    /// Multiply the current transform by the `transform`.
+    pub fn apply(&mut self, transform: Mat4f) {
+        let current = self.stack.last_mut().unwrap();
+        *current = *current * transform;
+    }
+
+    /// Save the current transform.
+    pub fn push(&mut self) {
+        self.stack.push(self.current());
+    }
+
+    /// Restore the transform saved by [`TransformStack::push`].
+    pub fn pop(&mut self) -> Result<()> {
+        if self.stack.len() == 1 {
+            return Err(VmError::MalformedCommand("transform stack underflow"));
+        }
+
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Number of the saved transforms.
+    pub fn depth(&self) -> usize {
+        self.stack.len() - 1
+    }
+}
+
+/// Translation matrix.
+pub fn translation(x: f32, y: f32) -> Mat4f {
+    Mat4f {
+        data: [
+            [1.0, 0.0, 0.0, x],
+            [0.0, 1.0, 0.0, y],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    }
+}
+
+/// Rotation matrix around the z axis, `angle` is in radians.
+pub fn rotation(angle: f32) -> Mat4f {
+    let (sin, cos) = angle.sin_cos();
+
+    Mat4f {
+        data: [
+            [cos, -sin, 0.0, 0.0],
+            [sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    }
+}
+
+/// Scale matrix.
+pub fn scaling(x: f32, y: f32) -> Mat4f {
+    Mat4f {
+        data: [
+            [x, 0.0, 0.0, 0.0],
+            [0.0, y, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    }
+}
+
+/// Projection that maps the pixel coordinates of the `width` x `height` screen
+/// with the origin in the upper left corner to the clip space.
+pub fn orthographic(width: f32, height: f32) -> Mat4f {
+    Mat4f {
+        data: [
+            [2.0 / width, 0.0, 0.0, -1.0],
+            [0.0, -2.0 / height, 0.0, 1.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    }
+}
+
+/// Apply transform commands from the `commands` buffer of the module
+/// at the `address`. The buffer is rewritten with `commands_bus`,
+/// transform commands are removed and draw commands are transformed.
+///
+/// The buffer is kept untouched if the rewritten commands don't fit in its budget.
+pub fn apply(commands: &ModuleCommands, commands_bus: &CommandsBus, address: &str) -> Result<()> {
+    let has_transforms = commands.read(|commands_reader| {
+        while let Some(command) = commands_reader.next()? {
+            if is_transform_command(command.id) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    })?;
+
+    if !has_transforms {
+        return Ok(());
+    }
+
+    let byte_order = commands.byte_order();
+    let output = commands.read(|commands_reader| process(commands_reader, byte_order))?;
+    macros::rewrite_commands(commands, commands_bus, address, &output)
+}
+
+fn process(
+    commands_reader: &mut CommandsReader,
+    byte_order: ByteOrder,
+) -> Result<Vec<MacroCommand>> {
+    let mut output = Vec::new();
+    let mut stack = TransformStack::new();
+
+    while let Some(command) = commands_reader.next()? {
+        let payload_len = match command.id {
+            commands::transforms::TRANSLATE | commands::transforms::SCALE => 8,
+            commands::transforms::ROTATE => 4,
+            _ => 0,
+        };
+
+        if command.len < payload_len {
+            return Err(ReadError::TruncatedCommand.into());
+        }
+
+        match command.id {
+            commands::transforms::TRANSLATE => {
+                let x = command.bytes_reader.read_f32();
+                let y = command.bytes_reader.read_f32();
+                stack.apply(translation(x, y));
+            }
+            commands::transforms::ROTATE => {
+                stack.apply(rotation(command.bytes_reader.read_f32()));
+            }
+            commands::transforms::SCALE => {
+                let x = command.bytes_reader.read_f32();
+                let y = command.bytes_reader.read_f32();
+                stack.apply(scaling(x, y));
+            }
+            commands::transforms::PUSH_TRANSFORM => stack.push(),
+            commands::transforms::POP_TRANSFORM => {
+                if let Err(err) = stack.pop() {
+                    log::warn!("pop transform is ignored: {}", err);
+                }
+            }
+            id => {
+                let payload = (0..command.len)
+                    .map(|_| command.bytes_reader.read_byte())
+                    .collect::<Vec<u8>>();
+                let transform = stack.current();
+
+                let payload = if is_draw_command(id) && transform != Mat4f::IDENT {
+                    macros::transform_payload(id, &payload, &transform, byte_order)?
+                }
+                else {
+                    payload
+                };
+
+                output.push(MacroCommand {
+                    id,
+                    payload,
+                    argument: None,
+                });
+            }
+        }
+    }
+
+    if stack.depth() != 0 {
+        log::warn!("{} transforms haven't been popped", stack.depth());
+    }
+
+    Ok(output)
+}
+
+fn is_transform_command(id: u64) -> bool {
+    matches!(
+        id,
+        commands::transforms::TRANSLATE
+            | commands::transforms::ROTATE
+            | commands::transforms::SCALE
+            | commands::transforms::PUSH_TRANSFORM
+            | commands::transforms::POP_TRANSFORM
+    )
+}
+
+fn is_draw_command(id: u64) -> bool {
+    matches!(
+        id,
+        commands::gapi::DRAW_QUADS
+            | commands::gapi::DRAW_CENTERED_QUADS
+            | commands::gapi::DRAW_LINES
+            | commands::gapi::DRAW_PATH
+            | commands::gapi::DRAW_TEXTS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use vm_buffers::IntoVMBuffers;
+    use vm_math::{Mat4f, Vec2f, Vec4f};
+
+    use super::{apply, orthographic, rotation, scaling, translation, TransformStack};
+    use crate::{
+        commands::{self, Source},
+        error::{Result, VmError},
+        gapi::{self, GApiContext},
+        module::{
+            self, create_commands_bus, Module, ModuleCommands, ModuleOptions, ModuleState,
+            StepState,
+        },
+        Vm,
+    };
+
+    #[cfg(feature = "rasterizer")]
+    use crate::rasterizer::Rasterizer;
+
+    const ADDRESS: &str = "tech.paws.tests.transforms";
+
+    /// Ids of the commands and the first matrix of the draw commands.
+    fn read_commands(commands: &ModuleCommands) -> Vec<(u64, Option<Mat4f>)> {
+        commands
+            .read(|commands_reader| {
+                let mut result = Vec::new();
+
+                while let Some(command) = commands_reader.next()? {
+                    let matrix = match command.id {
+                        commands::gapi::DRAW_QUADS => {
+                            command.bytes_reader.read_u64();
+                            Some(Mat4f::read_from_buffers(command.bytes_reader))
+                        }
+                        commands::gapi::DRAW_LINES => {
+                            Some(Mat4f::read_from_buffers(command.bytes_reader))
+                        }
+                        _ => None,
+                    };
+
+                    result.push((command.id, matrix));
+                }
+
+                Ok(result)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn transform_stack() {
+        let mut stack = TransformStack::new();
+        assert_eq!(stack.current(), Mat4f::IDENT);
+
+        stack.apply(translation(10.0, 20.0));
+        stack.push();
+        stack.apply(scaling(2.0, 2.0));
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(
+            stack.current() * Vec4f::new(1.0, 1.0, 0.0, 1.0),
+            Vec4f::new(12.0, 22.0, 0.0, 1.0)
+        );
+
+        stack.pop().unwrap();
+        assert_eq!(stack.current(), translation(10.0, 20.0));
+        assert_eq!(
+            stack.pop().err(),
+            Some(VmError::MalformedCommand("transform stack underflow"))
+        );
+    }
+
+    #[test]
+    fn rotation_matrix() {
+        let point = rotation(std::f32::consts::FRAC_PI_2) * Vec4f::new(1.0, 0.0, 0.0, 1.0);
+        assert!(point.x.abs() < 1e-6);
+        assert!((point.y - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn apply_transforms() {
+        let projection = orthographic(64.0, 64.0);
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::translate(&context, 10.0, 20.0).unwrap();
+        gapi::push_transform(&context).unwrap();
+        gapi::scale(&context, 2.0, 3.0).unwrap();
+        gapi::set_color_pipeline(&context, Vec4f::new(1.0, 1.0, 1.0, 1.0)).unwrap();
+        gapi::draw_quads(&context, &[projection]).unwrap();
+        gapi::pop_transform(&context).unwrap();
+        gapi::draw_lines(&context, &projection, &[Vec2f::new(0.0, 0.0)]).unwrap();
+
+        apply(&commands, &commands_bus, ADDRESS).unwrap();
+
+        assert_eq!(
+            read_commands(&commands),
+            vec![
+                (commands::gapi::SET_COLOR_PIPELINE, None),
+                (
+                    commands::gapi::DRAW_QUADS,
+                    Some(projection * translation(10.0, 20.0) * scaling(2.0, 3.0))
+                ),
+                (
+                    commands::gapi::DRAW_LINES,
+                    Some(projection * translation(10.0, 20.0))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rasterizer")]
+    fn translate_pixels() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::set_color_pipeline(&context, Vec4f::new(1.0, 0.0, 0.0, 1.0)).unwrap();
+        gapi::translate(&context, 3.0, 2.0).unwrap();
+        gapi::push_transform(&context).unwrap();
+        gapi::scale(&context, 2.0, 2.0).unwrap();
+        gapi::draw_lines(
+            &context,
+            &orthographic(8.0, 8.0),
+            &[Vec2f::new(0.25, 0.25), Vec2f::new(1.75, 0.25)],
+        )
+        .unwrap();
+        gapi::pop_transform(&context).unwrap();
+        gapi::draw_lines(
+            &context,
+            &orthographic(8.0, 8.0),
+            &[Vec2f::new(0.5, 3.5), Vec2f::new(0.5, 4.5)],
+        )
+        .unwrap();
+
+        apply(&commands, &commands_bus, ADDRESS).unwrap();
+
+        let mut rasterizer = Rasterizer::new(8, 8);
+        rasterizer.render_buffer(&commands).unwrap();
+        let framebuffer = rasterizer.framebuffer();
+        let coverage = (0..8)
+            .map(|y| {
+                (0..8)
+                    .map(|x| {
+                        if framebuffer.pixel(x, y)[3] > 0 {
+                            '#'
+                        }
+                        else {
+                            '.'
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>();
+
+        assert_eq!(
+            coverage,
+            vec![
+                "........", "........", "...####.", "........", "........", "...#....", "...#....",
+                "........",
+            ]
+        );
+    }
+
+    #[test]
+    fn pop_empty_stack() {
+        let (commands, mut commands_bus) = create_commands_bus(ADDRESS, ModuleOptions::default());
+        let context = GApiContext {
+            from: ADDRESS,
+            address: ADDRESS,
+            commands_bus: &mut commands_bus,
+        };
+
+        gapi::pop_transform(&context).unwrap();
+        gapi::translate(&context, 10.0, 20.0).unwrap();
+        gapi::draw_quads(&context, &[Mat4f::IDENT]).unwrap();
+
+        apply(&commands, &commands_bus, ADDRESS).unwrap();
+
+        assert_eq!(
+            read_commands(&commands),
+            vec![(commands::gapi::DRAW_QUADS, Some(translation(10.0, 20.0)))]
+        );
+    }
+
+    /// Renders with the `render` callback to the client.
+    struct RenderModule {
+        id: &'static str,
+        render: fn(&GApiContext) -> Result<()>,
+    }
+
+    impl Module for RenderModule {
+        fn id(&self) -> &'static str {
+            self.id
+        }
+
+        fn init(&mut self, _: &mut ModuleState) {}
+
+        fn shutdown(&mut self, _: &mut ModuleState) {}
+
+        fn step(&mut self, _: &mut ModuleState) -> StepState {
+            StepState::None
+        }
+
+        fn render(&mut self, state: &mut ModuleState) {
+            let context = GApiContext {
+                from: self.id,
+                address: module::CLIENT_ID,
+                commands_bus: &mut state.commands_bus,
+            };
+
+            (self.render)(&context).unwrap();
+        }
+    }
+
+    #[test]
+    fn transforms_are_scoped_per_module() {
+        let mut vm = Vm::new();
+        vm.register_module(Box::new(RenderModule {
+            id: "tech.paws.tests.transforms.unbalanced",
+            render: |context| {
+                gapi::push_transform(context)?;
+                gapi::translate(context, 10.0, 20.0)?;
+                gapi::draw_quads(context, &[Mat4f::IDENT])
+            },
+        }));
+        vm.register_module(Box::new(RenderModule {
+            id: "tech.paws.tests.transforms.plain",
+            render: |context| {
+                gapi::pop_transform(context)?;
+                gapi::draw_quads(context, &[Mat4f::IDENT])
+            },
+        }));
+
+        vm.state_mut().process_commands(Source::GAPI).unwrap();
+
+        let client_commands = &vm
+            .state_mut()
+            .module_state_mut(module::CLIENT_ID)
+            .unwrap()
+            .gapi_commands;
+
+        assert_eq!(
+            read_commands(client_commands),
+            vec![
+                (commands::gapi::DRAW_QUADS, Some(translation(10.0, 20.0))),
+                (commands::gapi::DRAW_QUADS, Some(Mat4f::IDENT)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Commands buffer wire header.
+//!
+//! Host and VM are shipped separately, so every commands buffer starts with
+//! a header that identifies the protocol:
+//!
+//! `magic + byte_order_mark u32 + version u32 + reserved u32`
+//!
+//! The header is followed by `count u64 + address + [id, len, payload]*`,
+//! see [`crate::commands_reader`]. All numbers are written in the byte order
+//! of the buffer, the byte order mark makes it possible to detect it.
+
+use vm_buffers::{ByteOrder, BytesReader, BytesWriter};
+
+use crate::commands_reader::ReadError;
+
+/// Magic bytes at the beginning of every commands buffer.
+pub const MAGIC: [u8; 4] = *b"TPVM";
+
+/// Current version of the commands buffer protocol.
+pub const VERSION: u32 = 1;
+
+/// Byte order mark, reads as `0x0403_0201` if the byte order doesn't match.
+pub const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+
+/// Size of the header in bytes.
+pub const HEADER_SIZE: u64 = 16;
+
+/// Offset of the commands count in the commands buffer.
+pub const COMMANDS_COUNT_OFFSET: u64 = HEADER_SIZE;
+
+/// Write the header of the current protocol version.
+pub fn write_header(bytes_writer: &mut BytesWriter) {
+    for byte in MAGIC.iter() {
+        bytes_writer.write_byte(*byte);
+    }
+
+    bytes_writer.write_u32(BYTE_ORDER_MARK);
+    bytes_writer.write_u32(VERSION);
+    bytes_writer.write_u32(0); // Reserved
+}
+
+/// Read and validate the header, `size` is the size of the buffer data in bytes.
+///
+/// Returns [`ReadError::ByteOrderMismatch`] if the buffer has been written
+/// with a byte order other than the byte order of `bytes_reader`.
+pub fn read_header(bytes_reader: &mut BytesReader, size: u64) -> Result<(), ReadError> {
+    if size.saturating_sub(bytes_reader.current_offset()) < HEADER_SIZE {
+        return Err(ReadError::TruncatedHeader);
+    }
+
+    for byte in MAGIC.iter() {
+        if bytes_reader.read_byte() != *byte {
+            return Err(ReadError::InvalidMagic);
+        }
+    }
+
+    match bytes_reader.read_u32() {
+        BYTE_ORDER_MARK => (),
+        mark if mark == BYTE_ORDER_MARK.swap_bytes() => return Err(ReadError::ByteOrderMismatch),
+        _ => return Err(ReadError::InvalidMagic),
+    }
+
+    let version = bytes_reader.read_u32();
+
+    if version != VERSION {
+        return Err(ReadError::UnsupportedVersion(version));
+    }
+
+    bytes_reader.read_u32(); // Reserved
+
+    Ok(())
+}
+
+/// Detect byte order of the commands buffer `data`,
+/// returns `None` if `data` doesn't start with a valid header.
+pub fn byte_order(data: &[u8]) -> Option<ByteOrder> {
+    if data.len() < HEADER_SIZE as usize || data[..4] != MAGIC {
+        return None;
+    }
+
+    let mut mark = [0; 4];
+    mark.copy_from_slice(&data[4..8]);
+
+    if u32::from_le_bytes(mark) == BYTE_ORDER_MARK {
+        Some(ByteOrder::LittleEndian)
+    }
+    else if u32::from_be_bytes(mark) == BYTE_ORDER_MARK {
+        Some(ByteOrder::BigEndian)
+    }
+    else {
+        None
+    }
+}